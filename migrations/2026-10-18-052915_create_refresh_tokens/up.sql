@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE refresh_tokens (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  -- only the hash of the token is stored
+  token_hash VARCHAR(255) NOT NULL UNIQUE,
+  -- every token rotated from the same login shares the family
+  family_id uuid NOT NULL,
+  wallet_id uuid NOT NULL REFERENCES wallets(id) ON DELETE CASCADE,
+  project_id uuid NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+  expires_at TIMESTAMP NOT NULL,
+  revoked_at TIMESTAMP,
+  replaced_by uuid REFERENCES refresh_tokens(id),
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens(family_id);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- JSON-RPC endpoint used to talk to the chain, e.g. for contract wallet signatures
+ALTER TABLE networks ADD COLUMN rpc_url VARCHAR(255);
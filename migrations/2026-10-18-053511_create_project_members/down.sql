@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE project_members;
+DROP TYPE project_role;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TYPE project_role AS ENUM ('admin', 'user');
+
+CREATE TABLE project_members (
+  project_id uuid NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+  wallet_id uuid NOT NULL REFERENCES wallets(id) ON DELETE CASCADE,
+  role project_role NOT NULL DEFAULT 'user',
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+
+  PRIMARY KEY (project_id, wallet_id)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE tickets;
+DROP TYPE ticket_state;
+DROP TYPE ticket_purpose;
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+CREATE TYPE ticket_purpose AS ENUM ('assign', 'assigned', 'redeem');
+CREATE TYPE ticket_state AS ENUM ('request', 'random_number', 'unpack', 'minted');
+
+CREATE TABLE tickets (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  -- secret shared by the tickets of the same order
+  token VARCHAR(255) NOT NULL,
+  code VARCHAR(255) NOT NULL UNIQUE,
+  email VARCHAR(255) NOT NULL,
+  name VARCHAR(255) NOT NULL DEFAULT '',
+  purpose ticket_purpose NOT NULL DEFAULT 'assign',
+  tier INTEGER NOT NULL,
+  state ticket_state NOT NULL DEFAULT 'request',
+  -- event the ticket was sold for
+  event VARCHAR(255),
+  expires_at TIMESTAMP,
+
+  project_id uuid NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+  wallet_id uuid REFERENCES wallets(id),
+
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX tickets_token_idx ON tickets(token);
+CREATE INDEX tickets_email_idx ON tickets(email);
+
+SELECT diesel_manage_updated_at('tickets');
@@ -0,0 +1,31 @@
+-- Your SQL goes here
+CREATE TABLE benefits (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  name VARCHAR(255) NOT NULL,
+  description TEXT NOT NULL,
+  image VARCHAR(255) NOT NULL,
+  expires_at TIMESTAMP,
+
+  collection_id uuid NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+SELECT diesel_manage_updated_at('benefits');
+
+CREATE TABLE nft_benefits (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  cupom_code VARCHAR(255),
+  available BOOLEAN NOT NULL DEFAULT TRUE,
+  expires_at TIMESTAMP,
+  redeemed_at TIMESTAMP,
+
+  benefit_id uuid NOT NULL REFERENCES benefits(id) ON DELETE CASCADE,
+  nft_id uuid NOT NULL REFERENCES nfts(id) ON DELETE CASCADE,
+  redeemed_by uuid REFERENCES wallets(id),
+
+  UNIQUE (benefit_id, nft_id)
+);
+
+CREATE INDEX nft_benefits_nft_id_idx ON nft_benefits(nft_id);
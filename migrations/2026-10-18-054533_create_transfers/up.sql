@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE transfers (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+
+  nft_id uuid NOT NULL REFERENCES nfts(id) ON DELETE CASCADE,
+  -- null when the nft was minted
+  from_id uuid REFERENCES wallets(id),
+  to_id uuid NOT NULL REFERENCES wallets(id),
+
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX transfers_nft_id_idx ON transfers(nft_id, created_at);
@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX transfers_tx_hash_log_index_idx;
+
+ALTER TABLE transfers
+  DROP COLUMN block_number,
+  DROP COLUMN tx_hash,
+  DROP COLUMN log_index;
+
+DROP TABLE contract_checkpoints;
+
+ALTER TABLE collection_contracts DROP COLUMN deployed_at_block;
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+-- indexing starts at this block
+ALTER TABLE collection_contracts ADD COLUMN deployed_at_block BIGINT;
+
+-- last block whose Transfer logs were indexed, per contract
+CREATE TABLE contract_checkpoints (
+  collection_contract_id uuid PRIMARY KEY REFERENCES collection_contracts(id) ON DELETE CASCADE,
+  block_number BIGINT NOT NULL,
+
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+SELECT diesel_manage_updated_at('contract_checkpoints');
+
+-- position of the log of on-chain transfers
+ALTER TABLE transfers
+  ADD COLUMN block_number BIGINT,
+  ADD COLUMN tx_hash VARCHAR(66),
+  ADD COLUMN log_index BIGINT;
+
+CREATE UNIQUE INDEX transfers_tx_hash_log_index_idx ON transfers(tx_hash, log_index);
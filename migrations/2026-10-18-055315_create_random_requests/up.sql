@@ -0,0 +1,22 @@
+-- Your SQL goes here
+CREATE TABLE random_requests (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  -- id of the request in the randomness source
+  request_id VARCHAR(255) NOT NULL UNIQUE,
+  tier INTEGER NOT NULL,
+  fulfilled BOOLEAN NOT NULL DEFAULT FALSE,
+  fulfilled_at TIMESTAMP,
+  -- kept to audit the draw: the nft is candidates[random_number % candidates]
+  random_number VARCHAR(78),
+  proof TEXT,
+  candidates INTEGER,
+
+  ticket_id uuid NOT NULL UNIQUE REFERENCES tickets(id) ON DELETE CASCADE,
+  wallet_id uuid NOT NULL REFERENCES wallets(id),
+  nft_id uuid UNIQUE REFERENCES nfts(id),
+
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+SELECT diesel_manage_updated_at('random_requests');
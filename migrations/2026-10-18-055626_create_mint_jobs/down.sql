@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE mint_jobs;
+DROP TYPE mint_job_state;
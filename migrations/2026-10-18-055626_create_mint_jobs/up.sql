@@ -0,0 +1,28 @@
+-- Your SQL goes here
+CREATE TYPE mint_job_state AS ENUM ('pending', 'submitted', 'confirmed', 'failed');
+
+CREATE TABLE mint_jobs (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  state mint_job_state NOT NULL DEFAULT 'pending',
+  nonce BIGINT,
+  -- every transaction sent for the job, the last one has the highest gas price
+  tx_hashes TEXT[] NOT NULL DEFAULT '{}',
+  gas_price VARCHAR(78),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  error TEXT,
+  submitted_at TIMESTAMP,
+  confirmed_at TIMESTAMP,
+
+  nft_id uuid NOT NULL REFERENCES nfts(id) ON DELETE CASCADE,
+  -- receiver of the nft
+  wallet_id uuid NOT NULL REFERENCES wallets(id),
+
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- an nft can only be minted once, failed jobs can be retried
+CREATE UNIQUE INDEX mint_jobs_nft_id_idx ON mint_jobs(nft_id) WHERE state <> 'failed';
+CREATE INDEX mint_jobs_state_idx ON mint_jobs(state);
+
+SELECT diesel_manage_updated_at('mint_jobs');
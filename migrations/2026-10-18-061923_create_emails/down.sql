@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE emails;
+DROP TABLE email_templates;
+DROP TYPE email_state;
@@ -0,0 +1,45 @@
+-- Your SQL goes here
+CREATE TYPE email_state AS ENUM ('pending', 'sent', 'failed');
+
+CREATE TABLE email_templates (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  -- identifier the admins refer to the template with
+  template_id VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  -- `{{variable}}` placeholders are replaced with the ticket data
+  body TEXT NOT NULL,
+
+  project_id uuid NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+
+  UNIQUE (project_id, template_id)
+);
+
+SELECT diesel_manage_updated_at('email_templates');
+
+-- outbox of the emails, rows are kept after being sent
+CREATE TABLE emails (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  state email_state NOT NULL DEFAULT 'pending',
+  recipient VARCHAR NOT NULL,
+  subject VARCHAR NOT NULL,
+  body TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  error TEXT,
+  -- pending emails are not sent before, grows with the attempts
+  next_attempt_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  sent_at TIMESTAMP,
+
+  project_id uuid NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+  ticket_id uuid REFERENCES tickets(id) ON DELETE SET NULL,
+
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX emails_pending_idx ON emails(next_attempt_at) WHERE state = 'pending';
+CREATE INDEX emails_ticket_id_idx ON emails(ticket_id);
+
+SELECT diesel_manage_updated_at('emails');
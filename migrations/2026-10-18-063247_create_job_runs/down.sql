@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE tickets DROP COLUMN emailed_at;
+DROP TABLE job_runs;
+DROP TYPE job_run_state;
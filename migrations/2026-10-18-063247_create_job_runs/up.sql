@@ -0,0 +1,22 @@
+-- Your SQL goes here
+CREATE TYPE job_run_state AS ENUM ('running', 'succeeded', 'failed');
+
+-- history of the scheduled jobs, one row per job and schedule tick
+CREATE TABLE job_runs (
+  id uuid PRIMARY KEY DEFAULT uuid_generate_v4(),
+  job VARCHAR NOT NULL,
+  state job_run_state NOT NULL DEFAULT 'running',
+  -- tick of the schedule the run belongs to, claimed by a single instance
+  scheduled_at TIMESTAMP NOT NULL,
+  output TEXT,
+  error TEXT,
+  started_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  finished_at TIMESTAMP,
+
+  UNIQUE (job, scheduled_at)
+);
+
+CREATE INDEX job_runs_started_at_idx ON job_runs(job, started_at DESC);
+
+-- tickets whose codes were not emailed to the attendee yet have it null
+ALTER TABLE tickets ADD COLUMN emailed_at TIMESTAMP;
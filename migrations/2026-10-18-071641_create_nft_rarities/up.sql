@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- rarity of the NFTs, computed from the traits of their collection and
+-- refreshed with them
+CREATE TABLE nft_rarities (
+  nft_id uuid PRIMARY KEY REFERENCES nfts(id) ON DELETE CASCADE,
+  collection_id uuid NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+  -- sum of the inverse frequencies of the traits
+  rarity_score DOUBLE PRECISION NOT NULL,
+  rarity_rank INTEGER NOT NULL,
+  -- product of the frequencies of the traits, lower is rarer
+  statistical_rarity DOUBLE PRECISION NOT NULL,
+  statistical_rank INTEGER NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX nft_rarities_collection_id_idx ON nft_rarities(collection_id, rarity_rank);
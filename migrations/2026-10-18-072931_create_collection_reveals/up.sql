@@ -0,0 +1,23 @@
+-- Your SQL goes here
+CREATE TYPE reveal_mode AS ENUM ('at_time', 'on_mint');
+
+CREATE TABLE collection_reveals (
+  collection_id uuid PRIMARY KEY REFERENCES collections(id) ON DELETE CASCADE,
+  mode reveal_mode NOT NULL,
+  reveal_at TIMESTAMP,
+  -- metadata of the NFTs until they are revealed
+  placeholder_name VARCHAR NOT NULL,
+  placeholder_description TEXT NOT NULL DEFAULT '',
+  placeholder_image VARCHAR NOT NULL,
+  placeholder_animation_url VARCHAR NOT NULL DEFAULT '',
+  -- hash of the final metadata, nothing is revealed before it's committed
+  provenance_hash VARCHAR(66),
+  committed_at TIMESTAMP,
+
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+
+  CHECK (mode <> 'at_time' OR reveal_at IS NOT NULL)
+);
+
+SELECT diesel_manage_updated_at('collection_reveals');
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- the backfilled links are the ones the services would have created, they stay
+SELECT 1;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- NFTs created after a benefit of their collection get it too, the services
+-- link the new ones
+INSERT INTO nft_benefits (benefit_id, nft_id, expires_at)
+SELECT benefits.id, nfts.id, benefits.expires_at
+FROM benefits
+INNER JOIN nfts ON nfts.collection_id = benefits.collection_id
+ON CONFLICT (benefit_id, nft_id) DO NOTHING;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- trait whose value is the tier of the NFTs drawn for tickets
+ALTER TABLE collections ADD COLUMN tier_trait VARCHAR(255) NOT NULL DEFAULT 'Tier';
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX nfts_network_contract_id_nft_id_idx;
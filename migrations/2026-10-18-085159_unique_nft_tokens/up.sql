@@ -0,0 +1,21 @@
+-- Your SQL goes here
+-- a contract mints each token id once. tokens imported twice keep the row
+-- drawn, minted or owned, or else the lowest id. a token drawn by two
+-- requests stays referenced and fails the migration until it's resolved
+CREATE TEMPORARY TABLE duplicate_nfts AS
+SELECT id, first_value(id) OVER (
+  PARTITION BY network_contract_id, nft_id
+  ORDER BY
+    NOT EXISTS (SELECT 1 FROM random_requests WHERE random_requests.nft_id = nfts.id),
+    minted_at IS NULL,
+    owner_id IS NULL,
+    id
+) AS kept_id
+FROM nfts;
+
+DELETE FROM duplicate_nfts WHERE id = kept_id;
+DELETE FROM attributes_on_nfts WHERE nft_id IN (SELECT id FROM duplicate_nfts);
+DELETE FROM nfts WHERE id IN (SELECT id FROM duplicate_nfts);
+DROP TABLE duplicate_nfts;
+
+CREATE UNIQUE INDEX nfts_network_contract_id_nft_id_idx ON nfts (network_contract_id, nft_id);
@@ -1,24 +1,174 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fs::File, io, path::PathBuf, process, sync::Arc};
 
+use clap::{Parser, Subcommand, ValueEnum};
+use diesel::{r2d2::ConnectionManager, PgConnection};
 use dotenvy::dotenv;
 use ethos_rs::{
     database::create_connection_pool,
+    events::EventBus,
     services::{
-        nft::{
-            AttributesOnNft, CollectionContract, Network, NewNft, Nft, NftAttribute, NftService,
-        },
+        export::ExportService,
+        import::{CollectionImport, ImportService, ImportSource},
+        nft::{CollectionContract, Network, NewNft, Nft, NftAttribute, NftService},
         project::ProjectService,
     },
 };
+use r2d2::Pool;
+use uuid::Uuid;
+
+/// Seeds the Taipe Experience collection, or imports or exports a collection
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Imports a collection from a directory of ERC-721 metadata JSON files
+    /// named by token id, or from a CSV manifest
+    Import {
+        /// directory of metadata files or CSV file
+        path: PathBuf,
+        /// name of the project, created when missing
+        #[arg(long)]
+        project: String,
+        /// name of the collection, created when missing
+        #[arg(long)]
+        collection: String,
+        #[arg(long)]
+        description: Option<String>,
+        #[arg(long)]
+        chain_id: i32,
+        /// address of the contract minting the NFTs
+        #[arg(long)]
+        contract: String,
+        #[arg(long)]
+        fee_recipient: String,
+        /// NFTs inserted per transaction
+        #[arg(long, default_value_t = 500)]
+        batch_size: usize,
+    },
+    /// Exports the NFTs of a collection with their attributes, owners and
+    /// mint status
+    Export {
+        collection_id: Uuid,
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+        /// file, or directory of the metadata, stdout when missing
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    /// a JSON object per line
+    Ndjson,
+    /// a metadata JSON file per token, named by token id in a folder per
+    /// chain and contract
+    Metadata,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
+    let cli = Cli::parse();
 
     let database_connection = create_connection_pool();
+    match cli.command {
+        Some(Command::Import {
+            path,
+            project,
+            collection,
+            description,
+            chain_id,
+            contract,
+            fee_recipient,
+            batch_size,
+        }) => {
+            let import = CollectionImport {
+                collection_name: collection,
+                description,
+                chain_id,
+                contract_address: contract,
+                fee_recipient,
+                batch_size,
+            };
+            import_collection(database_connection, &project, &import, &path)
+        }
+        Some(Command::Export {
+            collection_id,
+            format,
+            output,
+        }) => export_collection(database_connection, collection_id, format, output),
+        None => seed(database_connection),
+    }
+}
 
+fn import_collection(
+    database_connection: Pool<ConnectionManager<PgConnection>>,
+    project_name: &str,
+    import: &CollectionImport,
+    path: &std::path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
     let project_service = ProjectService::new(database_connection.clone());
-    let nft_service = NftService::new(database_connection.clone());
+    let project = match project_service.get_project_by_name(project_name).ok() {
+        Some(project) => project,
+        None => project_service.create_project(project_name, None)?,
+    };
+
+    let import_service = ImportService::new(database_connection);
+    let report = import_service.import(&project, import, &ImportSource::from_path(path))?;
+    for warning in &report.warnings {
+        println!("warning: {}", warning);
+    }
+    for error in &report.errors {
+        println!("{}", error);
+    }
+    println!(
+        "Imported {} nfts into collection {}, {} rows failed",
+        report.imported,
+        report.collection_id,
+        report.errors.len()
+    );
+    if !report.errors.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn export_collection(
+    database_connection: Pool<ConnectionManager<PgConnection>>,
+    collection_id: Uuid,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let export_service = ExportService::new(database_connection);
+    if let ExportFormat::Metadata = format {
+        let dir = output.ok_or("--output is required by the metadata format")?;
+        let count = export_service.export_metadata_dir(collection_id, &dir)?;
+        eprintln!("Exported {} nfts", count);
+        return Ok(());
+    }
+    let writer: Box<dyn io::Write> = match output {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    let count = match format {
+        ExportFormat::Csv => export_service.export_csv(collection_id, writer)?,
+        _ => export_service.export_ndjson(collection_id, writer)?,
+    };
+    eprintln!("Exported {} nfts", count);
+    Ok(())
+}
+
+fn seed(
+    database_connection: Pool<ConnectionManager<PgConnection>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let project_service = ProjectService::new(database_connection.clone());
+    let nft_service = NftService::new(database_connection.clone(), Arc::new(EventBus::default()));
     // create project
     let project_name = "Taipe Experience";
     let project = match project_service.get_project_by_name(project_name).ok() {
@@ -34,7 +184,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .ok()
     {
         Some(collection) => collection,
-        None => nft_service.create_collection(&project, collection_name.clone(), None)?,
+        None => nft_service.create_collection(&project, collection_name, None)?,
     };
 
     println!("{:?}", collection);
@@ -94,7 +244,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // create nfts
     let nfts = match nft_service.get_nfts_by_collection_id(collection.id) {
-        Ok(nfts) if nfts.len() > 0 => nfts,
+        Ok(nfts) if !nfts.is_empty() => nfts,
         _ => {
             let image_url = "https://assets.taipe.xyz/nft";
             let animation_url = "https://singulari3-turborepo-backoffice.vercel.app/collection";
@@ -104,7 +254,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .map(|i| {
                     let extension = if i <= 25 { "gif " } else { "png" };
                     let contract_network = if i <= 25 {
-                        collection_contracts.get(0).unwrap()
+                        collection_contracts.first().unwrap()
                     } else {
                         collection_contracts.get(1).unwrap()
                     };
@@ -114,12 +264,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         image: format!("{}/{}.{}", image_url, i, extension),
                         description: description.to_string(),
                         external_url: format!("https://taipe.xyz/nft/{}", i),
-                        animation_url: format!(
-                            "{}/{}/nft/{}",
-                            animation_url,
-                            collection.id.to_string(),
-                            i
-                        ),
+                        animation_url: format!("{}/{}/nft/{}", animation_url, collection.id, i),
                         collection_id: collection.id,
                         network_contract_id: contract_network.id,
                     };
@@ -129,12 +274,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             let nfts = nfts_data
                 .chunks(1000)
-                .into_iter()
-                .map(|chunk| {
-                    let nfts = nft_service.create_nfts(chunk.to_vec()).unwrap();
-                    nfts
-                })
-                .flatten()
+                .flat_map(|chunk| nft_service.create_nfts(chunk.to_vec()).unwrap())
                 .collect::<Vec<Nft>>();
             nfts
         }
@@ -142,15 +282,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // check if already exists nfts before creating
 
     println!("{} nfts", nfts.len());
-    let first_nft = nfts.get(0).unwrap();
+    let first_nft = nfts.first().unwrap();
     println!("First: {:?}", first_nft);
 
-    println!("Last: {:?}", nfts.get(nfts.len() - 1).unwrap());
+    println!("Last: {:?}", nfts.last().unwrap());
 
     // create tier attributes
 
     let attributes = match nft_service.get_attributes_from_type("Tier") {
-        Ok(attr) if attr.len() > 0 => attr,
+        Ok(attr) if !attr.is_empty() => attr,
         Ok(_) | Err(_) => {
             let attributes: Vec<NftAttribute> = (1..=3)
                 .map(|i| {
@@ -173,28 +313,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // create nft attribute relations
 
     let attributes_on_nft = match nft_service.get_nft_attributes(first_nft.id) {
-        Ok(attr) if attr.len() > 0 => attr,
+        Ok(attr) if !attr.is_empty() => attr,
         Ok(_) | Err(_) => {
-            let attributes_on_nft: Vec<AttributesOnNft> = nfts
+            let relations = nfts
                 .iter()
                 .map(|nft| {
                     let attribute_id = if nft.nft_id <= 25 {
-                        attributes.get(0).unwrap().id
+                        attributes.first().unwrap().id
                     } else if nft.nft_id <= 500 {
                         attributes.get(1).unwrap().id
                     } else {
                         attributes.get(2).unwrap().id
                     };
-                    let attr = nft_service.create_attribute_nft_relation(nft.id, attribute_id);
-
-                    attr.unwrap()
+                    (nft.id, attribute_id)
                 })
-                .collect();
-            attributes_on_nft
+                .collect::<Vec<_>>();
+            nft_service.create_attribute_nft_relations(&relations)?
         }
     };
 
-    println!("{:?}", attributes_on_nft.get(0).unwrap());
+    println!("{:?}", attributes_on_nft.first().unwrap());
 
     Ok(())
 }
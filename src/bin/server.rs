@@ -1,11 +1,15 @@
-use std::{net::SocketAddr, str::FromStr, sync::Arc};
-use tower_http::cors::{Any, CorsLayer};
+use std::{env, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use tower_http::cors::{AllowOrigin, CorsLayer};
 
 use async_graphql::*;
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
 use axum::{
     extract::State,
-    http::HeaderMap,
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE, HOST, ORIGIN},
+        request::Parts,
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    },
     response::{self, IntoResponse},
     routing::{get, post},
     Extension, Router,
@@ -13,19 +17,44 @@ use axum::{
 use dotenvy::dotenv;
 use uuid::Uuid;
 
+use ethos_rs::chain::providers::ChainProviders;
+use ethos_rs::chain::signers::ChainSigners;
 use ethos_rs::database::{create_connection_pool, ConnectionPool};
-use ethos_rs::resolvers::{MutationRoot, QueryRoot};
+use ethos_rs::events::EventBus;
+use ethos_rs::jobs::{
+    scheduler::Scheduler,
+    tickets::{HttpTicketSource, ImportTicketsJob, TicketEmailsJob},
+};
+use ethos_rs::loaders::Loaders;
+use ethos_rs::mail::{
+    queue::{EmailQueue, LocalQueue},
+    transport::{EmailTransport, FileTransport, SmtpTransport},
+};
+use ethos_rs::metadata;
+use ethos_rs::resolvers::{MutationRoot, QueryRoot, SubscriptionRoot};
 use ethos_rs::services::{
-    auth::AuthService, nft::NftService, profile::ProfileService, project::ProjectService,
+    auth::AuthService,
+    benefit::BenefitService,
+    email::EmailService,
+    indexer::IndexerService,
+    mint::MintService,
+    nft::NftService,
+    profile::ProfileService,
+    project::{normalize_origin, Project, ProjectService},
+    random::{LocalRandomness, NoRandomness, RandomRequestService, RandomnessSource},
+    ticket::TicketService,
     wallet::WalletService,
 };
 
 struct AppState {
     auth_service: Arc<AuthService>,
     project_service: Arc<ProjectService>,
+    loaders: Loaders,
+    /// `API_URL`, the origin the api is exposed at when it's behind a proxy
+    api_origin: Option<String>,
 }
 
-pub type MySchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+pub type MySchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
 
 #[tokio::main]
 async fn main() {
@@ -46,41 +75,161 @@ async fn main() {
     // services setup
     println!("Setting up services...");
     let project_service = Arc::new(ProjectService::new(database_connection.clone()));
+    project_service
+        .load_origins()
+        .expect("Failed to load project origins");
     let profile_service = ProfileService::new(database_connection.clone());
     let pool = ConnectionPool::new(database_connection.clone());
-    let wallet_service = Arc::new(WalletService::new(pool));
-    let auth_service = Arc::new(AuthService::new(wallet_service.clone()));
-    let collection_service = NftService::new(database_connection.clone());
-    let nft_service = NftService::new(database_connection.clone());
+    let events = Arc::new(EventBus::default());
+    let nft_service = Arc::new(NftService::new(database_connection.clone(), events.clone()));
+    let networks = nft_service.get_networks().expect("Failed to load networks");
+    let chain_providers = Arc::new(ChainProviders::from_networks(&networks));
+    let wallet_service = Arc::new(WalletService::new(pool, chain_providers.clone()));
+    let ticket_service = Arc::new(TicketService::new(database_connection.clone()));
+    let benefit_service = Arc::new(BenefitService::new(database_connection.clone()));
+    let email_service = Arc::new(EmailService::new(
+        database_connection.clone(),
+        email_transport(),
+        email_queue(),
+    ));
+    let random_request_service = Arc::new(RandomRequestService::new(
+        database_connection.clone(),
+        randomness_source(),
+        events.clone(),
+    ));
+    let auth_service = Arc::new(AuthService::new(
+        database_connection.clone(),
+        wallet_service.clone(),
+        nft_service.clone(),
+    ));
+
+    let email_worker = email_service.clone();
+    let email_interval = env::var("EMAIL_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(30);
+    tokio::spawn(async move { email_worker.run(Duration::from_secs(email_interval)).await });
+
+    let mut scheduler = Scheduler::new(database_connection.clone());
+    let ticket_emails_schedule =
+        env::var("TICKET_EMAILS_SCHEDULE").unwrap_or_else(|_| "0 * * * * *".to_string());
+    scheduler
+        .add(
+            &ticket_emails_schedule,
+            Arc::new(TicketEmailsJob::new(email_service.clone())),
+        )
+        .expect("TICKET_EMAILS_SCHEDULE not valid");
+    match (
+        env::var("TICKETS_SOURCE_URL"),
+        env::var("TICKETS_PROJECT_ID"),
+    ) {
+        (Ok(url), Ok(project_id)) => {
+            let project_id = Uuid::from_str(&project_id).expect("TICKETS_PROJECT_ID not valid");
+            let schedule =
+                env::var("TICKETS_IMPORT_SCHEDULE").unwrap_or_else(|_| "0 */5 * * * *".to_string());
+            let job = ImportTicketsJob::new(
+                ticket_service.clone(),
+                Arc::new(HttpTicketSource::new(&url)),
+                project_id,
+            );
+            scheduler
+                .add(&schedule, Arc::new(job))
+                .expect("TICKETS_IMPORT_SCHEDULE not valid");
+        }
+        _ => println!("TICKETS_SOURCE_URL or TICKETS_PROJECT_ID not set, tickets are not imported"),
+    }
+    scheduler.start();
+
+    let chain_signers = ChainSigners::from_networks(&networks);
+    if chain_signers.is_empty() {
+        println!("No MINTER_PRIVATE_KEY_<chain id> set, minting is disabled");
+    } else {
+        let mint_service = MintService::new(
+            database_connection.clone(),
+            chain_providers.clone(),
+            chain_signers,
+            events.clone(),
+        );
+        let mint_interval = env::var("MINT_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|interval| interval.parse().ok())
+            .unwrap_or(15);
+        tokio::spawn(async move { mint_service.run(Duration::from_secs(mint_interval)).await });
+    }
+
+    let indexer_service = IndexerService::new(
+        database_connection.clone(),
+        chain_providers,
+        wallet_service.clone(),
+        events.clone(),
+    );
+    let indexer_interval = env::var("INDEXER_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(15);
+    tokio::spawn(async move {
+        indexer_service
+            .run(Duration::from_secs(indexer_interval))
+            .await
+    });
 
     // schema setup
     println!("Setting up schema...");
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+    let loaders = Loaders::new(nft_service.clone(), wallet_service.clone());
+    let schema = loaders
+        .add_to_schema(Schema::build(QueryRoot, MutationRoot, SubscriptionRoot))
         .data(project_service.clone())
         .data(wallet_service)
         .data(auth_service.clone())
         .data(profile_service)
-        .data(nft_service)
-        .data(collection_service)
+        .data(nft_service.clone())
+        .data(ticket_service)
+        .data(benefit_service)
+        .data(email_service)
+        .data(random_request_service)
+        .data(events)
         .finish();
 
     // cors setup
-    // let allowed_origins = [
-    //     "http://localhost:3001".parse().unwrap(),
-    //     "http://localhost:3000".parse().unwrap(),
-    // ];
     println!("Setting up cors...");
-    let cors = CorsLayer::permissive().expose_headers(Any);
+    let origins_service = project_service.clone();
+    let origins_interval = env::var("CORS_REFRESH_SECONDS")
+        .ok()
+        .and_then(|interval| interval.parse().ok())
+        .unwrap_or(60);
+    tokio::spawn(async move {
+        origins_service
+            .refresh_origins(Duration::from_secs(origins_interval))
+            .await
+    });
+    let cors_project_service = project_service.clone();
+    let cors = CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin, parts| {
+            is_allowed_origin(&cors_project_service, origin, parts)
+        }))
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([
+            AUTHORIZATION,
+            CONTENT_TYPE,
+            HeaderName::from_static("project"),
+        ]);
 
+    let api_origin = env::var("API_URL")
+        .ok()
+        .map(|url| normalize_origin(&url).expect("API_URL not valid"));
     let state = Arc::new(AppState {
         auth_service,
         project_service,
+        loaders,
+        api_origin,
     });
 
     // axum setup
     let app = Router::new()
         .route("/", get(graphiql))
         .route("/graphql", post(graphql_handler))
+        .route_service("/ws", GraphQLSubscription::new(schema.clone()))
+        .merge(metadata::router(nft_service))
         .layer(Extension(schema))
         .layer(cors)
         .with_state(state);
@@ -94,6 +243,45 @@ async fn main() {
         .unwrap();
 }
 
+/// SMTP when `SMTP_URL` is set, otherwise the emails are written to `EMAIL_DIR`
+fn email_transport() -> Arc<dyn EmailTransport> {
+    let Ok(url) = env::var("SMTP_URL") else {
+        let dir = env::var("EMAIL_DIR").unwrap_or_else(|_| "emails".to_string());
+        println!("SMTP_URL not set, writing emails to {}", dir);
+        return Arc::new(FileTransport::new(dir));
+    };
+    let from = env::var("EMAIL_FROM").expect("EMAIL_FROM must be set");
+    Arc::new(SmtpTransport::new(&url, &from).expect("SMTP_URL not valid"))
+}
+
+/// Source of the random numbers drawing the NFTs of redeemed tickets, from
+/// `RANDOMNESS_SOURCE`. The local source is predictable by whoever holds its
+/// seed, so it's only available in development.
+fn randomness_source() -> Arc<dyn RandomnessSource> {
+    match env::var("RANDOMNESS_SOURCE").as_deref() {
+        Ok("local") => Arc::new(
+            LocalRandomness::from_env().expect("RANDOMNESS_SOURCE=local is only for development"),
+        ),
+        Ok(source) => panic!("RANDOMNESS_SOURCE `{}` not supported", source),
+        Err(_) => {
+            println!("RANDOMNESS_SOURCE not set, tickets can't be redeemed");
+            Arc::new(NoRandomness)
+        }
+    }
+}
+
+/// Redis when built with the `redis` feature and `REDIS_URL` is set, so a
+/// notification wakes one waiting worker of any instance, otherwise one worker
+/// of this process. The emails stay in Postgres either way.
+fn email_queue() -> Arc<dyn EmailQueue> {
+    #[cfg(feature = "redis")]
+    if let Ok(url) = env::var("REDIS_URL") {
+        let queue = ethos_rs::mail::queue::RedisQueue::new(&url).expect("REDIS_URL not valid");
+        return Arc::new(queue);
+    }
+    Arc::new(LocalQueue::default())
+}
+
 fn get_token_from_headers(headers: &HeaderMap) -> Option<String> {
     headers.get("Authorization").and_then(|value| {
         value.to_str().ok().map(|s| {
@@ -112,37 +300,135 @@ fn get_project_from_headers(headers: &HeaderMap) -> Option<String> {
         .and_then(|value| value.to_str().ok().map(|v| v.to_string()))
 }
 
+/// Only origins configured in the requested project are allowed, read from
+/// the origins kept in memory. Preflight requests don't carry the `project`
+/// header value, so any project's origin is accepted for them and
+/// `graphql_handler` checks the origin again once the project is known.
+fn is_allowed_origin(
+    project_service: &ProjectService,
+    origin: &HeaderValue,
+    parts: &Parts,
+) -> bool {
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    match get_project_from_headers(&parts.headers) {
+        Some(project_id) => Uuid::from_str(&project_id)
+            .map(|id| project_service.allows_origin(Some(id), origin))
+            .unwrap_or(false),
+        None => project_service.allows_origin(None, origin),
+    }
+}
+
+/// Browser requests must come from an origin of the project they act on, or
+/// from the api itself, e.g. graphiql. The api is served over plain http on
+/// its `Host` unless `api_origin` says where it's exposed.
+fn is_allowed_request(
+    project_service: &ProjectService,
+    project: Option<&Project>,
+    api_origin: Option<&str>,
+    headers: &HeaderMap,
+) -> bool {
+    let Some(origin) = headers.get(ORIGIN) else {
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+    let api_origin = match api_origin {
+        Some(api_origin) => Some(api_origin.to_string()),
+        None => headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(|host| normalize_origin(&format!("http://{}", host)).ok()),
+    };
+    if api_origin.is_some() && normalize_origin(origin).ok() == api_origin {
+        return true;
+    }
+    match project {
+        Some(project) => project.allows_origin(origin),
+        None => project_service.allows_origin(None, origin),
+    }
+}
+
 async fn graphql_handler(
     schema: Extension<MySchema>,
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     req: GraphQLRequest,
-) -> GraphQLResponse {
+) -> Result<GraphQLResponse, StatusCode> {
     let auth = &state.auth_service;
     let project_service = &state.project_service;
 
     let mut req = req.into_inner();
+    let project = get_project_from_headers(&headers)
+        .and_then(|project_id| Uuid::from_str(&project_id).ok())
+        .and_then(|id| project_service.get_project(id).ok());
+    if !is_allowed_request(
+        project_service,
+        project.as_ref(),
+        state.api_origin.as_deref(),
+        &headers,
+    ) {
+        return Err(StatusCode::FORBIDDEN);
+    }
     if let Some(token) = get_token_from_headers(&headers) {
-        if let Ok(wallet) = auth.validate(token.as_str()).await {
+        if let Ok(wallet) = auth.validate(token.as_str(), project.as_ref()).await {
             req = req.data(wallet);
         }
     }
-    if let Some(project_id) = get_project_from_headers(&headers) {
-        if let Ok(id) = Uuid::from_str(&project_id) {
-            if let Ok(project) = project_service.get_project(id) {
-                req = req.data(project);
-            }
-        }
+    if let Some(project) = project {
+        req = req.data(project);
     }
-    schema.execute(req).await.into()
+    req = state.loaders.add_to_request(req);
+    Ok(schema.execute(req).await.into())
 }
 
 async fn graphiql() -> impl IntoResponse {
-    response::Html(http::GraphiQLSource::build().endpoint("/graphql").finish())
+    response::Html(
+        http::GraphiQLSource::build()
+            .endpoint("/graphql")
+            .subscription_endpoint("/ws")
+            .finish(),
+    )
 }
 
 #[cfg(test)]
 mod tests {
+    use axum::http::{
+        header::{HOST, ORIGIN},
+        HeaderMap, HeaderValue,
+    };
+    use dotenvy::dotenv;
+    use ethos_rs::{database::create_connection_pool, services::project::ProjectService};
+
+    use super::is_allowed_request;
+
     #[test]
     fn schema() {}
+
+    #[test]
+    fn test_same_origin() {
+        dotenv().ok();
+        let project_service = ProjectService::new(create_connection_pool());
+        let request = |origin: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(HOST, HeaderValue::from_static("api.ethos.xyz"));
+            headers.insert(ORIGIN, HeaderValue::from_str(origin).unwrap());
+            headers
+        };
+        let allowed = |origin: &str, api_origin: Option<&str>| {
+            is_allowed_request(&project_service, None, api_origin, &request(origin))
+        };
+
+        // the api serves plain http on its host
+        assert!(allowed("http://api.ethos.xyz", None));
+        assert!(!allowed("https://api.ethos.xyz", None));
+        assert!(!allowed("http://api.ethos.xyz:8080", None));
+        // behind a proxy it's exposed where `API_URL` says
+        let api_origin = Some("https://api.ethos.xyz");
+        assert!(allowed("https://api.ethos.xyz", api_origin));
+        assert!(allowed("https://api.ethos.xyz:443", api_origin));
+        assert!(!allowed("http://api.ethos.xyz", api_origin));
+    }
 }
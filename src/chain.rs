@@ -0,0 +1,5 @@
+pub mod eip1271;
+pub mod erc721;
+pub mod nonce;
+pub mod providers;
+pub mod signers;
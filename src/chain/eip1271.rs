@@ -0,0 +1,108 @@
+use async_graphql::async_trait;
+use ethers::{
+    abi::{self, Token},
+    providers::{JsonRpcClient, Middleware, RpcError},
+    types::{Address, Bytes, TransactionRequest, H256},
+};
+
+use crate::errors::EthosError;
+
+use super::providers::ChainProviders;
+
+/// `bytes4(keccak256("isValidSignature(bytes32,bytes)"))`, also the value
+/// returned by the contract when the signature is valid
+pub const MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// Validates signatures of smart-contract wallets (EIP-1271)
+#[async_trait::async_trait]
+pub trait ContractSignatureValidator: Send + Sync {
+    async fn is_valid_signature(
+        &self,
+        chain_id: u64,
+        wallet: Address,
+        hash: H256,
+        signature: Bytes,
+    ) -> Result<bool, EthosError>;
+}
+
+pub fn encode_is_valid_signature(hash: H256, signature: Bytes) -> Bytes {
+    let args = abi::encode(&[
+        Token::FixedBytes(hash.as_bytes().to_vec()),
+        Token::Bytes(signature.to_vec()),
+    ]);
+    [MAGIC_VALUE.as_slice(), &args].concat().into()
+}
+
+#[async_trait::async_trait]
+impl<P: JsonRpcClient> ContractSignatureValidator for ChainProviders<P> {
+    async fn is_valid_signature(
+        &self,
+        chain_id: u64,
+        wallet: Address,
+        hash: H256,
+        signature: Bytes,
+    ) -> Result<bool, EthosError> {
+        let provider = self.get(chain_id)?;
+        let call = TransactionRequest::new()
+            .to(wallet)
+            .data(encode_is_valid_signature(hash, signature));
+
+        match provider.call(&call.into(), None).await {
+            // accounts without code return empty data
+            Ok(result) => Ok(result.len() >= 4 && result[..4] == MAGIC_VALUE),
+            // the node answered, the contract reverted
+            Err(err) if err.as_error_response().is_some() => Ok(false),
+            Err(err) => Err(EthosError::ProviderError(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{self, Token},
+        providers::Provider,
+        types::{Address, Bytes, H256},
+    };
+
+    use crate::chain::providers::ChainProviders;
+
+    use super::{ContractSignatureValidator, MAGIC_VALUE};
+
+    #[tokio::test]
+    async fn test_is_valid_signature() {
+        let (provider, mock) = Provider::mocked();
+        let mut providers = ChainProviders::new();
+        providers.insert(5, provider);
+
+        let valid = abi::encode(&[Token::FixedBytes(MAGIC_VALUE.to_vec())]);
+        mock.push::<Bytes, _>(Bytes::from(valid)).unwrap();
+        let result = providers
+            .is_valid_signature(
+                5,
+                Address::random(),
+                H256::random(),
+                Bytes::from(vec![1; 65]),
+            )
+            .await
+            .unwrap();
+        assert!(result);
+
+        mock.push::<Bytes, _>(Bytes::default()).unwrap();
+        let result = providers
+            .is_valid_signature(
+                5,
+                Address::random(),
+                H256::random(),
+                Bytes::from(vec![1; 65]),
+            )
+            .await
+            .unwrap();
+        assert!(!result);
+
+        let unknown_chain = providers
+            .is_valid_signature(1, Address::random(), H256::random(), Bytes::default())
+            .await;
+        assert!(unknown_chain.is_err());
+    }
+}
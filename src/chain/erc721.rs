@@ -0,0 +1,157 @@
+use async_graphql::async_trait;
+use chrono::NaiveDateTime;
+use ethers::{
+    abi::{self, Token},
+    providers::{JsonRpcClient, Middleware},
+    types::{Address, Bytes, Filter, Log, H256, U256},
+    utils::keccak256,
+};
+
+use crate::{errors::EthosError, services::nft::LogPosition};
+
+use super::providers::ChainProviders;
+
+/// Signature of the ERC-721 `Transfer` event, its first topic
+pub const TRANSFER_EVENT: &str = "Transfer(address,address,uint256)";
+
+/// Mint function the collection contracts expose to the minter
+pub const MINT_FUNCTION: &str = "mint(address,uint256)";
+
+/// Reads ERC-721 `Transfer` logs from the chain
+#[async_trait::async_trait]
+pub trait TransferLogSource: Send + Sync {
+    async fn block_number(&self, chain_id: u64) -> Result<u64, EthosError>;
+
+    async fn transfer_logs(
+        &self,
+        chain_id: u64,
+        contract: Address,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<Log>, EthosError>;
+
+    /// When the block was mined
+    async fn block_timestamp(&self, chain_id: u64, block: u64)
+        -> Result<NaiveDateTime, EthosError>;
+}
+
+#[async_trait::async_trait]
+impl<P: JsonRpcClient> TransferLogSource for ChainProviders<P> {
+    async fn block_number(&self, chain_id: u64) -> Result<u64, EthosError> {
+        let provider = self.get(chain_id)?;
+        Ok(provider.get_block_number().await?.as_u64())
+    }
+
+    async fn transfer_logs(
+        &self,
+        chain_id: u64,
+        contract: Address,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<Log>, EthosError> {
+        let provider = self.get(chain_id)?;
+        let filter = Filter::new()
+            .address(contract)
+            .topic0(transfer_topic())
+            .from_block(from_block)
+            .to_block(to_block);
+        Ok(provider.get_logs(&filter).await?)
+    }
+
+    async fn block_timestamp(
+        &self,
+        chain_id: u64,
+        block: u64,
+    ) -> Result<NaiveDateTime, EthosError> {
+        let provider = self.get(chain_id)?;
+        let block_data = provider
+            .get_block(block)
+            .await?
+            .ok_or(EthosError::BlockNotFound(chain_id, block))?;
+        NaiveDateTime::from_timestamp_opt(block_data.timestamp.low_u64() as i64, 0)
+            .ok_or(EthosError::BlockNotFound(chain_id, block))
+    }
+}
+
+pub fn transfer_topic() -> H256 {
+    H256::from(keccak256(TRANSFER_EVENT))
+}
+
+pub fn encode_mint(to: Address, token_id: U256) -> Bytes {
+    let args = abi::encode(&[Token::Address(to), Token::Uint(token_id)]);
+    [&keccak256(MINT_FUNCTION)[..4], &args].concat().into()
+}
+
+/// A decoded ERC-721 `Transfer` log
+#[derive(Debug, Clone)]
+pub struct TransferLog {
+    pub from: Address,
+    pub to: Address,
+    pub token_id: U256,
+    pub position: LogPosition,
+}
+
+impl TransferLog {
+    pub fn is_mint(&self) -> bool {
+        self.from == Address::zero()
+    }
+
+    pub fn is_burn(&self) -> bool {
+        self.to == Address::zero()
+    }
+}
+
+/// Decodes a `Transfer` log. ERC-20 transfers, which don't index the value, and
+/// logs removed by a reorg are ignored.
+pub fn decode_transfer(log: &Log) -> Option<TransferLog> {
+    if log.removed == Some(true) || log.topics.len() != 4 || log.topics[0] != transfer_topic() {
+        return None;
+    }
+    Some(TransferLog {
+        from: Address::from(log.topics[1]),
+        to: Address::from(log.topics[2]),
+        token_id: U256::from_big_endian(log.topics[3].as_bytes()),
+        position: LogPosition {
+            block_number: log.block_number?.as_u64() as i64,
+            tx_hash: format!("{:?}", log.transaction_hash?),
+            log_index: log.log_index?.as_u64() as i64,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Address, Log, H256, U256, U64};
+
+    use super::{decode_transfer, transfer_topic};
+
+    #[test]
+    fn test_decode_transfer() {
+        let to = Address::random();
+        let mut log = Log {
+            topics: vec![
+                transfer_topic(),
+                H256::zero(),
+                H256::from(to),
+                H256::from_low_u64_be(42),
+            ],
+            block_number: Some(U64::from(10)),
+            transaction_hash: Some(H256::random()),
+            log_index: Some(U256::from(3)),
+            ..Default::default()
+        };
+
+        let transfer = decode_transfer(&log).unwrap();
+        assert!(transfer.is_mint());
+        assert_eq!(transfer.to, to);
+        assert_eq!(transfer.token_id, U256::from(42));
+        assert_eq!(transfer.position.block_number, 10);
+        assert_eq!(transfer.position.log_index, 3);
+
+        log.removed = Some(true);
+        assert!(decode_transfer(&log).is_none());
+        log.removed = None;
+        log.topics.pop();
+        assert!(decode_transfer(&log).is_none());
+    }
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use ethers::{
+    providers::{JsonRpcClient, Middleware, Provider},
+    types::{Address, BlockNumber, U256},
+};
+
+use crate::errors::EthosError;
+
+/// Hands out the nonces of the signer of each chain. The first nonce is the
+/// pending transaction count of the node, the next ones are kept in memory.
+#[derive(Default)]
+pub struct NonceManager {
+    nonces: Mutex<HashMap<u64, U256>>,
+}
+
+impl NonceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Next nonce of `address`, the signer of the chain
+    pub async fn next<P: JsonRpcClient>(
+        &self,
+        chain_id: u64,
+        address: Address,
+        provider: &Provider<P>,
+    ) -> Result<U256, EthosError> {
+        if let Some(nonce) = self.take(chain_id, None) {
+            return Ok(nonce);
+        }
+        let count = provider
+            .get_transaction_count(address, Some(BlockNumber::Pending.into()))
+            .await?;
+        Ok(self.take(chain_id, Some(count)).unwrap_or(count))
+    }
+
+    /// Forgets the nonce of the chain, so the next one is read from the node.
+    /// Must be called when a transaction using a nonce was not sent.
+    pub fn reset(&self, chain_id: u64) {
+        self.nonces.lock().unwrap().remove(&chain_id);
+    }
+
+    fn take(&self, chain_id: u64, initial: Option<U256>) -> Option<U256> {
+        let mut nonces = self.nonces.lock().unwrap();
+        let nonce = match initial {
+            Some(initial) => nonces.entry(chain_id).or_insert(initial),
+            None => nonces.get_mut(&chain_id)?,
+        };
+        let current = *nonce;
+        *nonce += U256::one();
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::Provider,
+        types::{Address, U256},
+    };
+
+    use super::NonceManager;
+
+    #[tokio::test]
+    async fn test_nonce_manager() {
+        let (provider, mock) = Provider::mocked();
+        let nonces = NonceManager::new();
+        let address = Address::random();
+
+        mock.push(U256::from(7)).unwrap();
+        assert_eq!(
+            nonces.next(1, address, &provider).await.unwrap(),
+            U256::from(7)
+        );
+        // the following nonces don't hit the node
+        assert_eq!(
+            nonces.next(1, address, &provider).await.unwrap(),
+            U256::from(8)
+        );
+
+        mock.push(U256::from(3)).unwrap();
+        nonces.reset(1);
+        assert_eq!(
+            nonces.next(1, address, &provider).await.unwrap(),
+            U256::from(3)
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ethers::providers::{Http, JsonRpcClient, Provider};
+
+use crate::{errors::EthosError, services::nft::Network};
+
+/// JSON-RPC providers of the registered networks, indexed by chain id
+pub struct ChainProviders<P = Http> {
+    providers: HashMap<u64, Arc<Provider<P>>>,
+}
+
+impl ChainProviders<Http> {
+    /// Creates a provider for every network with a `rpc_url`
+    pub fn from_networks(networks: &[Network]) -> Self {
+        let mut providers = Self::new();
+        for network in networks {
+            let Some(rpc_url) = network.rpc_url.as_deref() else {
+                continue;
+            };
+            match Provider::<Http>::try_from(rpc_url) {
+                Ok(provider) => providers.insert(network.chain_id as u64, provider),
+                Err(err) => println!("Invalid rpc url for chain {}: {}", network.chain_id, err),
+            }
+        }
+        providers
+    }
+}
+
+impl<P: JsonRpcClient> ChainProviders<P> {
+    pub fn new() -> Self {
+        Self {
+            providers: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, chain_id: u64, provider: Provider<P>) {
+        self.providers.insert(chain_id, Arc::new(provider));
+    }
+
+    pub fn get(&self, chain_id: u64) -> Result<Arc<Provider<P>>, EthosError> {
+        self.providers
+            .get(&chain_id)
+            .cloned()
+            .ok_or(EthosError::NetworkNotConfigured(chain_id))
+    }
+}
+
+impl<P: JsonRpcClient> Default for ChainProviders<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,48 @@
+use std::{collections::HashMap, env, str::FromStr};
+
+use ethers::signers::{LocalWallet, Signer};
+
+use crate::{errors::EthosError, services::nft::Network};
+
+/// Minter keys of the registered networks, indexed by chain id
+#[derive(Default)]
+pub struct ChainSigners {
+    signers: HashMap<u64, LocalWallet>,
+}
+
+impl ChainSigners {
+    /// Reads the key of every network from `MINTER_PRIVATE_KEY_<chain id>`,
+    /// networks without one can't mint
+    pub fn from_networks(networks: &[Network]) -> Self {
+        let mut signers = Self::new();
+        for network in networks {
+            let Ok(key) = env::var(format!("MINTER_PRIVATE_KEY_{}", network.chain_id)) else {
+                continue;
+            };
+            match LocalWallet::from_str(&key) {
+                Ok(signer) => signers.insert(network.chain_id as u64, signer),
+                Err(err) => println!("Invalid minter key for chain {}: {}", network.chain_id, err),
+            }
+        }
+        signers
+    }
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, chain_id: u64, signer: LocalWallet) {
+        self.signers
+            .insert(chain_id, signer.with_chain_id(chain_id));
+    }
+
+    pub fn get(&self, chain_id: u64) -> Result<&LocalWallet, EthosError> {
+        self.signers
+            .get(&chain_id)
+            .ok_or(EthosError::MinterNotConfigured(chain_id))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signers.is_empty()
+    }
+}
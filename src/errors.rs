@@ -1,7 +1,14 @@
 use async_graphql::{Error, ErrorExtensions};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use ethers::types::SignatureError;
 use fixed_hash::rustc_hex::FromHexError;
 
+use crate::services::ticket::TicketState;
+
 #[derive(Debug, thiserror::Error)]
 pub enum EthosError {
     #[error("Connection Pool Error: {0:?}")]
@@ -23,12 +30,103 @@ pub enum EthosError {
 
     #[error("There was a database Error: {0:?}")]
     DatabaseError(#[from] diesel::result::Error),
+
+    #[error("Login message not valid: {0}")]
+    InvalidLoginMessage(String),
+
+    #[error("Chain {0} has no network configured")]
+    NetworkNotConfigured(u64),
+
+    #[error("Chain {0} has no minter key configured")]
+    MinterNotConfigured(u64),
+
+    #[error("Blockchain provider error: {0}")]
+    ProviderError(#[from] ethers::providers::ProviderError),
+
+    #[error("Block {1} of chain {0} was not found")]
+    BlockNotFound(u64, u64),
+
+    #[error("Failed to sign transaction: {0}")]
+    SignerError(#[from] ethers::signers::WalletError),
+
+    #[error("Origin `{0}` is not valid, expected `scheme://host[:port]`")]
+    InvalidOrigin(String),
+
+    #[error("A project must have at least one admin")]
+    LastProjectAdmin,
+
+    #[error("Ticket can't go from {0:?} to {1:?}")]
+    InvalidTicketTransition(TicketState, TicketState),
+
+    #[error("This operation is only available in development")]
+    DevelopmentOnly,
+
+    #[error("Benefit is not available to be redeemed")]
+    BenefitUnavailable,
+
+    #[error("Benefit is not available to this NFT")]
+    NftNotEligible,
+
+    #[error("Only the owner of the NFT can redeem its benefits")]
+    NotNftOwner,
+
+    #[error("Ticket can't be redeemed")]
+    TicketNotRedeemable,
+
+    #[error("No NFT of tier {0} is available")]
+    NoNftAvailable(i32),
+
+    #[error("No randomness source is configured")]
+    RandomnessNotConfigured,
+
+    #[error("Random request was already fulfilled")]
+    RandomRequestAlreadyFulfilled,
+
+    #[error("Random request was not fulfilled yet")]
+    RandomRequestNotFulfilled,
+
+    #[error("Failed to send email: {0}")]
+    EmailError(String),
+
+    #[error("Tickets were emailed recently, try again later")]
+    TicketEmailThrottled,
+
+    #[error("Schedule `{0}` is not a valid cron expression")]
+    InvalidSchedule(String),
+
+    #[error("Failed to fetch tickets: {0}")]
+    TicketSourceError(#[from] reqwest::Error),
+
+    #[error("Pagination not valid: {0}")]
+    InvalidPagination(&'static str),
+
+    #[error("NFT must be found by either its id or its nftId in the collection")]
+    InvalidNftLookup,
+
+    #[error("Reveal at a time needs the revealAt time")]
+    RevealTimeRequired,
+
+    #[error("Provenance of the collection is already committed, its reveal can't change")]
+    ProvenanceCommitted,
+
+    #[error("Import failed: {0}")]
+    ImportError(String),
+
+    #[error("Export failed: {0}")]
+    ExportError(String),
+
+    #[error("Refresh token not valid")]
+    InvalidRefreshToken,
+
+    #[error("Refresh token was already used, the session has been revoked")]
+    RefreshTokenReused,
 }
 
 impl ErrorExtensions for EthosError {
     fn extend(&self) -> Error {
         Error::new(format!("{}", self)).extend_with(|_err, e| match self {
             EthosError::ConnectionPoolError(_) => e.set("code", 500),
+            EthosError::InvalidRefreshToken | EthosError::RefreshTokenReused => e.set("code", 401),
             EthosError::DatabaseError(err) => {
                 println!("{:?}", err);
                 e.set("message", err.to_string());
@@ -37,3 +135,20 @@ impl ErrorExtensions for EthosError {
         })
     }
 }
+
+/// Errors of the REST routes, as `{ "error": message }`
+impl IntoResponse for EthosError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            EthosError::DatabaseError(diesel::result::Error::NotFound) => StatusCode::NOT_FOUND,
+            EthosError::DatabaseError(err) => {
+                println!("{:?}", err);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            EthosError::ConnectionPoolError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let body = serde_json::json!({ "error": self.to_string() });
+        (status, Json(body)).into_response()
+    }
+}
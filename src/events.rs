@@ -0,0 +1,98 @@
+use async_graphql::futures_util::{stream, Stream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+// events kept for subscribers that fall behind, older ones are dropped
+const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Transfer(TransferEvent),
+    RequestFulfilled(RequestFulfilledEvent),
+}
+
+/// An NFT changed hands
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferEvent {
+    pub nft_id: Uuid,
+    pub from: Option<Uuid>,
+    pub to: Uuid,
+    /// checksummed address of the new owner
+    pub to_address: String,
+}
+
+/// The random number of a request was delivered and its NFT drawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestFulfilledEvent {
+    pub request_id: String,
+}
+
+/// In-process broadcast of the events the subscriptions are fed with
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Publishes an event, it's dropped when nobody is listening
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    /// Events published from now on. Subscribers that lag behind skip the
+    /// events they missed.
+    pub fn subscribe(&self) -> impl Stream<Item = Event> {
+        stream::unfold(self.sender.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::futures_util::StreamExt;
+    use uuid::Uuid;
+
+    use super::{Event, EventBus, TransferEvent};
+
+    #[tokio::test]
+    async fn test_event_bus() {
+        let bus = EventBus::new(1);
+        // nobody is listening
+        bus.publish(Event::Transfer(transfer_event()));
+
+        let mut events = Box::pin(bus.subscribe());
+        let first = transfer_event();
+        let second = transfer_event();
+        bus.publish(Event::Transfer(first));
+        bus.publish(Event::Transfer(second.clone()));
+
+        // the first event was dropped for the lagging subscriber
+        assert_eq!(events.next().await, Some(Event::Transfer(second)));
+    }
+
+    fn transfer_event() -> TransferEvent {
+        TransferEvent {
+            nft_id: Uuid::new_v4(),
+            from: None,
+            to: Uuid::new_v4(),
+            to_address: String::new(),
+        }
+    }
+}
@@ -1,2 +1,3 @@
+pub mod has_role;
 pub mod is_authenticated;
 pub mod with_project;
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use async_graphql::{async_trait, Context, Error, ErrorExtensions, Guard};
+use uuid::Uuid;
+
+use crate::services::{
+    project::{Project, ProjectService, Role},
+    wallet::Wallet,
+};
+
+/// Requires the authenticated wallet to have `role` in the current project
+pub struct HasRole {
+    role: Role,
+    project_id: Option<Uuid>,
+}
+
+impl HasRole {
+    pub fn new(role: Role) -> Self {
+        Self {
+            role,
+            project_id: None,
+        }
+    }
+
+    /// Checks the role in the project an input names, which must be the
+    /// current project when the headers set one
+    pub fn project(mut self, project_id: Uuid) -> Self {
+        self.project_id = Some(project_id);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Guard for HasRole {
+    async fn check(&self, ctx: &Context<'_>) -> Result<(), Error> {
+        let Some(wallet) = ctx.data_opt::<Wallet>() else {
+            return Err("Forbidden".into());
+        };
+        let service = ctx.data::<Arc<ProjectService>>()?;
+        let role = match (self.project_id, ctx.data_opt::<Project>()) {
+            (Some(project_id), Some(project)) if project.id != project_id => {
+                return Err("`projectId` is not the project in your headers".into());
+            }
+            (Some(project_id), _) => service
+                .get_project(project_id)
+                .and_then(|project| service.get_member_role(&project, wallet)),
+            (None, Some(project)) => service.get_member_role(project, wallet),
+            (None, None) => {
+                return Err("You need a valid `project` value in your headers".into());
+            }
+        };
+        match role {
+            Ok(Some(role)) if role.grants(self.role) => Ok(()),
+            Ok(_) => Err("Forbidden".into()),
+            Err(err) => Err(err.extend()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use async_graphql::{EmptySubscription, Request, Schema, Variables};
+    use dotenvy::dotenv;
+    use ethers::{providers::Http, types::Address};
+    use serde_json::json;
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::{create_connection_pool, ConnectionPool},
+        resolvers::{MutationRoot, QueryRoot},
+        services::{project::ProjectService, wallet::WalletService},
+    };
+
+    #[tokio::test]
+    async fn test_update_project_role() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = Arc::new(ProjectService::new(pool.clone()));
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let admin = wallet_service.upsert_wallet(Address::random())?;
+        let stranger = wallet_service.upsert_wallet(Address::random())?;
+        let project = service.create_project_with_admin(&admin, "Guarded", None)?;
+        let other = service.create_project_with_admin(&admin, "Other guarded", None)?;
+
+        let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+            .data(service.clone())
+            .finish();
+        let update = |project_id| {
+            Request::new(
+                "mutation ($projectId: UUID!) { \
+                 updateProject(input: { projectId: $projectId, name: \"Renamed\" }) { name } }",
+            )
+            .variables(Variables::from_json(json!({ "projectId": project_id })))
+        };
+
+        // only admins of the project in the input can update it
+        let response = schema.execute(update(project.id).data(stranger)).await;
+        assert_eq!(response.errors[0].message, "Forbidden");
+        // which must be the project of the headers when they name one
+        let response = schema
+            .execute(update(project.id).data(admin.clone()).data(other))
+            .await;
+        assert_eq!(
+            response.errors[0].message,
+            "`projectId` is not the project in your headers"
+        );
+        let response = schema.execute(update(project.id).data(admin)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(service.get_project(project.id)?.name, "Renamed");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+use std::{any::Any, panic::AssertUnwindSafe, str::FromStr, sync::Arc};
+
+use async_graphql::{async_trait, futures_util::FutureExt};
+use cron::Schedule;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::sql_types::BigInt;
+use diesel::{Identifiable, PgConnection, Queryable};
+use diesel_derive_enum::DbEnum;
+use ethers::utils::keccak256;
+use r2d2::{Pool, PooledConnection};
+use uuid::Uuid;
+
+use crate::{database::ConnectionPool, errors::EthosError, schema::job_runs};
+
+sql_function!(fn pg_try_advisory_lock(key: BigInt) -> Bool);
+sql_function!(fn pg_advisory_unlock(key: BigInt) -> Bool);
+
+/// A task the scheduler runs on its schedule
+#[async_trait::async_trait]
+pub trait Job: Send + Sync {
+    /// Identifies the runs and the lock of the job, must be unique, e.g. by
+    /// including the project of jobs running once per project
+    fn name(&self) -> String;
+
+    /// Runs the job, returning a summary kept in its history
+    async fn run(&self) -> Result<String, EthosError>;
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::JobRunState"]
+pub enum JobRunState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+#[diesel(table_name = job_runs)]
+pub struct JobRun {
+    pub id: Uuid,
+    pub job: String,
+    pub state: JobRunState,
+    pub scheduled_at: chrono::NaiveDateTime,
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub started_at: chrono::NaiveDateTime,
+    pub finished_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Runs jobs on cron schedules. Every instance of the server runs a scheduler,
+/// each tick of a job is run by the first instance claiming it, and a job
+/// never overlaps with its own previous run.
+pub struct Scheduler {
+    pool: ConnectionPool,
+    jobs: Vec<(Schedule, Arc<dyn Job>)>,
+}
+
+impl Scheduler {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        Self {
+            pool: ConnectionPool::new(pool),
+            jobs: vec![],
+        }
+    }
+
+    /// Adds the job with a cron expression with seconds, like `0 */5 * * * *`
+    pub fn add(&mut self, schedule: &str, job: Arc<dyn Job>) -> Result<(), EthosError> {
+        let schedule = Schedule::from_str(schedule)
+            .map_err(|_| EthosError::InvalidSchedule(schedule.to_string()))?;
+        self.jobs.push((schedule, job));
+        Ok(())
+    }
+
+    /// Spawns a task per job, running until the runtime shuts down
+    pub fn start(self) {
+        let scheduler = Arc::new(self);
+        for (schedule, job) in scheduler.jobs.clone() {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                let mut after = chrono::Utc::now();
+                // ticks missed while the job was running are skipped
+                while let Some(tick) = schedule.after(&after).next() {
+                    let wait = (tick - chrono::Utc::now()).to_std().unwrap_or_default();
+                    tokio::time::sleep(wait).await;
+                    if let Err(err) = scheduler.run_at(job.as_ref(), tick.naive_utc()).await {
+                        println!("Failed to run job {}: {}", job.name(), err);
+                    }
+                    after = tick.max(chrono::Utc::now());
+                }
+            });
+        }
+    }
+
+    /// Runs the tick of the job, unless another instance claimed it or is still
+    /// running the job. Returns the run when it happened here.
+    pub async fn run_at(
+        &self,
+        job: &dyn Job,
+        scheduled_at: chrono::NaiveDateTime,
+    ) -> Result<Option<JobRun>, EthosError> {
+        let Some(mut lock) = JobLock::acquire(self.pool.get()?, &job.name())? else {
+            return Ok(None);
+        };
+
+        let claimed = diesel::insert_into(job_runs::table)
+            .values((
+                job_runs::job.eq(job.name()),
+                job_runs::scheduled_at.eq(scheduled_at),
+            ))
+            .on_conflict((job_runs::job, job_runs::scheduled_at))
+            .do_nothing()
+            .get_result::<JobRun>(&mut lock.conn)
+            .optional()?;
+        let Some(run) = claimed else {
+            return Ok(None);
+        };
+        lock.run = Some(run.id);
+
+        // a panicking job fails its run instead of the scheduler task
+        let (state, output, error) = match AssertUnwindSafe(job.run()).catch_unwind().await {
+            Ok(Ok(output)) => (JobRunState::Succeeded, Some(output), None),
+            Ok(Err(err)) => (JobRunState::Failed, None, Some(err.to_string())),
+            Err(panic) => (JobRunState::Failed, None, Some(panic_message(panic))),
+        };
+        let result = diesel::update(&run)
+            .set((
+                job_runs::state.eq(state),
+                job_runs::output.eq(output),
+                job_runs::error.eq(error),
+                job_runs::finished_at.eq(diesel::dsl::now),
+            ))
+            .get_result::<JobRun>(&mut lock.conn)?;
+        lock.run = None;
+        Ok(Some(result))
+    }
+
+    /// Latest runs of the job, newest first
+    pub fn get_runs(&self, job: &str, limit: i64) -> Result<Vec<JobRun>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = job_runs::table
+            .filter(job_runs::job.eq(job))
+            .order(job_runs::started_at.desc())
+            .limit(limit)
+            .load::<JobRun>(&mut conn)?;
+        Ok(result)
+    }
+}
+
+/// Session lock of a job, held while it runs. Dropping it, even when the run
+/// is cancelled, fails the unfinished run and releases the lock, which would
+/// otherwise stay with the pooled connection. The lock is also released when
+/// the connection is closed if the process dies.
+struct JobLock {
+    conn: PooledConnection<ConnectionManager<PgConnection>>,
+    key: i64,
+    // run started under the lock and not finished yet
+    run: Option<Uuid>,
+}
+
+impl JobLock {
+    fn acquire(
+        mut conn: PooledConnection<ConnectionManager<PgConnection>>,
+        name: &str,
+    ) -> Result<Option<Self>, EthosError> {
+        let key = lock_key(name);
+        if !diesel::select(pg_try_advisory_lock(key)).get_result::<bool>(&mut conn)? {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            conn,
+            key,
+            run: None,
+        }))
+    }
+}
+
+impl Drop for JobLock {
+    fn drop(&mut self) {
+        if let Some(run) = self.run {
+            let failed = diesel::update(job_runs::table.find(run))
+                .set((
+                    job_runs::state.eq(JobRunState::Failed),
+                    job_runs::error.eq("Run was interrupted"),
+                    job_runs::finished_at.eq(diesel::dsl::now),
+                ))
+                .execute(&mut self.conn);
+            if let Err(err) = failed {
+                println!("Failed to fail the interrupted run {}: {}", run, err);
+            }
+        }
+        let unlocked =
+            diesel::select(pg_advisory_unlock(self.key)).get_result::<bool>(&mut self.conn);
+        if let Err(err) = unlocked {
+            println!("Failed to release the job lock {}: {}", self.key, err);
+        }
+    }
+}
+
+/// Error of a run whose job panicked
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    format!("Job panicked: {}", message)
+}
+
+/// Advisory lock of the job, stable across instances
+fn lock_key(name: &str) -> i64 {
+    let hash = keccak256(name);
+    i64::from_be_bytes(hash[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use anyhow::Result;
+    use async_graphql::async_trait;
+    use chrono::Timelike;
+    use diesel::prelude::*;
+    use dotenvy::dotenv;
+    use tokio::sync::Notify;
+
+    use crate::{database::create_connection_pool, errors::EthosError};
+
+    use super::{lock_key, pg_advisory_unlock, pg_try_advisory_lock, Job, JobRunState, Scheduler};
+
+    /// Counts its runs, failing every other one. Waits to be released when
+    /// `blocked`.
+    #[derive(Default)]
+    struct CountingJob {
+        runs: AtomicUsize,
+        blocked: bool,
+        started: Notify,
+        release: Notify,
+    }
+
+    #[async_trait::async_trait]
+    impl Job for CountingJob {
+        fn name(&self) -> String {
+            if self.blocked {
+                "test_blocked_job".to_string()
+            } else {
+                "test_counting_job".to_string()
+            }
+        }
+
+        async fn run(&self) -> Result<String, EthosError> {
+            if self.blocked {
+                self.started.notify_one();
+                self.release.notified().await;
+            }
+            let runs = self.runs.fetch_add(1, Ordering::SeqCst) + 1;
+            if runs.is_multiple_of(2) {
+                return Err(EthosError::DevelopmentOnly);
+            }
+            Ok(format!("run {}", runs))
+        }
+    }
+
+    /// Panics, or never finishes when `stuck`
+    struct BrokenJob {
+        stuck: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl Job for BrokenJob {
+        fn name(&self) -> String {
+            if self.stuck {
+                "test_stuck_job".to_string()
+            } else {
+                "test_panicking_job".to_string()
+            }
+        }
+
+        async fn run(&self) -> Result<String, EthosError> {
+            if self.stuck {
+                std::future::pending::<()>().await;
+            }
+            panic!("broken job");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_once_per_tick() -> Result<()> {
+        dotenv().ok();
+        let scheduler = Scheduler::new(create_connection_pool());
+        let job = CountingJob::default();
+        let tick = chrono::Utc::now().naive_utc().with_nanosecond(0).unwrap();
+
+        let run = scheduler.run_at(&job, tick).await?.unwrap();
+        assert_eq!(run.state, JobRunState::Succeeded);
+        assert_eq!(run.output.as_deref(), Some("run 1"));
+        // another instance reaching the same tick skips it
+        assert!(scheduler.run_at(&job, tick).await?.is_none());
+
+        let next = tick + chrono::Duration::seconds(1);
+        let run = scheduler.run_at(&job, next).await?.unwrap();
+        assert_eq!(run.state, JobRunState::Failed);
+        assert!(run.error.is_some());
+        assert!(run.finished_at.is_some());
+
+        assert_eq!(job.runs.load(Ordering::SeqCst), 2);
+        let history = scheduler.get_runs(&job.name(), 2)?;
+        assert_eq!(history.len(), 2);
+        assert!(history.iter().any(|run| run.scheduled_at == next));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_runs_dont_overlap() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let scheduler = Arc::new(Scheduler::new(pool.clone()));
+        let job = Arc::new(CountingJob {
+            blocked: true,
+            ..Default::default()
+        });
+        let tick = chrono::Utc::now().naive_utc().with_nanosecond(0).unwrap();
+
+        let running = {
+            let (scheduler, job) = (scheduler.clone(), job.clone());
+            tokio::spawn(async move { scheduler.run_at(job.as_ref(), tick).await })
+        };
+        job.started.notified().await;
+        // the next tick is skipped while the job is still running
+        let next = tick + chrono::Duration::seconds(1);
+        assert!(scheduler.run_at(job.as_ref(), next).await?.is_none());
+        job.release.notify_one();
+        assert!(running.await??.is_some());
+
+        // the lock is released after the run
+        let mut conn = pool.get()?;
+        let locked = diesel::select(pg_try_advisory_lock(lock_key(&job.name())))
+            .get_result::<bool>(&mut conn)?;
+        assert!(locked);
+        diesel::select(pg_advisory_unlock(lock_key(&job.name()))).get_result::<bool>(&mut conn)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_broken_runs_release_the_lock() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let scheduler = Scheduler::new(pool.clone());
+        let tick = chrono::Utc::now().naive_utc().with_nanosecond(0).unwrap();
+
+        let job = BrokenJob { stuck: false };
+        let run = scheduler.run_at(&job, tick).await?.unwrap();
+        assert_eq!(run.state, JobRunState::Failed);
+        assert_eq!(run.error.as_deref(), Some("Job panicked: broken job"));
+        let next = tick + chrono::Duration::seconds(1);
+        assert!(scheduler.run_at(&job, next).await?.is_some());
+
+        // a cancelled run is failed
+        let job = BrokenJob { stuck: true };
+        let cancelled = tokio::time::timeout(
+            std::time::Duration::from_millis(200),
+            scheduler.run_at(&job, tick),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        let run = &scheduler.get_runs(&job.name(), 1)?[0];
+        assert_eq!(run.scheduled_at, tick);
+        assert_eq!(run.state, JobRunState::Failed);
+        assert!(run.finished_at.is_some());
+
+        let mut conn = pool.get()?;
+        let locked = diesel::select(pg_try_advisory_lock(lock_key(&job.name())))
+            .get_result::<bool>(&mut conn)?;
+        assert!(locked);
+        diesel::select(pg_advisory_unlock(lock_key(&job.name()))).get_result::<bool>(&mut conn)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use async_graphql::async_trait;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    errors::EthosError,
+    services::{
+        email::EmailService,
+        ticket::{NewTicket, TicketService},
+    },
+};
+
+use super::scheduler::Job;
+
+/// Tickets inserted at once, well under the bind parameter limit of Postgres
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// A ticket sold by the ticketing platform
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExternalTicket {
+    /// order of the ticket
+    pub token: String,
+    pub code: String,
+    pub email: String,
+    #[serde(default)]
+    pub name: String,
+    pub tier: Option<i32>,
+    pub event: Option<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Where the sold tickets are fetched from
+#[async_trait::async_trait]
+pub trait TicketSource: Send + Sync {
+    async fn fetch(&self) -> Result<Vec<ExternalTicket>, EthosError>;
+}
+
+/// Endpoint returning a JSON array of [`ExternalTicket`]
+pub struct HttpTicketSource {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpTicketSource {
+    pub fn new(url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TicketSource for HttpTicketSource {
+    async fn fetch(&self) -> Result<Vec<ExternalTicket>, EthosError> {
+        let tickets = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<ExternalTicket>>()
+            .await?;
+        Ok(tickets)
+    }
+}
+
+/// Imports the tickets of the source into the project, known codes are skipped
+pub struct ImportTicketsJob {
+    tickets: Arc<TicketService>,
+    source: Arc<dyn TicketSource>,
+    project_id: Uuid,
+    batch_size: usize,
+}
+
+impl ImportTicketsJob {
+    pub fn new(
+        tickets: Arc<TicketService>,
+        source: Arc<dyn TicketSource>,
+        project_id: Uuid,
+    ) -> Self {
+        Self {
+            tickets,
+            source,
+            project_id,
+            batch_size: IMPORT_BATCH_SIZE,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for ImportTicketsJob {
+    fn name(&self) -> String {
+        format!("import_tickets:{}", self.project_id)
+    }
+
+    async fn run(&self) -> Result<String, EthosError> {
+        let fetched = self.source.fetch().await?;
+        let total = fetched.len();
+        let mut imported = 0;
+        for batch in fetched.chunks(self.batch_size.max(1)) {
+            let new_tickets = batch
+                .iter()
+                .cloned()
+                .map(|ticket| NewTicket {
+                    token: ticket.token,
+                    code: ticket.code,
+                    email: ticket.email,
+                    name: ticket.name,
+                    tier: ticket.tier.unwrap_or(1),
+                    event: ticket.event,
+                    expires_at: ticket.expires_at,
+                    project_id: self.project_id,
+                })
+                .collect::<Vec<NewTicket>>();
+            imported += self.tickets.import_tickets(new_tickets)?.len();
+        }
+        Ok(format!("imported {} of {} tickets", imported, total))
+    }
+}
+
+/// Emails their codes to the attendees of the tickets that were never emailed
+pub struct TicketEmailsJob {
+    emails: Arc<EmailService>,
+}
+
+impl TicketEmailsJob {
+    pub fn new(emails: Arc<EmailService>) -> Self {
+        Self { emails }
+    }
+}
+
+#[async_trait::async_trait]
+impl Job for TicketEmailsJob {
+    fn name(&self) -> String {
+        "ticket_emails".to_string()
+    }
+
+    async fn run(&self) -> Result<String, EthosError> {
+        let emailed = self.emails.send_new_ticket_emails().await?;
+        Ok(format!("emailed {} tickets", emailed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use async_graphql::async_trait;
+    use dotenvy::dotenv;
+    use uuid::Uuid;
+
+    use crate::{
+        database::create_connection_pool,
+        errors::EthosError,
+        jobs::scheduler::Job,
+        mail::{queue::LocalQueue, transport::MemoryTransport},
+        services::{email::EmailService, project::ProjectService, ticket::TicketService},
+    };
+
+    use super::{ExternalTicket, ImportTicketsJob, TicketEmailsJob, TicketSource};
+
+    struct StaticTickets(Vec<ExternalTicket>);
+
+    #[async_trait::async_trait]
+    impl TicketSource for StaticTickets {
+        async fn fetch(&self) -> Result<Vec<ExternalTicket>, EthosError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_and_email_tickets() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let project = ProjectService::new(pool.clone()).create_project("Importer", None)?;
+        let ticket_service = Arc::new(TicketService::new(pool.clone()));
+        let transport = Arc::new(MemoryTransport::default());
+        let mut email_service = EmailService::new(
+            pool.clone(),
+            transport.clone(),
+            Arc::new(LocalQueue::default()),
+        );
+        // the other tests' tickets and emails are left to them
+        email_service.project_id = Some(project.id);
+        // the orders are emailed a page at a time
+        email_service.order_batch_size = 1;
+        let email_service = Arc::new(email_service);
+
+        let token = Uuid::new_v4().simple().to_string();
+        let email = format!("{}@example.com", token);
+        // the last ticket is another order of the attendee
+        let source = StaticTickets(
+            [&token, &token, &format!("{}-2", token)]
+                .into_iter()
+                .map(|token| ExternalTicket {
+                    token: token.clone(),
+                    code: Uuid::new_v4().simple().to_string(),
+                    email: email.clone(),
+                    name: "Ana".to_string(),
+                    tier: Some(2),
+                    event: None,
+                    expires_at: None,
+                })
+                .collect(),
+        );
+        let mut import =
+            ImportTicketsJob::new(ticket_service.clone(), Arc::new(source), project.id);
+        // each project has its own runs and lock
+        assert_eq!(import.name(), format!("import_tickets:{}", project.id));
+        // the tickets are inserted a batch at a time
+        import.batch_size = 1;
+        assert_eq!(import.run().await?, "imported 3 of 3 tickets");
+        // imports are idempotent
+        assert_eq!(import.run().await?, "imported 0 of 3 tickets");
+        let tickets = ticket_service.get_tickets_by_token(&project, &token)?;
+        assert!(tickets.iter().all(|ticket| ticket.tier == 2));
+
+        // the tickets are emailed once, an email per order
+        let emails = TicketEmailsJob::new(email_service.clone());
+        assert_eq!(emails.run().await?, "emailed 3 tickets");
+        assert_eq!(emails.run().await?, "emailed 0 tickets");
+        email_service.process().await?;
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 2);
+        let order = sent
+            .iter()
+            .find(|sent| sent.body.ends_with(&format!("{}.", token)))
+            .unwrap();
+        assert!(tickets
+            .iter()
+            .all(|ticket| order.body.contains(&ticket.code)));
+        Ok(())
+    }
+}
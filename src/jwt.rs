@@ -1,39 +1,73 @@
-use std::{collections::HashSet, env};
+use std::env;
 
+use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// access tokens are short lived, sessions are kept alive with refresh tokens
+const DEFAULT_ACCESS_TOKEN_TTL_SECONDS: i64 = 15 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// id of the authenticated wallet
+    pub sub: Uuid,
+    /// id of the project the token was issued for
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+}
 
 pub struct JwtAuthentication {
     secret: String,
+    access_token_ttl: Duration,
 }
 
 impl JwtAuthentication {
     pub fn new() -> Self {
         let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-        Self { secret }
+        let ttl = env::var("JWT_ACCESS_TOKEN_TTL")
+            .ok()
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(DEFAULT_ACCESS_TOKEN_TTL_SECONDS);
+        Self {
+            secret,
+            access_token_ttl: Duration::seconds(ttl),
+        }
     }
 
-    pub fn create_token<T>(&self, obj: &T) -> Result<String, jsonwebtoken::errors::Error>
-    where
-        T: Serialize,
-    {
+    pub fn create_token(
+        &self,
+        subject: Uuid,
+        audience: &str,
+    ) -> Result<(String, Claims), jsonwebtoken::errors::Error> {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: subject,
+            aud: audience.to_string(),
+            iat: now.timestamp(),
+            exp: (now + self.access_token_ttl).timestamp(),
+        };
         let token = encode(
             &Header::default(),
-            &obj,
+            &claims,
             &EncodingKey::from_secret(self.secret.as_ref()),
-        );
-        println!("Token {:?}", token);
-        token
+        )?;
+        Ok((token, claims))
     }
 
-    pub fn validate<T: DeserializeOwned>(
+    /// Decodes a token, checking its expiration and, when given, its audience
+    pub fn validate(
         &self,
         token: &str,
-    ) -> Result<T, jsonwebtoken::errors::Error> {
+        audience: Option<&str>,
+    ) -> Result<Claims, jsonwebtoken::errors::Error> {
         let mut validation = Validation::default();
-        validation.validate_exp = false;
-        validation.required_spec_claims = HashSet::new();
-        let decoded = decode::<T>(
+        validation.set_required_spec_claims(&["exp", "sub", "aud"]);
+        if let Some(audience) = audience {
+            validation.set_audience(&[audience]);
+        }
+        let decoded = decode::<Claims>(
             token,
             &DecodingKey::from_secret(self.secret.as_ref()),
             &validation,
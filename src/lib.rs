@@ -1,7 +1,15 @@
+pub mod chain;
 pub mod database;
 mod errors;
+pub mod events;
 mod guards;
+pub mod jobs;
 mod jwt;
+pub mod loaders;
+pub mod mail;
+pub mod metadata;
+pub mod pagination;
 pub mod resolvers;
 pub mod schema;
 pub mod services;
+mod siwe;
@@ -0,0 +1,373 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::{
+    async_trait,
+    dataloader::{DataLoader, Loader},
+    ErrorExtensions, Request, SchemaBuilder,
+};
+use uuid::Uuid;
+
+use crate::services::{
+    nft::{Collection, CollectionContract, NftAttribute, NftService},
+    rarity::NftRarity,
+    reveal::CollectionReveal,
+    wallet::{Wallet, WalletService},
+};
+
+/// Attributes of NFTs by the id of the NFT, sorted by trait
+pub struct AttributesLoader(Arc<NftService>);
+
+#[async_trait::async_trait]
+impl Loader<Uuid> for AttributesLoader {
+    type Value = Vec<NftAttribute>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let attributes = self
+            .0
+            .get_attributes_of_nfts(keys)
+            .map_err(|err| err.extend())?;
+        let mut result = keys
+            .iter()
+            .map(|nft| (*nft, vec![]))
+            .collect::<HashMap<_, Vec<_>>>();
+        for (nft, attribute) in attributes {
+            result.entry(nft).or_default().push(attribute);
+        }
+        Ok(result)
+    }
+}
+
+pub struct WalletLoader(Arc<WalletService>);
+
+#[async_trait::async_trait]
+impl Loader<Uuid> for WalletLoader {
+    type Value = Wallet;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let wallets = self
+            .0
+            .get_wallets_by_ids(keys)
+            .map_err(|err| err.extend())?;
+        Ok(wallets
+            .into_iter()
+            .map(|wallet| (wallet.id, wallet))
+            .collect())
+    }
+}
+
+pub struct CollectionLoader(Arc<NftService>);
+
+#[async_trait::async_trait]
+impl Loader<Uuid> for CollectionLoader {
+    type Value = Collection;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let collections = self
+            .0
+            .get_collections_by_ids(keys)
+            .map_err(|err| err.extend())?;
+        Ok(collections
+            .into_iter()
+            .map(|collection| (collection.id, collection))
+            .collect())
+    }
+}
+
+pub struct CollectionContractLoader(Arc<NftService>);
+
+#[async_trait::async_trait]
+impl Loader<Uuid> for CollectionContractLoader {
+    type Value = CollectionContract;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let contracts = self
+            .0
+            .get_collection_contracts_by_ids(keys)
+            .map_err(|err| err.extend())?;
+        Ok(contracts
+            .into_iter()
+            .map(|contract| (contract.id, contract))
+            .collect())
+    }
+}
+
+/// Rarity of NFTs by the id of the NFT, missing for unranked NFTs
+pub struct RarityLoader(Arc<NftService>);
+
+#[async_trait::async_trait]
+impl Loader<Uuid> for RarityLoader {
+    type Value = NftRarity;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let rarities = self.0.get_rarities(keys).map_err(|err| err.extend())?;
+        Ok(rarities
+            .into_iter()
+            .map(|rarity| (rarity.nft_id, rarity))
+            .collect())
+    }
+}
+
+/// Delayed reveals by the id of their collection
+pub struct RevealLoader(Arc<NftService>);
+
+#[async_trait::async_trait]
+impl Loader<Uuid> for RevealLoader {
+    type Value = CollectionReveal;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Uuid]) -> Result<HashMap<Uuid, Self::Value>, Self::Error> {
+        let reveals = self.0.get_reveals(keys).map_err(|err| err.extend())?;
+        Ok(reveals
+            .into_iter()
+            .map(|reveal| (reveal.collection_id, reveal))
+            .collect())
+    }
+}
+
+/// Creates the loaders of the NFT relations. The loaders of a request batch
+/// the rows its fields ask for, so every request gets new ones.
+pub struct Loaders {
+    nfts: Arc<NftService>,
+    wallets: Arc<WalletService>,
+}
+
+impl Loaders {
+    pub fn new(nfts: Arc<NftService>, wallets: Arc<WalletService>) -> Self {
+        Self { nfts, wallets }
+    }
+
+    pub fn add_to_request(&self, request: Request) -> Request {
+        request
+            .data(DataLoader::new(
+                AttributesLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                WalletLoader(self.wallets.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                CollectionLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                CollectionContractLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                RarityLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                RevealLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+    }
+
+    /// Loaders shared by the requests without their own, like subscriptions.
+    /// They don't cache, so they never return stale rows.
+    pub fn add_to_schema<Q, M, S>(&self, schema: SchemaBuilder<Q, M, S>) -> SchemaBuilder<Q, M, S> {
+        schema
+            .data(DataLoader::new(
+                AttributesLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                WalletLoader(self.wallets.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                CollectionLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                CollectionContractLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                RarityLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+            .data(DataLoader::new(
+                RevealLoader(self.nfts.clone()),
+                tokio::spawn,
+            ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use anyhow::Result;
+    use async_graphql::{value, EmptyMutation, EmptySubscription, Request, Schema, Variables};
+    use diesel::{r2d2::ConnectionManager, PgConnection};
+    use dotenvy::dotenv;
+    use ethers::{providers::Http, types::Address};
+    use r2d2::{
+        event::{CheckoutEvent, HandleEvent},
+        Pool,
+    };
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::ConnectionPool,
+        events::EventBus,
+        resolvers::QueryRoot,
+        services::{
+            nft::{NewNft, Nft, NftService},
+            project::{Project, ProjectService},
+            wallet::WalletService,
+        },
+    };
+
+    use super::Loaders;
+
+    /// Counts the connections taken from the pool, one per service call
+    #[derive(Debug, Default)]
+    struct Checkouts(Arc<AtomicUsize>);
+
+    impl HandleEvent for Checkouts {
+        fn handle_checkout(&self, _event: CheckoutEvent) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Creates a collection of the project with `count` NFTs on a new network
+    fn create_nfts(service: &NftService, project: &Project, count: i32) -> Result<Vec<Nft>> {
+        let network = service.create_network((Uuid::new_v4().as_u128() % 1_000_000) as i32)?;
+        let collection = service.create_collection(project, "Loaders", None)?;
+        let contract = service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        let new_nfts = (1..=count)
+            .map(|nft_id| NewNft {
+                nft_id,
+                name: format!("Loader #{}", nft_id),
+                image: String::new(),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: collection.id,
+                network_contract_id: contract.id,
+            })
+            .collect();
+        Ok(service.create_nfts(new_nfts)?)
+    }
+
+    #[tokio::test]
+    async fn test_nft_relations() -> Result<()> {
+        dotenv().ok();
+        let checkouts = Arc::new(AtomicUsize::new(0));
+        let pool = Pool::builder()
+            .max_size(15)
+            .event_handler(Box::new(Checkouts(checkouts.clone())))
+            .build(ConnectionManager::<PgConnection>::new(env::var(
+                "DATABASE_URL",
+            )?))?;
+        let nft_service = Arc::new(NftService::new(pool.clone(), Arc::new(EventBus::default())));
+        let wallet_service = Arc::new(WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        ));
+        let project = ProjectService::new(pool).create_project("Loaders", None)?;
+        let nft = create_nfts(&nft_service, &project, 1)?.remove(0);
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+        nft_service.transfer_nft(nft.id, &wallet)?;
+        let tier = nft_service.create_attribute(Some("Tier"), Some("1".to_string()), None, None)?;
+        nft_service.create_attribute_nft_relation(nft.id, tier.id)?;
+
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .data(nft_service.clone())
+            .finish();
+        let loaders = Loaders::new(nft_service.clone(), wallet_service);
+        let query = r#"
+            query ($id: UUID!, $collection: UUID!) {
+                nft(input: { id: $id, collectionId: $collection }) {
+                    name
+                    revealed
+                    minted
+                    owner { address }
+                    collection { id }
+                    attributes { traitType value }
+                    history { ... on Transfer { from { address } to { address } } }
+                }
+            }
+        "#;
+        let request = Request::new(query).variables(Variables::from_json(json!({
+            "id": nft.id,
+            "collection": nft.collection_id,
+        })));
+        let response = schema.execute(loaders.add_to_request(request)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data,
+            value!({
+                "nft": {
+                    "name": "Loader #1",
+                    "revealed": true,
+                    "minted": false,
+                    "owner": { "address": wallet.address.clone() },
+                    "collection": { "id": nft.collection_id.to_string() },
+                    "attributes": [{ "traitType": "Tier", "value": "1" }],
+                    "history": [{ "from": null, "to": { "address": wallet.address.clone() } }],
+                }
+            })
+        );
+
+        // a page of NFTs loads each relation in one batch, whatever its size
+        let page = r#"
+            query ($collection: UUID!) {
+                nfts(input: { collectionId: $collection }) {
+                    edges {
+                        name
+                        owner { address }
+                        collection { id }
+                        networkContract { address }
+                        attributes { traitType value }
+                        rarityRank
+                    }
+                }
+            }
+        "#;
+        let queries = |collection_id| {
+            let checkouts = checkouts.clone();
+            let (schema, loaders) = (&schema, &loaders);
+            async move {
+                let before = checkouts.load(Ordering::SeqCst);
+                let request = Request::new(page)
+                    .variables(Variables::from_json(json!({ "collection": collection_id })));
+                let response = schema.execute(loaders.add_to_request(request)).await;
+                assert!(response.errors.is_empty(), "{:?}", response.errors);
+                checkouts.load(Ordering::SeqCst) - before
+            }
+        };
+        let page_nfts = create_nfts(&nft_service, &project, 5)?;
+        for page_nft in &page_nfts {
+            nft_service.transfer_nft(page_nft.id, &wallet)?;
+            nft_service.create_attribute_nft_relation(page_nft.id, tier.id)?;
+        }
+        assert_eq!(
+            queries(page_nfts[0].collection_id).await,
+            queries(nft.collection_id).await
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,3 @@
+pub mod queue;
+pub mod template;
+pub mod transport;
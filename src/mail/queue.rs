@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use async_graphql::async_trait;
+use tokio::sync::Notify;
+
+#[cfg(feature = "redis")]
+use crate::errors::EthosError;
+
+/// Wakes up the outbox workers when emails are queued. The emails themselves
+/// live in the database, a lost notification only delays them until the next
+/// poll.
+#[async_trait::async_trait]
+pub trait EmailQueue: Send + Sync {
+    async fn notify(&self);
+
+    /// Returns on a notification or after the timeout
+    async fn wait(&self, timeout: Duration);
+}
+
+/// Notifies the workers of this process
+#[derive(Default)]
+pub struct LocalQueue {
+    notify: Notify,
+}
+
+#[async_trait::async_trait]
+impl EmailQueue for LocalQueue {
+    async fn notify(&self) {
+        self.notify.notify_one();
+    }
+
+    async fn wait(&self, timeout: Duration) {
+        let _ = tokio::time::timeout(timeout, self.notify.notified()).await;
+    }
+}
+
+/// Wakes one waiting worker of any process through a Redis list, BLPOP hands
+/// each notification to a single waiter
+#[cfg(feature = "redis")]
+pub struct RedisQueue {
+    client: redis::Client,
+    key: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisQueue {
+    pub fn new(url: &str) -> Result<Self, EthosError> {
+        let client =
+            redis::Client::open(url).map_err(|err| EthosError::EmailError(err.to_string()))?;
+        Ok(Self {
+            client,
+            key: "ethos:emails".to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait::async_trait]
+impl EmailQueue for RedisQueue {
+    async fn notify(&self) {
+        let pushed = async {
+            let mut conn = self.client.get_async_connection().await?;
+            redis::cmd("LPUSH")
+                .arg(&self.key)
+                .arg(1)
+                .query_async::<_, ()>(&mut conn)
+                .await
+        };
+        if let Err(err) = pushed.await {
+            println!("Failed to notify the email queue: {}", err);
+        }
+    }
+
+    async fn wait(&self, timeout: Duration) {
+        let popped = async {
+            let mut conn = self.client.get_async_connection().await?;
+            redis::cmd("BLPOP")
+                .arg(&self.key)
+                .arg(timeout.as_secs().max(1))
+                .query_async::<_, Option<(String, String)>>(&mut conn)
+                .await
+        };
+        if let Err(err) = popped.await {
+            println!("Failed to wait on the email queue: {}", err);
+            tokio::time::sleep(timeout).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{EmailQueue, LocalQueue};
+
+    #[tokio::test]
+    async fn test_local_queue() {
+        let queue = LocalQueue::default();
+        // a notification sent before waiting is not lost
+        queue.notify().await;
+        tokio::time::timeout(Duration::from_secs(1), queue.wait(Duration::from_secs(60)))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+/// Replaces the `{{variable}}` placeholders of the template. Placeholders
+/// without a value are kept, so a typo shows up in the sent email.
+pub fn render(template: &str, variables: &HashMap<&str, String>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start..start + end + 2];
+        rendered.push_str(&rest[..start]);
+        match variables.get(placeholder[2..placeholder.len() - 2].trim()) {
+            Some(value) => rendered.push_str(value),
+            None => rendered.push_str(placeholder),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::render;
+
+    #[test]
+    fn test_render() {
+        let variables = HashMap::from([("name", "Ana".to_string()), ("code", "X1".to_string())]);
+
+        assert_eq!(
+            render("Hi {{name}}, your code is {{ code }}.", &variables),
+            "Hi Ana, your code is X1."
+        );
+        assert_eq!(
+            render("{{unknown}} {{name", &variables),
+            "{{unknown}} {{name"
+        );
+    }
+}
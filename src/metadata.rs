@@ -0,0 +1,274 @@
+use std::{env, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::errors::EthosError;
+use crate::services::nft::{DisplayType, NftService};
+
+/// ERC-721 metadata of a token, in the format read by OpenSea
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub description: String,
+    pub image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub animation_url: Option<String>,
+    pub attributes: Vec<MetadataAttribute>,
+}
+
+/// Values of attributes with a display type are numbers
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MetadataAttribute {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trait_type: Option<String>,
+    pub value: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_value: Option<Value>,
+}
+
+impl MetadataAttribute {
+    pub fn new(
+        trait_type: Option<String>,
+        value: Option<String>,
+        max_value: Option<String>,
+        display_type: Option<DisplayType>,
+    ) -> Self {
+        let numeric = display_type.is_some();
+        Self {
+            trait_type,
+            value: value.map_or(Value::Null, |value| metadata_value(value, numeric)),
+            display_type: display_type.map(|display_type| match display_type {
+                DisplayType::Number => "number",
+                DisplayType::BoostPercentage => "boost_percentage",
+                DisplayType::BoostNumber => "boost_number",
+            }),
+            max_value: max_value.map(|value| metadata_value(value, numeric)),
+        }
+    }
+}
+
+/// Contract-level metadata of a collection, returned by `contractURI`
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ContractMetadata {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_link: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seller_fee_basis_points: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_recipient: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContractMetadataQuery {
+    /// address of the contract whose fee recipient is returned
+    pub contract: Option<String>,
+    /// chain id of the contract, an address can be deployed on several
+    pub chain: Option<i32>,
+}
+
+/// URL the metadata routes are served at, from `PUBLIC_URL`
+pub fn public_url() -> String {
+    env::var("PUBLIC_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| "http://127.0.0.1:3000".to_string())
+}
+
+/// `tokenURI` of the contract at `address` on the chain without the token id
+pub fn token_uri_base(chain_id: i32, address: &str) -> String {
+    format!("{}/metadata/{}/{}/", public_url(), chain_id, address)
+}
+
+pub fn contract_uri(collection_id: Uuid, chain_id: i32, address: &str) -> String {
+    format!(
+        "{}/collections/{}/contract-metadata?chain={}&contract={}",
+        public_url(),
+        collection_id,
+        chain_id,
+        address
+    )
+}
+
+pub fn router<S>(nft_service: Arc<NftService>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/metadata/:chain/:contract/:token_id", get(token_metadata))
+        .route("/collections/:id/contract-metadata", get(contract_metadata))
+        .with_state(nft_service)
+}
+
+pub async fn token_metadata(
+    State(service): State<Arc<NftService>>,
+    Path((chain, contract, token_id)): Path<(i32, String, i32)>,
+) -> Result<Json<TokenMetadata>, EthosError> {
+    service
+        .get_token_metadata(chain, &contract, token_id)
+        .map(Json)
+}
+
+pub async fn contract_metadata(
+    State(service): State<Arc<NftService>>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ContractMetadataQuery>,
+) -> Result<Json<ContractMetadata>, EthosError> {
+    service
+        .get_contract_metadata(id, query.chain, query.contract.as_deref())
+        .map(Json)
+}
+
+fn metadata_value(value: String, numeric: bool) -> Value {
+    if numeric {
+        if let Ok(number) = value.parse::<serde_json::Number>() {
+            return Value::Number(number);
+        }
+    }
+    Value::String(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use axum::{
+        extract::{Path, Query, State},
+        http::StatusCode,
+        response::IntoResponse,
+    };
+    use dotenvy::dotenv;
+    use ethers::types::Address;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::{
+        database::create_connection_pool,
+        events::EventBus,
+        services::{
+            nft::{DisplayType, NewNft, NftService},
+            project::ProjectService,
+        },
+    };
+
+    use super::{contract_metadata, token_metadata, ContractMetadataQuery};
+
+    #[tokio::test]
+    async fn test_metadata_routes() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = Arc::new(NftService::new(pool.clone(), Arc::new(EventBus::default())));
+        let project = ProjectService::new(pool).create_project("Metadata", None)?;
+        let chain = (Uuid::new_v4().as_u128() % 1_000_000) as i32;
+        let network = service.create_network(chain)?;
+        let collection = service.create_collection(&project, "Metadata", None)?;
+        let contract = service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        let nft = service
+            .create_nfts(vec![NewNft {
+                nft_id: 1,
+                name: "Metadata #1".to_string(),
+                image: "https://ethos.xyz/1.png".to_string(),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: collection.id,
+                network_contract_id: contract.id,
+            }])?
+            .remove(0);
+        let attributes = [
+            service.create_attribute(Some("Hat"), Some("cap".to_string()), None, None)?,
+            service.create_attribute(
+                Some("Level"),
+                Some("3".to_string()),
+                Some("10".to_string()),
+                Some(DisplayType::Number),
+            )?,
+        ];
+        for attribute in &attributes {
+            service.create_attribute_nft_relation(nft.id, attribute.id)?;
+        }
+
+        let Ok(metadata) = token_metadata(
+            State(service.clone()),
+            Path((chain, contract.address.to_lowercase(), nft.nft_id)),
+        )
+        .await
+        else {
+            panic!("token metadata not found");
+        };
+        assert_eq!(
+            serde_json::to_value(&metadata.0)?,
+            json!({
+                "name": "Metadata #1",
+                "description": "",
+                "image": "https://ethos.xyz/1.png",
+                "attributes": [
+                    { "trait_type": "Hat", "value": "cap" },
+                    { "trait_type": "Level", "value": 3, "display_type": "number", "max_value": 10 },
+                ],
+            })
+        );
+
+        let Err(err) = token_metadata(
+            State(service.clone()),
+            Path((chain, contract.address.clone(), 2)),
+        )
+        .await
+        else {
+            panic!("token 2 doesn't exist");
+        };
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+        // the same address on another chain is another contract
+        let Err(err) = token_metadata(
+            State(service.clone()),
+            Path((chain + 1, contract.address.clone(), nft.nft_id)),
+        )
+        .await
+        else {
+            panic!("the contract isn't deployed on another chain");
+        };
+        assert_eq!(err.into_response().status(), StatusCode::NOT_FOUND);
+
+        let Ok(metadata) = contract_metadata(
+            State(service.clone()),
+            Path(collection.id),
+            Query(ContractMetadataQuery {
+                contract: Some(contract.address.clone()),
+                chain: Some(chain),
+            }),
+        )
+        .await
+        else {
+            panic!("contract metadata not found");
+        };
+        assert_eq!(
+            serde_json::to_value(&metadata.0)?,
+            json!({
+                "name": "Metadata",
+                "fee_recipient": contract.fee_recipient,
+            })
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use async_graphql::{
+    connection::{self, CursorType, Edge},
+    OutputType, SimpleObject, ID,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::errors::EthosError;
+
+// page size when none is requested
+pub const DEFAULT_TAKE: i64 = 20;
+// largest page a client can request
+pub const MAX_TAKE: i64 = 100;
+
+/// Position of a row in a sorted list: the value of the sort key and the id
+/// breaking its ties. Clients get it as an opaque string.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor<K> {
+    pub key: K,
+    pub id: Uuid,
+}
+
+impl<K: Serialize + DeserializeOwned> Cursor<K> {
+    pub fn new(key: K, id: Uuid) -> Self {
+        Self { key, id }
+    }
+
+    pub fn encode(&self) -> ID {
+        let json = serde_json::to_vec(self).expect("cursor is serializable");
+        ID(URL_SAFE_NO_PAD.encode(json))
+    }
+
+    pub fn decode(cursor: &ID) -> Result<Self, EthosError> {
+        URL_SAFE_NO_PAD
+            .decode(cursor.as_str())
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or(EthosError::InvalidPagination("cursor is not valid"))
+    }
+}
+
+impl<K: Serialize + DeserializeOwned> CursorType for Cursor<K> {
+    type Error = EthosError;
+
+    fn decode_cursor(s: &str) -> Result<Self, Self::Error> {
+        Cursor::decode(&ID::from(s))
+    }
+
+    fn encode_cursor(&self) -> String {
+        self.encode().0
+    }
+}
+
+/// Fields every connection has besides its edges and `pageInfo`
+#[derive(SimpleObject)]
+pub struct ConnectionFields {
+    /// rows matching the query, in every page
+    pub total_count: i64,
+}
+
+/// Relay connection of the rows sorted by `K`
+pub type Connection<K, T> = connection::Connection<Cursor<K>, T, ConnectionFields>;
+
+/// Which side of the cursor a page is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// rows after the cursor
+    Forward,
+    /// rows before the cursor, for paging back
+    Backward,
+}
+
+/// Rows requested from a sorted list
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageRequest<K> {
+    pub direction: Direction,
+    pub cursor: Option<Cursor<K>>,
+    /// size of the page
+    pub take: i64,
+}
+
+impl<K> PageRequest<K> {
+    /// Page requested with the Relay arguments, `first` rows after `after` or
+    /// `last` rows before `before`
+    pub fn new(
+        after: Option<Cursor<K>>,
+        before: Option<Cursor<K>>,
+        first: Option<usize>,
+        last: Option<usize>,
+    ) -> Result<Self, EthosError> {
+        let size = |size: Option<usize>| take(size.map(|size| size.min(MAX_TAKE as usize) as i32));
+        match (after, before, first, last) {
+            (Some(_), Some(_), _, _) => Err(EthosError::InvalidPagination(
+                "after and before can't be used together",
+            )),
+            (_, _, Some(_), Some(_)) => Err(EthosError::InvalidPagination(
+                "first and last can't be used together",
+            )),
+            (after, None, first, None) => Ok(Self {
+                direction: Direction::Forward,
+                cursor: after,
+                take: size(first),
+            }),
+            (None, before, None, last) => Ok(Self {
+                direction: Direction::Backward,
+                cursor: before,
+                take: size(last),
+            }),
+            _ => Err(EthosError::InvalidPagination(
+                "first pages after a cursor and last before it",
+            )),
+        }
+    }
+
+    /// Whether the rows are read against the sort, to be reversed by [`PageRequest::page`]
+    pub fn is_backward(&self) -> bool {
+        self.direction == Direction::Backward
+    }
+
+    /// Builds the page from the rows read from the cursor, which are one more
+    /// than `take` when the list goes on
+    pub fn page<T>(&self, mut rows: Vec<T>, total_count: i64) -> Page<T> {
+        let has_more = rows.len() as i64 > self.take;
+        rows.truncate(self.take as usize);
+        let (has_next_page, has_previous_page) = match self.direction {
+            Direction::Forward => (has_more, self.cursor.is_some()),
+            Direction::Backward => {
+                rows.reverse();
+                (self.cursor.is_some(), has_more)
+            }
+        };
+        Page {
+            rows,
+            total_count,
+            has_next_page,
+            has_previous_page,
+        }
+    }
+}
+
+/// Page requested with `cursor` (after) or `before`, never both
+pub fn page_args<K: Serialize + DeserializeOwned>(
+    after: Option<&ID>,
+    before: Option<&ID>,
+    size: Option<i32>,
+) -> Result<PageRequest<K>, EthosError> {
+    let (direction, cursor) = match (after, before) {
+        (Some(_), Some(_)) => {
+            return Err(EthosError::InvalidPagination(
+                "cursor and before can't be used together",
+            ))
+        }
+        (Some(after), None) => (Direction::Forward, Some(Cursor::decode(after)?)),
+        (None, Some(before)) => (Direction::Backward, Some(Cursor::decode(before)?)),
+        (None, None) => (Direction::Forward, None),
+    };
+    Ok(PageRequest {
+        direction,
+        cursor,
+        take: take(size),
+    })
+}
+
+/// Page size requested by the client, capped to [`MAX_TAKE`]
+pub fn take(take: Option<i32>) -> i64 {
+    take.map(i64::from)
+        .unwrap_or(DEFAULT_TAKE)
+        .clamp(1, MAX_TAKE)
+}
+
+/// Rows of a page, in the order of the sort
+#[derive(Debug)]
+pub struct Page<T> {
+    pub rows: Vec<T>,
+    pub total_count: i64,
+    pub has_next_page: bool,
+    pub has_previous_page: bool,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            rows: self.rows.into_iter().map(f).collect(),
+            total_count: self.total_count,
+            has_next_page: self.has_next_page,
+            has_previous_page: self.has_previous_page,
+        }
+    }
+}
+
+impl<T: OutputType> Page<T> {
+    pub fn into_connection<K>(self, cursor_of: impl Fn(&T) -> Cursor<K>) -> Connection<K, T>
+    where
+        K: Serialize + DeserializeOwned + Send + Sync,
+    {
+        let mut connection = Connection::with_additional_fields(
+            self.has_previous_page,
+            self.has_next_page,
+            ConnectionFields {
+                total_count: self.total_count,
+            },
+        );
+        connection.edges = self
+            .rows
+            .into_iter()
+            .map(|row| Edge::new(cursor_of(&row), row))
+            .collect();
+        connection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_graphql::ID;
+    use uuid::Uuid;
+
+    use super::{page_args, take, Cursor, Direction, PageRequest, MAX_TAKE};
+
+    #[test]
+    fn test_cursor() {
+        let cursor = Cursor::new(Some(42), Uuid::new_v4());
+        let encoded = cursor.encode();
+        assert_eq!(Cursor::<Option<i32>>::decode(&encoded).unwrap(), cursor);
+        assert!(Cursor::<Option<i32>>::decode(&ID::from("not a cursor")).is_err());
+
+        let request = page_args::<Option<i32>>(None, Some(&encoded), None).unwrap();
+        assert_eq!(request.direction, Direction::Backward);
+        assert_eq!(request.cursor, Some(cursor));
+        assert!(page_args::<Option<i32>>(Some(&encoded), Some(&encoded), None).is_err());
+
+        assert_eq!(take(Some(1000)), MAX_TAKE);
+        assert_eq!(take(Some(0)), 1);
+    }
+
+    #[test]
+    fn test_page_request() {
+        let cursor = || Some(Cursor::new(1, Uuid::new_v4()));
+        let request = PageRequest::<i32>::new(None, None, None, None).unwrap();
+        assert_eq!(request.direction, Direction::Forward);
+        assert_eq!(request.take, 20);
+        let request = PageRequest::new(None, cursor(), None, Some(1000)).unwrap();
+        assert!(request.is_backward());
+        assert_eq!(request.take, MAX_TAKE);
+        assert!(PageRequest::new(cursor(), cursor(), None, None).is_err());
+        assert!(PageRequest::new(None, cursor(), Some(2), None).is_err());
+        assert!(PageRequest::new(cursor(), None, None, Some(2)).is_err());
+
+        // the extra row tells there's a next page
+        let request = PageRequest::new(cursor(), None, Some(2), None).unwrap();
+        let page = request.page(vec![1, 2, 3], 10);
+        assert_eq!(page.rows, vec![1, 2]);
+        assert!(page.has_next_page && page.has_previous_page);
+
+        // rows read backwards are returned in the order of the sort
+        let request = PageRequest::new(None, cursor(), None, Some(2)).unwrap();
+        let page = request.page(vec![3, 2, 1], 10);
+        assert_eq!(page.rows, vec![2, 3]);
+        assert!(page.has_next_page && page.has_previous_page);
+        let page = request.page(vec![3, 2], 10);
+        assert!(!page.has_previous_page);
+    }
+}
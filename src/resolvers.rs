@@ -1,5 +1,21 @@
+use crate::events::{Event, EventBus};
+use crate::guards::has_role::HasRole;
 use crate::guards::with_project::WithProject;
-use crate::services::nft::{FilterNFTsInput, PaginatedNFTs};
+use crate::pagination::{Connection, Cursor, PageRequest};
+use crate::services::benefit::{
+    Benefit, BenefitService, CreateBenefitInput, NftBenefit, RedeemBenefitInput,
+};
+use crate::services::email::{CreateEmailTemplateInput, EmailService, EmailTemplate};
+use crate::services::nft::{
+    AttributeSortKey, FilterNFTInput, FilterNFTsInput, Nft, NftAttribute, OnTransferInput,
+    PaginatedNFTs,
+};
+use crate::services::project::{Role, UpdateAdminsProject, UpdateProjectInput};
+use crate::services::random::{RandomRequest, RandomRequestService, RedeemTicketResponse};
+use crate::services::reveal::{CollectionReveal, SetRevealInput};
+use crate::services::ticket::{
+    AssignTicketInput, AssignTicketResponse, Ticket, TicketService, TicketsResponse,
+};
 use crate::{
     guards::is_authenticated::IsAuthenticated,
     services::{
@@ -7,8 +23,9 @@ use crate::{
         profile::UpdateProfileInput,
     },
 };
-use async_graphql::{Context, Object};
-use ethers::types::Address;
+use async_graphql::futures_util::{Stream, StreamExt};
+use async_graphql::{connection, Context, ErrorExtensions, Object, Subscription};
+use ethers::{types::Address, utils::to_checksum};
 use std::{str::FromStr, sync::Arc};
 use uuid::Uuid;
 
@@ -37,16 +54,60 @@ impl QueryRoot {
         service.get_profile(wallet)
     }
 
+    /// returns the collections of the current project, oldest first
     #[graphql(guard = "WithProject")]
-    async fn collections<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<Collection>, EthosError> {
-        let service = ctx.data::<NftService>().unwrap();
+    async fn collections<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<chrono::NaiveDateTime, Collection>> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
         let project = ctx.data::<Project>().unwrap();
-        service.get_collections(project)
+        connection::query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let request = PageRequest::new(after, before, first, last)?;
+                let page = service.get_collections(project, &request)?;
+                Ok::<_, EthosError>(page.into_connection(|collection| {
+                    Cursor::new(collection.created_at, collection.id)
+                }))
+            },
+        )
+        .await
     }
 
-    async fn projects<'ctx>(&self, ctx: &Context<'ctx>) -> Result<Vec<Project>, EthosError> {
+    /// returns the projects the current wallet is an admin of, oldest first
+    #[graphql(guard = "IsAuthenticated")]
+    async fn projects<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<chrono::NaiveDateTime, Project>> {
+        let wallet = ctx.data_unchecked::<Wallet>();
         let service = ctx.data::<Arc<ProjectService>>().unwrap();
-        service.get_projects()
+        connection::query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let request = PageRequest::new(after, before, first, last)?;
+                let page = service.get_projects(wallet, &request)?;
+                Ok::<_, EthosError>(
+                    page.into_connection(|project| Cursor::new(project.created_at, project.id)),
+                )
+            },
+        )
+        .await
     }
 
     async fn collection<'ctx>(
@@ -54,32 +115,160 @@ impl QueryRoot {
         ctx: &Context<'ctx>,
         id: Uuid,
     ) -> Result<Collection, EthosError> {
-        let service = ctx.data::<NftService>().unwrap();
+        let service = ctx.data::<Arc<NftService>>().unwrap();
         service.get_collection(id)
     }
 
+    /// returns the attributes of the NFTs of a collection of the current
+    /// project, sorted by trait and value
+    #[graphql(guard = "WithProject")]
+    async fn attributes<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        collection_id: Uuid,
+        after: Option<String>,
+        before: Option<String>,
+        first: Option<i32>,
+        last: Option<i32>,
+    ) -> async_graphql::Result<Connection<AttributeSortKey, NftAttribute>> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        let project = ctx.data::<Project>().unwrap();
+        connection::query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let request = PageRequest::new(after, before, first, last)?;
+                let page = service.get_collection_attributes(project, collection_id, &request)?;
+                Ok::<_, EthosError>(
+                    page.into_connection(|attribute| {
+                        Cursor::new(attribute.sort_key(), attribute.id)
+                    }),
+                )
+            },
+        )
+        .await
+    }
+
+    async fn nft<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        input: FilterNFTInput,
+    ) -> Result<Nft, EthosError> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        service.find_nft(input)
+    }
+
     async fn nfts<'ctx>(
         &self,
         ctx: &Context<'ctx>,
         input: FilterNFTsInput,
     ) -> Result<PaginatedNFTs, EthosError> {
-        let service = ctx.data::<NftService>().unwrap();
+        let service = ctx.data::<Arc<NftService>>().unwrap();
         service.get_nfts(input)
     }
+
+    /// returns the benefits of the collections of the current project
+    #[graphql(guard = "WithProject")]
+    async fn benefits<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(default = 0, validator(minimum = 0))] page: i32,
+        #[graphql(default = 20, validator(minimum = 1, maximum = 100))] limit: i32,
+    ) -> Result<Vec<Benefit>, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<BenefitService>>().unwrap();
+        service.get_benefits(project, page, limit)
+    }
+
+    /// returns the tickets of an order
+    #[graphql(guard = "IsAuthenticated.and(WithProject)")]
+    async fn tickets<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        token: String,
+    ) -> Result<TicketsResponse, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<TicketService>>().unwrap();
+        service.get_tickets(project, &token)
+    }
+
+    #[graphql(guard = "WithProject")]
+    async fn email_has_ticket<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        email: String,
+    ) -> Result<bool, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<TicketService>>().unwrap();
+        service.email_has_ticket(project, &email)
+    }
 }
 
 pub struct MutationRoot;
 
 #[Object]
 impl MutationRoot {
+    /// Creates a project, the authenticated wallet becomes its first admin
+    #[graphql(guard = "IsAuthenticated")]
     async fn create_project<'ctx>(
         &self,
         ctx: &Context<'ctx>,
         name: String,
         description: Option<String>,
     ) -> Result<Project, EthosError> {
+        let wallet = ctx.data_unchecked::<Wallet>();
         let service = ctx.data::<Arc<ProjectService>>().unwrap();
-        service.create_project(&name, description)
+        service.create_project_with_admin(wallet, &name, description)
+    }
+
+    /// Updates a project the wallet is an admin of
+    #[graphql(guard = "HasRole::new(Role::Admin).project(input.project_id)")]
+    async fn update_project<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        input: UpdateProjectInput,
+    ) -> Result<Project, EthosError> {
+        let service = ctx.data::<Arc<ProjectService>>().unwrap();
+        let project = service.get_project(input.project_id)?;
+        service.update_project(&project, input)
+    }
+
+    /// Promotes and downgrades admins of the current project, returning the admins
+    #[graphql(guard = "HasRole::new(Role::Admin)")]
+    async fn update_project_admins<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        input: UpdateAdminsProject,
+    ) -> Result<Vec<Wallet>, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<ProjectService>>().unwrap();
+        service.update_admins(project, input)
+    }
+
+    /// Creates a benefit for every NFT of a collection of the current project
+    #[graphql(guard = "HasRole::new(Role::Admin)")]
+    async fn create_benefit<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        input: CreateBenefitInput,
+    ) -> Result<Benefit, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<BenefitService>>().unwrap();
+        service.create_benefit(project, input)
+    }
+
+    /// Redeems a benefit of an NFT, `sig` must be signed by the owner of the NFT
+    #[graphql(guard = "WithProject")]
+    async fn redeem_benefit<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        input: RedeemBenefitInput,
+    ) -> Result<NftBenefit, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<BenefitService>>().unwrap();
+        service.redeem_benefit(project, input)
     }
 
     #[graphql(guard = "IsAuthenticated")]
@@ -94,6 +283,128 @@ impl MutationRoot {
         Ok(profile)
     }
 
+    /// Redeems the ticket with code `token`, requesting the random number that
+    /// draws its NFT
+    #[graphql(guard = "IsAuthenticated.and(WithProject)")]
+    async fn redeem_ticket<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        token: String,
+    ) -> Result<RedeemTicketResponse, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let wallet = ctx.data_unchecked::<Wallet>();
+        let service = ctx.data::<Arc<RandomRequestService>>().unwrap();
+        service.redeem_ticket(project, wallet, &token).await
+    }
+
+    /// Using requestId unpack the nft and send to user wallet
+    #[graphql(guard = "IsAuthenticated")]
+    async fn unpack<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        request_id: String,
+        tier: i32,
+    ) -> Result<Nft, EthosError> {
+        let wallet = ctx.data_unchecked::<Wallet>();
+        let service = ctx.data::<Arc<RandomRequestService>>().unwrap();
+        service.unpack(wallet, &request_id, tier)
+    }
+
+    /// mutation for assign ticket to user
+    #[graphql(guard = "IsAuthenticated.and(WithProject)")]
+    async fn assign_ticket<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        token: String,
+        tickets_to_assign: Vec<AssignTicketInput>,
+    ) -> Result<AssignTicketResponse, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<TicketService>>().unwrap();
+        service.assign_tickets(project, &token, tickets_to_assign)
+    }
+
+    /// Sends again the ticket codes of every order of the email. Returns false
+    /// when the email has no tickets.
+    #[graphql(guard = "WithProject")]
+    async fn resend_ticket_email<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(validator(email))] email: String,
+    ) -> Result<bool, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<EmailService>>().unwrap();
+        service.resend_ticket_email(project, &email).await
+    }
+
+    /// Sets the delayed reveal of a collection of the current project, until
+    /// its provenance is committed
+    #[graphql(guard = "HasRole::new(Role::Admin)")]
+    async fn set_collection_reveal<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        input: SetRevealInput,
+    ) -> Result<CollectionReveal, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        service.set_reveal(project, input)
+    }
+
+    /// Commits the provenance hash of the final metadata of a collection of
+    /// the current project. Its NFTs aren't revealed before it.
+    #[graphql(guard = "HasRole::new(Role::Admin)")]
+    async fn commit_collection_provenance<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        collection_id: Uuid,
+    ) -> Result<CollectionReveal, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        service.commit_provenance(project, collection_id)
+    }
+
+    /// Creates an email template, replacing the one with the same templateId
+    #[graphql(guard = "HasRole::new(Role::Admin)")]
+    async fn create_email_template<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        input: CreateEmailTemplateInput,
+    ) -> Result<EmailTemplate, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<EmailService>>().unwrap();
+        service.create_template(project, input)
+    }
+
+    /// Sends the template to every ticket of the events, returning the tickets
+    #[graphql(guard = "HasRole::new(Role::Admin)")]
+    async fn send_template_email_to_tickets<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        events: Vec<String>,
+        template_id: String,
+    ) -> Result<Vec<Ticket>, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<EmailService>>().unwrap();
+        service
+            .send_template_email_to_tickets(project, &events, &template_id)
+            .await
+    }
+
+    /// Create tickets for a specific email.
+    /// Work only in development environment
+    #[graphql(guard = "WithProject")]
+    async fn create_tickets<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(validator(email))] email: String,
+        #[graphql(validator(minimum = 1, maximum = 100))] quantity: i32,
+        tier: Option<i32>,
+        name: Option<String>,
+    ) -> Result<Vec<Ticket>, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<TicketService>>().unwrap();
+        service.create_tickets(project, &email, quantity, tier, name)
+    }
+
     async fn wallet<'ctx>(
         &self,
         ctx: &Context<'ctx>,
@@ -104,14 +415,110 @@ impl MutationRoot {
         service.upsert_wallet(address)
     }
 
-    async fn login<'ctx>(
+    /// Returns the EIP-4361 message the wallet has to sign to log into the current project
+    #[graphql(guard = "WithProject")]
+    async fn login_message<'ctx>(
         &self,
         ctx: &Context<'ctx>,
         address: String,
+        chain_id: i32,
+    ) -> Result<String, EthosError> {
+        let address = Address::from_str(&address)?;
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<AuthService>>().unwrap();
+        service.create_login_message(project, address, chain_id)
+    }
+
+    #[graphql(guard = "WithProject")]
+    async fn login<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        message: String,
         signature: String,
     ) -> Result<LoginResponse, EthosError> {
-        let address = Address::from_str(&address)?;
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<AuthService>>().unwrap();
+        service.login(project, &message, signature).await
+    }
+
+    /// Rotates the refresh token and returns a new access token
+    #[graphql(guard = "WithProject")]
+    async fn refresh_token<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        refresh_token: String,
+    ) -> Result<LoginResponse, EthosError> {
+        let project = ctx.data_unchecked::<Project>();
+        let service = ctx.data::<Arc<AuthService>>().unwrap();
+        service.refresh(project, &refresh_token)
+    }
+
+    /// Revokes the session of the refresh token
+    async fn logout<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        refresh_token: String,
+    ) -> Result<bool, EthosError> {
         let service = ctx.data::<Arc<AuthService>>().unwrap();
-        service.login(address, signature).await
+        service.logout(&refresh_token)
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// returns the nft when backend detects a transfer to a wallet, or every
+    /// transfer without `input`
+    async fn on_transfer<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        input: Option<OnTransferInput>,
+    ) -> Result<impl Stream<Item = async_graphql::Result<Nft>>, EthosError> {
+        let (address, nft_id) = match input {
+            Some(input) => (
+                Some(to_checksum(&Address::from_str(&input.address)?, None)),
+                input.nft_id,
+            ),
+            None => (None, None),
+        };
+        let service = ctx.data::<Arc<NftService>>().unwrap().clone();
+        let events = ctx.data::<Arc<EventBus>>().unwrap();
+
+        Ok(events.subscribe().filter_map(move |event| {
+            let result = match event {
+                Event::Transfer(transfer)
+                    if address
+                        .as_ref()
+                        .is_none_or(|address| transfer.to_address == *address)
+                        && nft_id.is_none_or(|id| id == transfer.nft_id) =>
+                {
+                    Some(service.get_nft(transfer.nft_id).map_err(|err| err.extend()))
+                }
+                _ => None,
+            };
+            async move { result }
+        }))
+    }
+
+    async fn on_request_fulfilled<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        request_id: String,
+    ) -> impl Stream<Item = async_graphql::Result<RandomRequest>> {
+        let service = ctx.data::<Arc<RandomRequestService>>().unwrap().clone();
+        let events = ctx.data::<Arc<EventBus>>().unwrap();
+
+        events.subscribe().filter_map(move |event| {
+            let result = match event {
+                Event::RequestFulfilled(fulfilled) if fulfilled.request_id == request_id => Some(
+                    service
+                        .get_random_request(&request_id)
+                        .map_err(|err| err.extend()),
+                ),
+                _ => None,
+            };
+            async move { result }
+        })
     }
 }
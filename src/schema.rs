@@ -4,6 +4,34 @@ pub mod sql_types {
     #[derive(diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "display_type"))]
     pub struct DisplayType;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "email_state"))]
+    pub struct EmailState;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "job_run_state"))]
+    pub struct JobRunState;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "mint_job_state"))]
+    pub struct MintJobState;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "project_role"))]
+    pub struct ProjectRole;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "reveal_mode"))]
+    pub struct RevealMode;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "ticket_purpose"))]
+    pub struct TicketPurpose;
+
+    #[derive(diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "ticket_state"))]
+    pub struct TicketState;
 }
 
 diesel::table! {
@@ -13,6 +41,19 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    benefits (id) {
+        id -> Uuid,
+        name -> Varchar,
+        description -> Text,
+        image -> Varchar,
+        expires_at -> Nullable<Timestamp>,
+        collection_id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     collection_contracts (id) {
         id -> Uuid,
@@ -21,6 +62,26 @@ diesel::table! {
         fee_recipient -> Varchar,
         collection_id -> Uuid,
         network_id -> Uuid,
+        deployed_at_block -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::RevealMode;
+
+    collection_reveals (collection_id) {
+        collection_id -> Uuid,
+        mode -> RevealMode,
+        reveal_at -> Nullable<Timestamp>,
+        placeholder_name -> Varchar,
+        placeholder_description -> Text,
+        placeholder_image -> Varchar,
+        placeholder_animation_url -> Varchar,
+        provenance_hash -> Nullable<Varchar>,
+        committed_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -35,6 +96,85 @@ diesel::table! {
         project_id -> Uuid,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        tier_trait -> Varchar,
+    }
+}
+
+diesel::table! {
+    contract_checkpoints (collection_contract_id) {
+        collection_contract_id -> Uuid,
+        block_number -> Int8,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    email_templates (id) {
+        id -> Uuid,
+        template_id -> Varchar,
+        subject -> Varchar,
+        body -> Text,
+        project_id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::EmailState;
+
+    emails (id) {
+        id -> Uuid,
+        state -> EmailState,
+        recipient -> Varchar,
+        subject -> Varchar,
+        body -> Text,
+        attempts -> Int4,
+        error -> Nullable<Text>,
+        next_attempt_at -> Timestamp,
+        sent_at -> Nullable<Timestamp>,
+        project_id -> Uuid,
+        ticket_id -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JobRunState;
+
+    job_runs (id) {
+        id -> Uuid,
+        job -> Varchar,
+        state -> JobRunState,
+        scheduled_at -> Timestamp,
+        output -> Nullable<Text>,
+        error -> Nullable<Text>,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MintJobState;
+
+    mint_jobs (id) {
+        id -> Uuid,
+        state -> MintJobState,
+        nonce -> Nullable<Int8>,
+        tx_hashes -> Array<Nullable<Text>>,
+        gas_price -> Nullable<Varchar>,
+        attempts -> Int4,
+        error -> Nullable<Text>,
+        submitted_at -> Nullable<Timestamp>,
+        confirmed_at -> Nullable<Timestamp>,
+        nft_id -> Uuid,
+        wallet_id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -42,6 +182,7 @@ diesel::table! {
     networks (id) {
         id -> Uuid,
         chain_id -> Int4,
+        rpc_url -> Nullable<Varchar>,
     }
 }
 
@@ -58,6 +199,31 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    nft_benefits (id) {
+        id -> Uuid,
+        cupom_code -> Nullable<Varchar>,
+        available -> Bool,
+        expires_at -> Nullable<Timestamp>,
+        redeemed_at -> Nullable<Timestamp>,
+        benefit_id -> Uuid,
+        nft_id -> Uuid,
+        redeemed_by -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    nft_rarities (nft_id) {
+        nft_id -> Uuid,
+        collection_id -> Uuid,
+        rarity_score -> Float8,
+        rarity_rank -> Int4,
+        statistical_rarity -> Float8,
+        statistical_rank -> Int4,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     nfts (id) {
         id -> Uuid,
@@ -83,6 +249,18 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ProjectRole;
+
+    project_members (project_id, wallet_id) {
+        project_id -> Uuid,
+        wallet_id -> Uuid,
+        role -> ProjectRole,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     projects (id) {
         id -> Uuid,
@@ -95,6 +273,75 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    random_requests (id) {
+        id -> Uuid,
+        request_id -> Varchar,
+        tier -> Int4,
+        fulfilled -> Bool,
+        fulfilled_at -> Nullable<Timestamp>,
+        random_number -> Nullable<Varchar>,
+        proof -> Nullable<Text>,
+        candidates -> Nullable<Int4>,
+        ticket_id -> Uuid,
+        wallet_id -> Uuid,
+        nft_id -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Uuid,
+        token_hash -> Varchar,
+        family_id -> Uuid,
+        wallet_id -> Uuid,
+        project_id -> Uuid,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        replaced_by -> Nullable<Uuid>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::TicketPurpose;
+    use super::sql_types::TicketState;
+
+    tickets (id) {
+        id -> Uuid,
+        token -> Varchar,
+        code -> Varchar,
+        email -> Varchar,
+        name -> Varchar,
+        purpose -> TicketPurpose,
+        tier -> Int4,
+        state -> TicketState,
+        event -> Nullable<Varchar>,
+        expires_at -> Nullable<Timestamp>,
+        project_id -> Uuid,
+        wallet_id -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        emailed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    transfers (id) {
+        id -> Uuid,
+        nft_id -> Uuid,
+        from_id -> Nullable<Uuid>,
+        to_id -> Uuid,
+        created_at -> Timestamp,
+        block_number -> Nullable<Int8>,
+        tx_hash -> Nullable<Varchar>,
+        log_index -> Nullable<Int8>,
+    }
+}
+
 diesel::table! {
     wallets (id) {
         id -> Uuid,
@@ -107,22 +354,59 @@ diesel::table! {
 
 diesel::joinable!(attributes_on_nfts -> nft_attributes (attribute_id));
 diesel::joinable!(attributes_on_nfts -> nfts (nft_id));
+diesel::joinable!(benefits -> collections (collection_id));
 diesel::joinable!(collection_contracts -> collections (collection_id));
 diesel::joinable!(collection_contracts -> networks (network_id));
+diesel::joinable!(collection_reveals -> collections (collection_id));
 diesel::joinable!(collections -> projects (project_id));
+diesel::joinable!(contract_checkpoints -> collection_contracts (collection_contract_id));
+diesel::joinable!(email_templates -> projects (project_id));
+diesel::joinable!(emails -> projects (project_id));
+diesel::joinable!(emails -> tickets (ticket_id));
+diesel::joinable!(mint_jobs -> nfts (nft_id));
+diesel::joinable!(mint_jobs -> wallets (wallet_id));
+diesel::joinable!(nft_benefits -> benefits (benefit_id));
+diesel::joinable!(nft_benefits -> nfts (nft_id));
+diesel::joinable!(nft_benefits -> wallets (redeemed_by));
+diesel::joinable!(nft_rarities -> collections (collection_id));
+diesel::joinable!(nft_rarities -> nfts (nft_id));
 diesel::joinable!(nfts -> collection_contracts (network_contract_id));
 diesel::joinable!(nfts -> collections (collection_id));
 diesel::joinable!(nfts -> wallets (owner_id));
 diesel::joinable!(profiles -> wallets (wallet_id));
+diesel::joinable!(project_members -> projects (project_id));
+diesel::joinable!(project_members -> wallets (wallet_id));
+diesel::joinable!(random_requests -> nfts (nft_id));
+diesel::joinable!(random_requests -> tickets (ticket_id));
+diesel::joinable!(random_requests -> wallets (wallet_id));
+diesel::joinable!(refresh_tokens -> projects (project_id));
+diesel::joinable!(refresh_tokens -> wallets (wallet_id));
+diesel::joinable!(tickets -> projects (project_id));
+diesel::joinable!(tickets -> wallets (wallet_id));
+diesel::joinable!(transfers -> nfts (nft_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     attributes_on_nfts,
+    benefits,
     collection_contracts,
+    collection_reveals,
     collections,
+    contract_checkpoints,
+    email_templates,
+    emails,
+    job_runs,
+    mint_jobs,
     networks,
     nft_attributes,
+    nft_benefits,
+    nft_rarities,
     nfts,
     profiles,
+    project_members,
     projects,
+    random_requests,
+    refresh_tokens,
+    tickets,
+    transfers,
     wallets,
 );
@@ -1,5 +1,15 @@
 pub mod auth;
+pub mod benefit;
+pub mod email;
+pub mod export;
+pub mod import;
+pub mod indexer;
+pub mod mint;
 pub mod nft;
 pub mod profile;
 pub mod project;
+pub mod random;
+pub mod rarity;
+pub mod reveal;
+pub mod ticket;
 pub mod wallet;
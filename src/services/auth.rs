@@ -1,44 +1,361 @@
-use std::sync::Arc;
+use std::{env, str::FromStr, sync::Arc};
 
 use async_graphql::SimpleObject;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{prelude::*, r2d2::ConnectionManager};
+use ethers::utils::{hex, keccak256};
+use r2d2::Pool;
+use uuid::Uuid;
 
-use crate::{errors::EthosError, jwt::JwtAuthentication};
+use crate::{
+    database::ConnectionPool,
+    errors::EthosError,
+    jwt::JwtAuthentication,
+    schema::refresh_tokens,
+    siwe::{domain_from_url, SiweMessage, VERSION},
+};
 use ethers::types::Address;
 
-use super::wallet::{Wallet, WalletService};
+use super::{
+    nft::NftService,
+    project::Project,
+    wallet::{Wallet, WalletService},
+};
+
+const LOGIN_STATEMENT: &str = "Click to sign in and accept the Terms of Service. \
+    This request will not trigger a blockchain transaction or cost any gas fees. \
+    Your authentication status will reset after 24 hours.";
+// time the user has to sign the login message
+const LOGIN_MESSAGE_TTL_MINUTES: i64 = 10;
+// a session can be refreshed until this many seconds after the login
+const DEFAULT_REFRESH_TOKEN_TTL_SECONDS: i64 = 24 * 60 * 60;
 
 #[derive(Debug, SimpleObject)]
 pub struct LoginResponse {
     token: String,
+    expires_at: NaiveDateTime,
+    refresh_token: String,
     wallet: Wallet,
 }
 
+#[derive(Debug, Queryable, Identifiable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub token_hash: String,
+    pub family_id: Uuid,
+    pub wallet_id: Uuid,
+    pub project_id: Uuid,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub replaced_by: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = refresh_tokens)]
+struct NewRefreshToken {
+    token_hash: String,
+    family_id: Uuid,
+    wallet_id: Uuid,
+    project_id: Uuid,
+    expires_at: NaiveDateTime,
+}
+
 pub struct AuthService {
+    pool: ConnectionPool,
     wallet_service: Arc<WalletService>,
+    nft_service: Arc<NftService>,
     jwt_auth: JwtAuthentication,
+    refresh_token_ttl: Duration,
 }
 impl AuthService {
-    pub fn new(wallet_service: Arc<WalletService>) -> Self {
+    pub fn new(
+        pool: Pool<ConnectionManager<PgConnection>>,
+        wallet_service: Arc<WalletService>,
+        nft_service: Arc<NftService>,
+    ) -> Self {
+        let refresh_token_ttl = env::var("JWT_REFRESH_TOKEN_TTL")
+            .ok()
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(DEFAULT_REFRESH_TOKEN_TTL_SECONDS);
         AuthService {
+            pool: ConnectionPool::new(pool),
             wallet_service,
+            nft_service,
             jwt_auth: JwtAuthentication::new(),
+            refresh_token_ttl: Duration::seconds(refresh_token_ttl),
         }
     }
-    pub async fn login(
+
+    /// Issues the EIP-4361 message the wallet has to sign to log into `project`
+    pub fn create_login_message(
         &self,
+        project: &Project,
         addr: Address,
+        chain_id: i32,
+    ) -> Result<String, EthosError> {
+        let url = project_url(project)?;
+        self.check_chain(chain_id as u64)?;
+        let wallet = self.wallet_service.upsert_wallet(addr)?;
+
+        let issued_at = Utc::now();
+        let message = SiweMessage {
+            domain: domain_from_url(url)?,
+            address: addr,
+            statement: Some(LOGIN_STATEMENT.to_string()),
+            uri: url.to_string(),
+            version: VERSION.to_string(),
+            chain_id: chain_id as u64,
+            nonce: wallet.login_nonce(),
+            issued_at,
+            expiration_time: Some(issued_at + Duration::minutes(LOGIN_MESSAGE_TTL_MINUTES)),
+            not_before: None,
+            request_id: None,
+            resources: vec![project_resource(project)],
+        };
+        Ok(message.to_string())
+    }
+
+    pub async fn login(
+        &self,
+        project: &Project,
+        message: &str,
         signature: String,
     ) -> Result<LoginResponse, EthosError> {
-        let wallet = self.wallet_service.get_wallet(&addr)?;
+        let url = project_url(project)?;
+        let parsed = SiweMessage::from_str(message)?;
+        parsed.validate(&domain_from_url(url)?, Utc::now())?;
+        if parsed.uri != url {
+            return Err(EthosError::InvalidLoginMessage(format!(
+                "uri `{}` does not match the project url",
+                parsed.uri
+            )));
+        }
+        if !parsed.resources.contains(&project_resource(project)) {
+            return Err(EthosError::InvalidLoginMessage(
+                "message was not issued for this project".to_string(),
+            ));
+        }
+        self.check_chain(parsed.chain_id)?;
+
+        let wallet = self.wallet_service.get_wallet(&parsed.address)?;
         let wallet = self
             .wallet_service
-            .verify_and_update_nonce(&wallet, signature)
+            .verify_and_update_nonce(&wallet, message, signature)
             .await?;
-        let token = self.jwt_auth.create_token(&wallet)?;
-        Ok(LoginResponse { token, wallet })
+        self.create_session(wallet, project)
     }
 
-    pub async fn validate(&self, token: &str) -> Result<Wallet, EthosError> {
-        Ok(self.jwt_auth.validate(token)?)
+    /// Starts a new refresh token family, valid for the whole session
+    fn create_session(
+        &self,
+        wallet: Wallet,
+        project: &Project,
+    ) -> Result<LoginResponse, EthosError> {
+        let mut conn = self.pool.get()?;
+        let (refresh_token, token_hash) = generate_refresh_token();
+        diesel::insert_into(refresh_tokens::table)
+            .values(&NewRefreshToken {
+                token_hash,
+                family_id: Uuid::new_v4(),
+                wallet_id: wallet.id,
+                project_id: project.id,
+                expires_at: Utc::now().naive_utc() + self.refresh_token_ttl,
+            })
+            .execute(&mut conn)?;
+        self.login_response(wallet, project, refresh_token)
+    }
+
+    /// Exchanges a refresh token for a new access token and refresh token.
+    ///
+    /// Refresh tokens can be used only once. Presenting a token that was already
+    /// rotated means it leaked, so the whole family is revoked.
+    pub fn refresh(
+        &self,
+        project: &Project,
+        refresh_token: &str,
+    ) -> Result<LoginResponse, EthosError> {
+        use crate::schema::refresh_tokens::dsl::*;
+        let mut conn = self.pool.get()?;
+        let now = Utc::now().naive_utc();
+
+        let result = conn.transaction::<_, EthosError, _>(|conn| {
+            let current = refresh_tokens
+                .filter(token_hash.eq(hash_token(refresh_token)))
+                .for_update()
+                .first::<RefreshToken>(conn)
+                .optional()?
+                .ok_or(EthosError::InvalidRefreshToken)?;
+
+            if current.revoked_at.is_some() {
+                diesel::update(refresh_tokens)
+                    .filter(family_id.eq(current.family_id))
+                    .filter(revoked_at.is_null())
+                    .set(revoked_at.eq(now))
+                    .execute(conn)?;
+                return Ok(Err(EthosError::RefreshTokenReused));
+            }
+            if current.expires_at <= now || current.project_id != project.id {
+                return Err(EthosError::InvalidRefreshToken);
+            }
+
+            let (new_token, new_hash) = generate_refresh_token();
+            let rotated = diesel::insert_into(refresh_tokens)
+                .values(&NewRefreshToken {
+                    token_hash: new_hash,
+                    family_id: current.family_id,
+                    wallet_id: current.wallet_id,
+                    project_id: current.project_id,
+                    // rotation doesn't extend the session
+                    expires_at: current.expires_at,
+                })
+                .get_result::<RefreshToken>(conn)?;
+            diesel::update(&current)
+                .set((revoked_at.eq(now), replaced_by.eq(rotated.id)))
+                .execute(conn)?;
+            Ok(Ok((current.wallet_id, new_token)))
+        })?;
+
+        // the revocation of a reused family must be committed before failing
+        let (wallet, new_token) = result?;
+        let wallet = self.wallet_service.get_wallet_by_id(wallet)?;
+        self.login_response(wallet, project, new_token)
+    }
+
+    /// Revokes the session the refresh token belongs to
+    pub fn logout(&self, refresh_token: &str) -> Result<bool, EthosError> {
+        use crate::schema::refresh_tokens::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let current = refresh_tokens
+            .filter(token_hash.eq(hash_token(refresh_token)))
+            .first::<RefreshToken>(&mut conn)
+            .optional()?;
+
+        match current {
+            Some(current) => {
+                diesel::update(refresh_tokens)
+                    .filter(family_id.eq(current.family_id))
+                    .filter(revoked_at.is_null())
+                    .set(revoked_at.eq(Utc::now().naive_utc()))
+                    .execute(&mut conn)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn login_response(
+        &self,
+        wallet: Wallet,
+        project: &Project,
+        refresh_token: String,
+    ) -> Result<LoginResponse, EthosError> {
+        let (token, claims) = self
+            .jwt_auth
+            .create_token(wallet.id, &project.id.to_string())?;
+        let expires_at = NaiveDateTime::from_timestamp_opt(claims.exp, 0).unwrap_or_default();
+        Ok(LoginResponse {
+            token,
+            expires_at,
+            refresh_token,
+            wallet,
+        })
+    }
+
+    /// Returns the wallet of a valid access token. When the request is made on
+    /// behalf of a project, the token must have been issued for it.
+    pub async fn validate(
+        &self,
+        token: &str,
+        project: Option<&Project>,
+    ) -> Result<Wallet, EthosError> {
+        let audience = project.map(|project| project.id.to_string());
+        let claims = self.jwt_auth.validate(token, audience.as_deref())?;
+        self.wallet_service.get_wallet_by_id(claims.sub)
+    }
+
+    /// Only chains with a registered network can be used to log in
+    fn check_chain(&self, chain_id: u64) -> Result<(), EthosError> {
+        let unsupported =
+            || EthosError::InvalidLoginMessage(format!("chain {} is not supported", chain_id));
+        let chain_id = i32::try_from(chain_id).map_err(|_| unsupported())?;
+        match self.nft_service.get_network_by_id(chain_id) {
+            Ok(_) => Ok(()),
+            Err(EthosError::DatabaseError(diesel::result::Error::NotFound)) => Err(unsupported()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn generate_refresh_token() -> (String, String) {
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(keccak256(token.as_bytes()))
+}
+
+fn project_url(project: &Project) -> Result<&str, EthosError> {
+    project.url.as_deref().ok_or_else(|| {
+        EthosError::InvalidLoginMessage("project has no url to sign in to".to_string())
+    })
+}
+
+fn project_resource(project: &Project) -> String {
+    format!("urn:uuid:{}", project.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use dotenvy::dotenv;
+    use ethers::{providers::Http, types::Address};
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::{create_connection_pool, ConnectionPool},
+        errors::EthosError,
+        events::EventBus,
+        services::{nft::NftService, project::ProjectService, wallet::WalletService},
+    };
+
+    use super::AuthService;
+
+    #[test]
+    fn test_refresh_token_rotation() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let wallet_service = Arc::new(WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        ));
+        let nft_service = Arc::new(NftService::new(pool.clone(), Arc::new(EventBus::default())));
+        let auth_service = AuthService::new(pool.clone(), wallet_service.clone(), nft_service);
+        let project = ProjectService::new(pool).create_project("Refresh tokens", None)?;
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+
+        let session = auth_service.create_session(wallet, &project)?;
+        let rotated = auth_service.refresh(&project, &session.refresh_token)?;
+        assert_ne!(session.refresh_token, rotated.refresh_token);
+        assert_eq!(session.wallet, rotated.wallet);
+
+        // using a rotated token again revokes the whole session
+        let reused = auth_service.refresh(&project, &session.refresh_token);
+        assert!(matches!(reused, Err(EthosError::RefreshTokenReused)));
+        let revoked = auth_service.refresh(&project, &rotated.refresh_token);
+        assert!(matches!(revoked, Err(EthosError::RefreshTokenReused)));
+
+        let session = auth_service.create_session(rotated.wallet, &project)?;
+        assert!(auth_service.logout(&session.refresh_token)?);
+        let logged_out = auth_service.refresh(&project, &session.refresh_token);
+        assert!(logged_out.is_err());
+
+        Ok(())
     }
 }
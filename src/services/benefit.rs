@@ -0,0 +1,455 @@
+use std::sync::Arc;
+
+use async_graphql::{ComplexObject, Context, InputObject, SimpleObject};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::{Identifiable, IntoSql, PgConnection, Queryable};
+use ethers::types::Signature;
+use ethers::utils::to_checksum;
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::database::ConnectionPool;
+use crate::errors::EthosError;
+use crate::guards::is_authenticated::IsAuthenticated;
+use crate::schema::{benefits, collections, nft_benefits, nfts, wallets};
+
+use super::nft::{Collection, Nft};
+use super::project::Project;
+use super::wallet::{SignatureInput, Wallet};
+
+#[derive(Debug, Queryable, SimpleObject, Associations, Identifiable)]
+#[diesel(belongs_to(Collection))]
+#[diesel(table_name = benefits)]
+pub struct Benefit {
+    pub id: Uuid,
+    name: String,
+    description: String,
+    image: String,
+    expires_at: Option<chrono::NaiveDateTime>,
+    #[graphql(skip)]
+    pub collection_id: Uuid,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Queryable, SimpleObject, Associations, Identifiable)]
+#[diesel(belongs_to(Benefit))]
+#[diesel(belongs_to(Nft))]
+#[diesel(table_name = nft_benefits)]
+#[graphql(name = "NFTBenefit", complex)]
+pub struct NftBenefit {
+    pub id: Uuid,
+    #[graphql(skip)]
+    cupom_code: Option<String>,
+    available: bool,
+    expires_at: Option<chrono::NaiveDateTime>,
+    redeemed_at: Option<chrono::NaiveDateTime>,
+    #[graphql(skip)]
+    pub benefit_id: Uuid,
+    #[graphql(skip)]
+    pub nft_id: Uuid,
+    #[graphql(skip)]
+    pub redeemed_by: Option<Uuid>,
+}
+
+#[ComplexObject]
+impl NftBenefit {
+    pub async fn benefit(&self, ctx: &Context<'_>) -> Result<Benefit, EthosError> {
+        let service = ctx.data::<Arc<BenefitService>>().unwrap();
+        service.get_benefit(self.benefit_id)
+    }
+
+    /// Only revealed to the current owner of the NFT
+    #[graphql(guard = "IsAuthenticated")]
+    pub async fn cupom_code(&self, ctx: &Context<'_>) -> Result<Option<String>, EthosError> {
+        let wallet = ctx.data_unchecked::<Wallet>();
+        let service = ctx.data::<Arc<BenefitService>>().unwrap();
+        if service.is_nft_owner(self.nft_id, wallet.id)? {
+            return Ok(self.cupom_code.clone());
+        }
+        Ok(None)
+    }
+}
+
+#[derive(InputObject)]
+pub struct CreateBenefitInput {
+    pub collection_id: Uuid,
+    pub name: String,
+    pub description: String,
+    #[graphql(validator(url))]
+    pub image: String,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(InputObject)]
+pub struct RedeemBenefitInput {
+    pub nft_id: Uuid,
+    pub benefit_id: Uuid,
+    pub sig: SignatureInput,
+}
+
+pub struct BenefitService {
+    pool: ConnectionPool,
+}
+
+impl BenefitService {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        Self {
+            pool: ConnectionPool::new(pool),
+        }
+    }
+
+    /// Creates a benefit and makes it available to every NFT of the collection,
+    /// the ones created later are linked to it by `link_collection_benefits`
+    pub fn create_benefit(
+        &self,
+        project: &Project,
+        input: CreateBenefitInput,
+    ) -> Result<Benefit, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        conn.transaction(|conn| {
+            let collection = collections::table
+                .filter(collections::id.eq(input.collection_id))
+                .filter(collections::project_id.eq(project.id))
+                .first::<Collection>(conn)?;
+
+            let benefit = diesel::insert_into(benefits::table)
+                .values((
+                    benefits::name.eq(input.name),
+                    benefits::description.eq(input.description),
+                    benefits::image.eq(input.image),
+                    benefits::expires_at.eq(input.expires_at),
+                    benefits::collection_id.eq(collection.id),
+                ))
+                .get_result::<Benefit>(conn)?;
+
+            diesel::insert_into(nft_benefits::table)
+                .values(
+                    nfts::table
+                        .filter(nfts::collection_id.eq(collection.id))
+                        .select((
+                        benefit.id.into_sql::<diesel::sql_types::Uuid>(),
+                        nfts::id,
+                        benefit
+                            .expires_at
+                            .into_sql::<diesel::sql_types::Nullable<diesel::sql_types::Timestamp>>(
+                            ),
+                    )),
+                )
+                .into_columns((
+                    nft_benefits::benefit_id,
+                    nft_benefits::nft_id,
+                    nft_benefits::expires_at,
+                ))
+                .execute(conn)?;
+            Ok(benefit)
+        })
+    }
+
+    /// Sets the coupon codes of NFTs, indexed by the NFT id
+    pub fn set_cupom_codes(
+        &self,
+        benefit: &Benefit,
+        codes: Vec<(Uuid, String)>,
+    ) -> Result<(), EthosError> {
+        use crate::schema::nft_benefits::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        conn.transaction(|conn| {
+            for (nft, code) in codes {
+                diesel::update(nft_benefits)
+                    .filter(benefit_id.eq(benefit.id))
+                    .filter(nft_id.eq(nft))
+                    .set(cupom_code.eq(code))
+                    .execute(conn)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn get_benefit(&self, id: Uuid) -> Result<Benefit, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = benefits::table.find(id).first::<Benefit>(&mut conn)?;
+        Ok(result)
+    }
+
+    pub fn get_benefits(
+        &self,
+        project: &Project,
+        page: i32,
+        limit: i32,
+    ) -> Result<Vec<Benefit>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = benefits::table
+            .inner_join(collections::table)
+            .filter(collections::project_id.eq(project.id))
+            .select(benefits::all_columns)
+            .order(benefits::created_at.desc())
+            .offset((page * limit).into())
+            .limit(limit.into())
+            .load::<Benefit>(&mut conn)?;
+        Ok(result)
+    }
+
+    pub fn get_nft_benefits(&self, nft: &Nft) -> Result<Vec<NftBenefit>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = NftBenefit::belonging_to(nft).load::<NftBenefit>(&mut conn)?;
+        Ok(result)
+    }
+
+    pub fn is_nft_owner(&self, nft: Uuid, wallet: Uuid) -> Result<bool, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = diesel::select(diesel::dsl::exists(
+            nfts::table
+                .filter(nfts::id.eq(nft))
+                .filter(nfts::owner_id.eq(wallet)),
+        ))
+        .get_result::<bool>(&mut conn)?;
+        Ok(result)
+    }
+
+    /// Redeems a benefit of an NFT. The signature must be made by the current
+    /// owner of the NFT over [`redeem_benefit_message`].
+    pub fn redeem_benefit(
+        &self,
+        project: &Project,
+        input: RedeemBenefitInput,
+    ) -> Result<NftBenefit, EthosError> {
+        let signature = Signature::try_from(&input.sig)?;
+        let signer = signature.recover(redeem_benefit_message(input.nft_id, input.benefit_id))?;
+        let mut conn = self.pool.get()?;
+        let now = chrono::Utc::now().naive_utc();
+
+        conn.transaction(|conn| {
+            let nft_benefit = nft_benefits::table
+                .filter(nft_benefits::nft_id.eq(input.nft_id))
+                .filter(nft_benefits::benefit_id.eq(input.benefit_id))
+                .for_update()
+                .first::<NftBenefit>(conn)
+                .optional()?
+                .ok_or(EthosError::NftNotEligible)?;
+            let benefit = benefits::table
+                .inner_join(collections::table)
+                .filter(benefits::id.eq(input.benefit_id))
+                .filter(collections::project_id.eq(project.id))
+                .select(benefits::all_columns)
+                .first::<Benefit>(conn)?;
+
+            let expired = [nft_benefit.expires_at, benefit.expires_at]
+                .iter()
+                .flatten()
+                .any(|expires_at| *expires_at <= now);
+            if !nft_benefit.available || expired {
+                return Err(EthosError::BenefitUnavailable);
+            }
+
+            let owner = nfts::table
+                .inner_join(wallets::table)
+                .filter(nfts::id.eq(input.nft_id))
+                .filter(wallets::address.eq(to_checksum(&signer, None)))
+                .select(wallets::id)
+                .first::<Uuid>(conn)
+                .optional()?
+                .ok_or(EthosError::NotNftOwner)?;
+
+            Ok(diesel::update(&nft_benefit)
+                .set((
+                    nft_benefits::available.eq(false),
+                    nft_benefits::redeemed_at.eq(now),
+                    nft_benefits::redeemed_by.eq(owner),
+                ))
+                .get_result::<NftBenefit>(conn)?)
+        })
+    }
+}
+
+/// Message the owner of the NFT signs to redeem one of its benefits
+/// Links the NFTs to the benefits their collections already have. Must be
+/// called in the transaction inserting the NFTs.
+pub fn link_collection_benefits(
+    conn: &mut PgConnection,
+    nft_ids: &[Uuid],
+) -> Result<usize, EthosError> {
+    let result = diesel::insert_into(nft_benefits::table)
+        .values(
+            nfts::table
+                .inner_join(benefits::table.on(benefits::collection_id.eq(nfts::collection_id)))
+                .filter(nfts::id.eq_any(nft_ids))
+                .select((benefits::id, nfts::id, benefits::expires_at)),
+        )
+        .into_columns((
+            nft_benefits::benefit_id,
+            nft_benefits::nft_id,
+            nft_benefits::expires_at,
+        ))
+        .on_conflict((nft_benefits::benefit_id, nft_benefits::nft_id))
+        .do_nothing()
+        .execute(conn)?;
+    Ok(result)
+}
+
+pub fn redeem_benefit_message(nft: Uuid, benefit: Uuid) -> String {
+    format!(
+        "Redeem benefit\n\n\
+            Benefit:\n\
+            {}\n\n\
+            NFT:\n\
+            {}",
+        benefit, nft
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use dotenvy::dotenv;
+    use ethers::types::Address;
+    use ethers::{
+        providers::Http,
+        signers::{LocalWallet, Signer},
+        types::{Signature, U256},
+        utils::hex,
+    };
+    use fixed_hash::rand::thread_rng;
+    use uuid::Uuid;
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::{create_connection_pool, ConnectionPool},
+        errors::EthosError,
+        events::EventBus,
+        services::{
+            nft::{CollectionContract, NewNft, Nft, NftService},
+            project::{Project, ProjectService},
+            wallet::{SignatureInput, WalletService},
+        },
+    };
+
+    use super::{redeem_benefit_message, BenefitService, CreateBenefitInput, RedeemBenefitInput};
+
+    /// Creates a collection of the project on a new network
+    fn create_contract(service: &NftService, project: &Project) -> Result<CollectionContract> {
+        let network = service.create_network((Uuid::new_v4().as_u128() % 1_000_000) as i32)?;
+        let collection = service.create_collection(project, "Benefits", None)?;
+        Ok(service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?)
+    }
+
+    fn create_nft(service: &NftService, contract: &CollectionContract, nft_id: i32) -> Result<Nft> {
+        let nft = NewNft {
+            nft_id,
+            name: format!("Benefit #{}", nft_id),
+            image: String::new(),
+            description: String::new(),
+            external_url: String::new(),
+            animation_url: String::new(),
+            collection_id: contract.collection_id,
+            network_contract_id: contract.id,
+        };
+        Ok(service.create_nfts(vec![nft])?.remove(0))
+    }
+
+    fn signature_input(signature: &Signature) -> SignatureInput {
+        let component = |value: U256| {
+            let mut bytes = [0u8; 32];
+            value.to_big_endian(&mut bytes);
+            format!("0x{}", hex::encode(bytes))
+        };
+        SignatureInput {
+            r: component(signature.r),
+            s: component(signature.s),
+            v: signature.v as i32,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_benefit_redemption() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let nft_service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let benefit_service = BenefitService::new(pool.clone());
+        let project = ProjectService::new(pool.clone()).create_project("Benefits", None)?;
+
+        let contract = create_contract(&nft_service, &project)?;
+        let nft = create_nft(&nft_service, &contract, 1)?;
+
+        let owner = LocalWallet::new(&mut thread_rng());
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let wallet = wallet_service.upsert_wallet(owner.address())?;
+        nft_service.transfer_nft(nft.id, &wallet)?;
+
+        let benefit = benefit_service.create_benefit(
+            &project,
+            CreateBenefitInput {
+                collection_id: nft.collection_id,
+                name: "Free drink".to_string(),
+                description: "One drink at the bar".to_string(),
+                image: "https://ethos.xyz/drink.png".to_string(),
+                expires_at: None,
+            },
+        )?;
+        benefit_service.set_cupom_codes(&benefit, vec![(nft.id, "DRINK-1".to_string())])?;
+        assert_eq!(benefit_service.get_nft_benefits(&nft)?.len(), 1);
+        assert!(benefit_service.is_nft_owner(nft.id, wallet.id)?);
+
+        let message = redeem_benefit_message(nft.id, benefit.id);
+        let redeem = |signature: &Signature| RedeemBenefitInput {
+            nft_id: nft.id,
+            benefit_id: benefit.id,
+            sig: signature_input(signature),
+        };
+
+        // someone else can't redeem the benefit
+        let stranger = LocalWallet::new(&mut thread_rng());
+        let signature = stranger.sign_message(&message).await?;
+        let result = benefit_service.redeem_benefit(&project, redeem(&signature));
+        assert!(matches!(result, Err(EthosError::NotNftOwner)));
+
+        let signature = owner.sign_message(&message).await?;
+        let redeemed = benefit_service.redeem_benefit(&project, redeem(&signature))?;
+        assert!(!redeemed.available);
+        assert_eq!(redeemed.redeemed_by, Some(wallet.id));
+
+        // benefits can be redeemed only once
+        let result = benefit_service.redeem_benefit(&project, redeem(&signature));
+        assert!(matches!(result, Err(EthosError::BenefitUnavailable)));
+
+        // NFTs created later get the benefits of their collection
+        let later = create_nft(&nft_service, &contract, 2)?;
+        let benefits = benefit_service.get_nft_benefits(&later)?;
+        assert_eq!(benefits.len(), 1);
+        assert_eq!(benefits[0].benefit_id, benefit.id);
+
+        let other_contract = create_contract(&nft_service, &project)?;
+        let other = create_nft(&nft_service, &other_contract, 1)?;
+        nft_service.transfer_nft(other.id, &wallet)?;
+        let message = redeem_benefit_message(other.id, benefit.id);
+        let signature = owner.sign_message(&message).await?;
+        let result = benefit_service.redeem_benefit(
+            &project,
+            RedeemBenefitInput {
+                nft_id: other.id,
+                benefit_id: benefit.id,
+                sig: signature_input(&signature),
+            },
+        );
+        assert!(matches!(result, Err(EthosError::NftNotEligible)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,676 @@
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use async_graphql::{InputObject, SimpleObject};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::sql_types::{Nullable, Uuid as SqlUuid};
+use diesel::{Identifiable, PgConnection, Queryable};
+use diesel_derive_enum::DbEnum;
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::{
+    database::ConnectionPool,
+    errors::EthosError,
+    mail::{
+        queue::EmailQueue,
+        template::render,
+        transport::{EmailTransport, OutgoingEmail},
+    },
+    schema::{email_templates, emails, profiles, projects, tickets},
+};
+
+use super::{project::Project, ticket::Ticket};
+
+// deliveries of an email before it's given up
+const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+// wait after the first failed delivery, doubled on every retry
+const DEFAULT_BACKOFF_SECONDS: i64 = 30;
+// longest wait between two deliveries
+const MAX_BACKOFF_SECONDS: i64 = 60 * 60;
+// emails sent on each run of the worker
+const BATCH_SIZE: i64 = 50;
+// orders of new tickets emailed in one transaction
+const ORDER_BATCH_SIZE: i64 = 100;
+// claimed emails are hidden from other workers while they are being sent
+const CLAIM_SECONDS: i64 = 5 * 60;
+// a ticket's codes are resent at most once in this time
+const RESEND_COOLDOWN_SECONDS: i64 = 10 * 60;
+
+const TICKET_SUBJECT: &str = "Your tickets for {{project}}";
+const TICKET_BODY: &str = "Hi {{name}},
+
+Here are your ticket codes for {{project}}:
+
+{{codes}}
+
+Your order token is {{token}}.";
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::EmailState"]
+pub enum EmailState {
+    /// Waiting to be delivered, or to be retried
+    Pending,
+    Sent,
+    /// Every delivery attempt failed
+    Failed,
+}
+
+#[derive(Debug, Queryable, Identifiable, Associations)]
+#[diesel(belongs_to(Project))]
+#[diesel(belongs_to(Ticket))]
+#[diesel(table_name = emails)]
+pub struct Email {
+    pub id: Uuid,
+    pub state: EmailState,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub next_attempt_at: chrono::NaiveDateTime,
+    pub sent_at: Option<chrono::NaiveDateTime>,
+    pub project_id: Uuid,
+    pub ticket_id: Option<Uuid>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = emails)]
+pub struct NewEmail {
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub project_id: Uuid,
+    pub ticket_id: Option<Uuid>,
+}
+
+#[derive(Debug, Queryable, SimpleObject, Associations, Identifiable)]
+#[diesel(belongs_to(Project))]
+#[diesel(table_name = email_templates)]
+pub struct EmailTemplate {
+    pub id: Uuid,
+    pub template_id: String,
+    pub subject: String,
+    /// `{{variable}}` placeholders are replaced with the ticket data: `name`,
+    /// `email`, `code`, `codes`, `tier`, `event`, `token` and `project`
+    pub body: String,
+    #[graphql(skip)]
+    pub project_id: Uuid,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(InputObject)]
+pub struct CreateEmailTemplateInput {
+    pub template_id: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Durable outbox of the emails, delivered by a worker with retries. The
+/// emails are always kept in Postgres, the queue only wakes up the workers.
+pub struct EmailService {
+    pool: ConnectionPool,
+    transport: Arc<dyn EmailTransport>,
+    queue: Arc<dyn EmailQueue>,
+    max_attempts: i32,
+    backoff: i64,
+    /// Handles only the tickets and emails of this project when set, every
+    /// project's otherwise
+    pub project_id: Option<Uuid>,
+    pub order_batch_size: i64,
+}
+
+impl EmailService {
+    pub fn new(
+        pool: Pool<ConnectionManager<PgConnection>>,
+        transport: Arc<dyn EmailTransport>,
+        queue: Arc<dyn EmailQueue>,
+    ) -> Self {
+        let max_attempts = env::var("EMAIL_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_ATTEMPTS);
+        let backoff = env::var("EMAIL_BACKOFF_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_BACKOFF_SECONDS);
+        Self {
+            pool: ConnectionPool::new(pool),
+            transport,
+            queue,
+            max_attempts,
+            backoff,
+            project_id: None,
+            order_batch_size: ORDER_BATCH_SIZE,
+        }
+    }
+
+    /// Creates the template, or replaces the one with the same `template_id`
+    pub fn create_template(
+        &self,
+        project: &Project,
+        input: CreateEmailTemplateInput,
+    ) -> Result<EmailTemplate, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = diesel::insert_into(email_templates::table)
+            .values((
+                email_templates::template_id.eq(&input.template_id),
+                email_templates::subject.eq(&input.subject),
+                email_templates::body.eq(&input.body),
+                email_templates::project_id.eq(project.id),
+            ))
+            .on_conflict((email_templates::project_id, email_templates::template_id))
+            .do_update()
+            .set((
+                email_templates::subject.eq(&input.subject),
+                email_templates::body.eq(&input.body),
+            ))
+            .get_result::<EmailTemplate>(&mut conn)?;
+        Ok(result)
+    }
+
+    /// Queues one email per order of the address, listing its ticket codes.
+    /// Tickets emailed in the last `RESEND_COOLDOWN_SECONDS` are left out, so
+    /// an address can't be flooded. Returns whether the address has tickets.
+    pub async fn resend_ticket_email(
+        &self,
+        project: &Project,
+        email: &str,
+    ) -> Result<bool, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let queued = conn.transaction::<_, EthosError, _>(|conn| {
+            let tickets = tickets::table
+                .filter(tickets::project_id.eq(project.id))
+                .filter(tickets::email.eq(email))
+                .order((tickets::token.asc(), tickets::created_at.asc()))
+                .for_update()
+                .load::<Ticket>(conn)?;
+            if tickets.is_empty() {
+                return Ok(false);
+            }
+
+            let resend_before =
+                chrono::Utc::now().naive_utc() - chrono::Duration::seconds(RESEND_COOLDOWN_SECONDS);
+            let tickets = tickets
+                .into_iter()
+                .filter(|ticket| ticket.emailed_at.is_none_or(|at| at <= resend_before))
+                .collect::<Vec<Ticket>>();
+            if tickets.is_empty() {
+                return Err(EthosError::TicketEmailThrottled);
+            }
+
+            let new_emails = order_emails(conn, project, &tickets)?;
+            enqueue(conn, new_emails)?;
+            diesel::update(tickets::table)
+                .filter(tickets::id.eq_any(tickets.iter().map(|ticket| ticket.id)))
+                .set(tickets::emailed_at.eq(diesel::dsl::now))
+                .execute(conn)?;
+            Ok(true)
+        })?;
+
+        if queued {
+            self.queue.notify().await;
+        }
+        Ok(queued)
+    }
+
+    /// Queues the codes of the tickets that were never emailed, one email per
+    /// order and attendee, a page of orders at a time. Returns how many
+    /// tickets were emailed.
+    pub async fn send_new_ticket_emails(&self) -> Result<usize, EthosError> {
+        let mut emailed = 0;
+        loop {
+            let (orders, page) = self.email_new_tickets()?;
+            emailed += page;
+            // the tickets of an empty page are being emailed by another worker
+            if orders < self.order_batch_size as usize || page == 0 {
+                break;
+            }
+        }
+
+        if emailed > 0 {
+            self.queue.notify().await;
+        }
+        Ok(emailed)
+    }
+
+    /// Queues the emails of the next page of orders with tickets that were
+    /// never emailed. Returns how many orders were read and how many tickets
+    /// were emailed.
+    fn email_new_tickets(&self) -> Result<(usize, usize), EthosError> {
+        let mut conn = self.pool.get()?;
+
+        conn.transaction::<_, EthosError, _>(|conn| {
+            let in_scope = || {
+                tickets::project_id
+                    .nullable()
+                    .eq(self.project_id)
+                    .or(self.project_id.into_sql::<Nullable<SqlUuid>>().is_null())
+            };
+            let orders = tickets::table
+                .filter(tickets::emailed_at.is_null())
+                .filter(in_scope())
+                .select((tickets::project_id, tickets::email, tickets::token))
+                .distinct()
+                .order((
+                    tickets::project_id.asc(),
+                    tickets::email.asc(),
+                    tickets::token.asc(),
+                ))
+                .limit(self.order_batch_size)
+                .load::<(Uuid, String, String)>(conn)?;
+            // every ticket matching the three columns belongs to a whole order
+            let tickets = tickets::table
+                .inner_join(projects::table)
+                .filter(tickets::emailed_at.is_null())
+                .filter(tickets::project_id.eq_any(orders.iter().map(|order| order.0)))
+                .filter(tickets::email.eq_any(orders.iter().map(|order| &order.1)))
+                .filter(tickets::token.eq_any(orders.iter().map(|order| &order.2)))
+                .order((
+                    tickets::project_id.asc(),
+                    tickets::email.asc(),
+                    tickets::token.asc(),
+                    tickets::created_at.asc(),
+                ))
+                .for_update()
+                .skip_locked()
+                .load::<(Ticket, Project)>(conn)?;
+
+            let mut projects: Vec<(Project, Vec<Ticket>)> = vec![];
+            for (ticket, project) in tickets {
+                match projects.last_mut() {
+                    Some((last, tickets)) if last.id == project.id => tickets.push(ticket),
+                    _ => projects.push((project, vec![ticket])),
+                }
+            }
+            let mut emailed = vec![];
+            for (project, tickets) in &projects {
+                let new_emails = order_emails(conn, project, tickets)?;
+                enqueue(conn, new_emails)?;
+                emailed.extend(tickets.iter().map(|ticket| ticket.id));
+            }
+            diesel::update(tickets::table)
+                .filter(tickets::id.eq_any(&emailed))
+                .set(tickets::emailed_at.eq(diesel::dsl::now))
+                .execute(conn)?;
+            Ok((orders.len(), emailed.len()))
+        })
+    }
+
+    /// Queues the template to every ticket of the events
+    pub async fn send_template_email_to_tickets(
+        &self,
+        project: &Project,
+        events: &[String],
+        template_id: &str,
+    ) -> Result<Vec<Ticket>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let template = email_templates::table
+            .filter(email_templates::project_id.eq(project.id))
+            .filter(email_templates::template_id.eq(template_id))
+            .first::<EmailTemplate>(&mut conn)?;
+        let tickets = tickets::table
+            .filter(tickets::project_id.eq(project.id))
+            .filter(tickets::event.eq_any(events))
+            .order(tickets::created_at.asc())
+            .load::<Ticket>(&mut conn)?;
+
+        let new_emails = tickets
+            .iter()
+            .map(|ticket| {
+                let variables = ticket_variables(&mut conn, project, ticket)?;
+                Ok(NewEmail {
+                    recipient: ticket.email.clone(),
+                    subject: render(&template.subject, &variables),
+                    body: render(&template.body, &variables),
+                    project_id: project.id,
+                    ticket_id: Some(ticket.id),
+                })
+            })
+            .collect::<Result<Vec<NewEmail>, EthosError>>()?;
+
+        enqueue(&mut conn, new_emails)?;
+        if !tickets.is_empty() {
+            self.queue.notify().await;
+        }
+        Ok(tickets)
+    }
+
+    pub fn get_email(&self, id: Uuid) -> Result<Email, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = emails::table.find(id).first::<Email>(&mut conn)?;
+        Ok(result)
+    }
+
+    /// Delivers the due emails whenever the queue is notified, or on each
+    /// interval, until the task is dropped
+    pub async fn run(&self, interval: Duration) {
+        loop {
+            if let Err(err) = self.process().await {
+                println!("Failed to process emails: {}", err);
+            }
+            self.queue.wait(interval).await;
+        }
+    }
+
+    /// Delivers the due emails, returning how many were sent
+    pub async fn process(&self) -> Result<usize, EthosError> {
+        let mut sent = 0;
+        loop {
+            let batch = self.claim()?;
+            let last = (batch.len() as i64) < BATCH_SIZE;
+            for email in batch {
+                if self.deliver(&email).await? {
+                    sent += 1;
+                }
+            }
+            if last {
+                return Ok(sent);
+            }
+        }
+    }
+
+    /// Takes the due emails, hiding them from the other workers
+    fn claim(&self) -> Result<Vec<Email>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        conn.transaction(|conn| {
+            let now = chrono::Utc::now().naive_utc();
+            let due = emails::table
+                .filter(emails::state.eq(EmailState::Pending))
+                .filter(emails::next_attempt_at.le(now))
+                .filter(
+                    emails::project_id
+                        .nullable()
+                        .eq(self.project_id)
+                        .or(self.project_id.into_sql::<Nullable<SqlUuid>>().is_null()),
+                )
+                .order(emails::next_attempt_at.asc())
+                .limit(BATCH_SIZE)
+                .for_update()
+                .skip_locked()
+                .load::<Email>(conn)?;
+
+            let ids = due.iter().map(|email| email.id).collect::<Vec<Uuid>>();
+            diesel::update(emails::table)
+                .filter(emails::id.eq_any(ids))
+                .set(emails::next_attempt_at.eq(now + chrono::Duration::seconds(CLAIM_SECONDS)))
+                .execute(conn)?;
+            Ok(due)
+        })
+    }
+
+    async fn deliver(&self, email: &Email) -> Result<bool, EthosError> {
+        let outgoing = OutgoingEmail {
+            to: email.recipient.clone(),
+            subject: email.subject.clone(),
+            body: email.body.clone(),
+        };
+        let delivered = self.transport.send(&outgoing).await;
+
+        let mut conn = self.pool.get()?;
+        let attempts = email.attempts + 1;
+        match delivered {
+            Ok(()) => {
+                diesel::update(email)
+                    .set((
+                        emails::state.eq(EmailState::Sent),
+                        emails::attempts.eq(attempts),
+                        emails::error.eq(None::<String>),
+                        emails::sent_at.eq(diesel::dsl::now),
+                    ))
+                    .execute(&mut conn)?;
+                Ok(true)
+            }
+            Err(err) => {
+                println!("Failed to send email {}: {}", email.id, err);
+                let state = if attempts >= self.max_attempts {
+                    EmailState::Failed
+                } else {
+                    EmailState::Pending
+                };
+                let next_attempt_at =
+                    chrono::Utc::now().naive_utc() + backoff(self.backoff, attempts);
+                diesel::update(email)
+                    .set((
+                        emails::state.eq(state),
+                        emails::attempts.eq(attempts),
+                        emails::error.eq(err.to_string()),
+                        emails::next_attempt_at.eq(next_attempt_at),
+                    ))
+                    .execute(&mut conn)?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Adds the emails to the outbox, for callers running their own transaction.
+/// The workers pick them up on their next poll.
+pub fn enqueue(conn: &mut PgConnection, new_emails: Vec<NewEmail>) -> Result<usize, EthosError> {
+    let result = diesel::insert_into(emails::table)
+        .values(new_emails)
+        .execute(conn)?;
+    Ok(result)
+}
+
+/// Ticket emails of the orders, the tickets must be sorted by email and token
+fn order_emails(
+    conn: &mut PgConnection,
+    project: &Project,
+    tickets: &[Ticket],
+) -> Result<Vec<NewEmail>, EthosError> {
+    let mut orders: Vec<Vec<&Ticket>> = vec![];
+    for ticket in tickets {
+        match orders.last_mut() {
+            Some(order) if order[0].token == ticket.token && order[0].email == ticket.email => {
+                order.push(ticket)
+            }
+            _ => orders.push(vec![ticket]),
+        }
+    }
+    orders
+        .into_iter()
+        .map(|order| {
+            let mut variables = ticket_variables(conn, project, order[0])?;
+            let codes = order
+                .iter()
+                .map(|ticket| ticket.code.as_str())
+                .collect::<Vec<&str>>();
+            variables.insert("codes", codes.join("\n"));
+            Ok(NewEmail {
+                recipient: order[0].email.clone(),
+                subject: render(TICKET_SUBJECT, &variables),
+                body: render(TICKET_BODY, &variables),
+                project_id: project.id,
+                ticket_id: None,
+            })
+        })
+        .collect()
+}
+
+/// Wait before the delivery following the `attempts` failed ones
+fn backoff(base: i64, attempts: i32) -> chrono::Duration {
+    let exponent = (attempts - 1).clamp(0, 20) as u32;
+    let seconds = base.saturating_mul(2i64.saturating_pow(exponent));
+    chrono::Duration::seconds(seconds.min(MAX_BACKOFF_SECONDS))
+}
+
+/// Variables the ticket emails are rendered with. The name of the profile of
+/// the ticket's owner takes precedence over the name given to the ticket, the
+/// email of a profile isn't verified so it doesn't tell who the attendee is.
+fn ticket_variables<'a>(
+    conn: &mut PgConnection,
+    project: &Project,
+    ticket: &Ticket,
+) -> Result<HashMap<&'a str, String>, EthosError> {
+    let profile_name = profiles::table
+        .filter(profiles::wallet_id.nullable().eq(ticket.wallet_id))
+        .filter(profiles::name.is_not_null())
+        .select(profiles::name)
+        .first::<Option<String>>(conn)
+        .optional()?
+        .flatten();
+    let name = profile_name.unwrap_or_else(|| ticket.name.clone());
+
+    Ok(HashMap::from([
+        ("name", name),
+        ("email", ticket.email.clone()),
+        ("code", ticket.code.clone()),
+        ("codes", ticket.code.clone()),
+        ("tier", ticket.tier.to_string()),
+        ("event", ticket.event.clone().unwrap_or_default()),
+        ("token", ticket.token.clone()),
+        ("project", project.name.clone()),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use diesel::prelude::*;
+    use dotenvy::dotenv;
+    use ethers::{providers::Http, types::Address};
+    use uuid::Uuid;
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::{create_connection_pool, ConnectionPool},
+        errors::EthosError,
+        mail::{queue::LocalQueue, transport::MemoryTransport},
+        schema::{emails, tickets},
+        services::{
+            profile::ProfileService,
+            project::ProjectService,
+            ticket::{NewTicket, TicketService},
+            wallet::WalletService,
+        },
+    };
+
+    use super::{backoff, CreateEmailTemplateInput, Email, EmailService, EmailState};
+
+    #[tokio::test]
+    async fn test_email_outbox() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let transport = Arc::new(MemoryTransport::default());
+        let mut service = EmailService::new(
+            pool.clone(),
+            transport.clone(),
+            Arc::new(LocalQueue::default()),
+        );
+        let project = ProjectService::new(pool.clone()).create_project("Mailer", None)?;
+        // the other tests' emails are left to them
+        service.project_id = Some(project.id);
+        let address = format!("{}@example.com", Uuid::new_v4().simple());
+        let codes = (0..3)
+            .map(|_| Uuid::new_v4().simple().to_string())
+            .collect::<Vec<String>>();
+        let ticket = |token: &str, code: &str, event: &str| NewTicket {
+            token: format!("{}-{}", token, codes[0]),
+            code: code.to_string(),
+            email: address.clone(),
+            name: "Ticket Name".to_string(),
+            tier: 1,
+            event: Some(event.to_string()),
+            expires_at: None,
+            project_id: project.id,
+        };
+        TicketService::new(pool.clone()).insert_tickets(vec![
+            ticket("order-1", &codes[0], "day-1"),
+            ticket("order-1", &codes[1], "day-2"),
+            ticket("order-2", &codes[2], "day-3"),
+        ])?;
+        let wallet = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        )
+        .upsert_wallet(Address::random())?;
+        let profile_service = ProfileService::new(pool.clone());
+        profile_service.get_profile(&wallet)?;
+        profile_service.update_profile(&wallet, Some("Ana".to_string()), Some(address.clone()))?;
+        // the first order belongs to the wallet, the second only shares the
+        // email of its profile
+        let mut conn = pool.get()?;
+        diesel::update(tickets::table)
+            .filter(tickets::token.eq(format!("order-1-{}", codes[0])))
+            .set(tickets::wallet_id.eq(wallet.id))
+            .execute(&mut conn)?;
+
+        assert!(
+            !service
+                .resend_ticket_email(&project, "nobody@example.com")
+                .await?
+        );
+        assert!(service.resend_ticket_email(&project, &address).await?);
+        // resent tickets wait for the cooldown
+        assert!(matches!(
+            service.resend_ticket_email(&project, &address).await,
+            Err(EthosError::TicketEmailThrottled)
+        ));
+        service.create_template(
+            &project,
+            CreateEmailTemplateInput {
+                template_id: "reminder".to_string(),
+                subject: "See you at {{event}}".to_string(),
+                body: "{{name}}, bring {{code}}".to_string(),
+            },
+        )?;
+        let tickets = service
+            .send_template_email_to_tickets(&project, &["day-2".to_string()], "reminder")
+            .await?;
+        assert_eq!(tickets.len(), 1);
+
+        // the first delivery fails and is retried later
+        transport.fail_next(1);
+        assert_eq!(service.process().await?, 2);
+        let pending = emails::table
+            .filter(emails::project_id.eq(project.id))
+            .filter(emails::state.eq(EmailState::Pending))
+            .first::<Email>(&mut conn)?;
+        assert_eq!(pending.attempts, 1);
+        assert!(pending.error.is_some());
+        assert!(pending.next_attempt_at > chrono::Utc::now().naive_utc());
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 2);
+        assert!(sent.iter().all(|email| email.to == address));
+        assert!(sent.iter().any(|email| email.subject == "See you at day-2"
+            && email.body == format!("Ana, bring {}", codes[1])));
+        let pending_body = service.get_email(pending.id)?.body;
+        assert!(sent
+            .iter()
+            .map(|email| email.body.as_str())
+            .chain([pending_body.as_str()])
+            .any(|body| body.starts_with("Hi Ana,")));
+        assert!(sent
+            .iter()
+            .map(|email| email.body.as_str())
+            .chain([pending_body.as_str()])
+            .any(|body| body.starts_with("Hi Ticket Name,")));
+
+        // retried once it's due
+        diesel::update(&pending)
+            .set(emails::next_attempt_at.eq(diesel::dsl::now))
+            .execute(&mut conn)?;
+        assert_eq!(service.process().await?, 1);
+        assert_eq!(service.get_email(pending.id)?.state, EmailState::Sent);
+        Ok(())
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(30, 1).num_seconds(), 30);
+        assert_eq!(backoff(30, 3).num_seconds(), 120);
+        assert_eq!(backoff(30, 30).num_seconds(), 60 * 60);
+    }
+}
@@ -0,0 +1,499 @@
+use std::{collections::HashMap, fs, io::Write, path::Path};
+
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::PgConnection;
+use r2d2::Pool;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::database::ConnectionPool;
+use crate::errors::EthosError;
+use crate::metadata::TokenMetadata;
+use crate::schema::{
+    attributes_on_nfts, collection_contracts, networks, nft_attributes, nfts, wallets,
+};
+
+use super::nft::{token_metadata, Nft, NftAttribute};
+
+/// NFTs loaded at once while exporting
+const PAGE_SIZE: i64 = 500;
+
+/// Columns of the CSV export before the traits, the import reads it back
+const CSV_COLUMNS: [&str; 9] = [
+    "token_id",
+    "name",
+    "description",
+    "image",
+    "external_url",
+    "animation_url",
+    "owner",
+    "minted",
+    "minted_at",
+];
+
+/// An NFT of an export, with its revealed metadata
+#[derive(Debug, Serialize)]
+pub struct ExportedNft {
+    pub token_id: i32,
+    /// chain and address of the contract minting it
+    pub chain_id: i32,
+    pub contract: String,
+    /// address of the wallet holding it
+    pub owner: Option<String>,
+    pub minted: bool,
+    pub minted_at: Option<chrono::NaiveDateTime>,
+    #[serde(flatten)]
+    pub metadata: TokenMetadata,
+}
+
+/// An NFT with its owner's address, chain and contract address
+type ExportRow = (Nft, Option<String>, i32, String);
+
+pub struct ExportService {
+    pool: ConnectionPool,
+    page_size: i64,
+}
+
+impl ExportService {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        Self {
+            pool: ConnectionPool::new(pool),
+            page_size: PAGE_SIZE,
+        }
+    }
+
+    /// Writes the NFTs of the collection as CSV, returning how many were
+    /// written. Every trait is a column, the values of a trait held more than
+    /// once are a JSON array and attributes without a trait are left out.
+    pub fn export_csv<W: Write>(
+        &self,
+        collection_id: Uuid,
+        writer: W,
+    ) -> Result<usize, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let trait_types = attributes_on_nfts::table
+            .inner_join(nft_attributes::table)
+            .inner_join(nfts::table)
+            .filter(nfts::collection_id.eq(collection_id))
+            .filter(nft_attributes::trait_type.is_not_null())
+            .select(nft_attributes::trait_type.assume_not_null())
+            .distinct()
+            .order(nft_attributes::trait_type)
+            .load::<String>(&mut conn)?;
+        let mut csv = csv::Writer::from_writer(writer);
+        csv.write_record(
+            CSV_COLUMNS
+                .iter()
+                .copied()
+                .chain(trait_types.iter().map(String::as_str)),
+        )
+        .map_err(export_error)?;
+
+        let count = self.for_each_nft(&mut conn, collection_id, |nft| {
+            let mut traits = HashMap::<&str, Vec<String>>::new();
+            for attribute in &nft.metadata.attributes {
+                if let Some(trait_type) = &attribute.trait_type {
+                    let value = match &attribute.value {
+                        serde_json::Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    };
+                    traits.entry(trait_type).or_default().push(value);
+                }
+            }
+            let metadata = &nft.metadata;
+            let mut record = vec![
+                nft.token_id.to_string(),
+                metadata.name.clone(),
+                metadata.description.clone(),
+                metadata.image.clone(),
+                metadata.external_url.clone().unwrap_or_default(),
+                metadata.animation_url.clone().unwrap_or_default(),
+                nft.owner.clone().unwrap_or_default(),
+                nft.minted.to_string(),
+                nft.minted_at.map(|at| at.to_string()).unwrap_or_default(),
+            ];
+            record.extend(trait_types.iter().map(|trait_type| {
+                traits
+                    .get(trait_type.as_str())
+                    .map(|values| trait_cell(values))
+                    .unwrap_or_default()
+            }));
+            csv.write_record(record).map_err(export_error)
+        })?;
+        csv.flush().map_err(export_error)?;
+        Ok(count)
+    }
+
+    /// Writes the NFTs of the collection as JSON, one per line
+    pub fn export_ndjson<W: Write>(
+        &self,
+        collection_id: Uuid,
+        mut writer: W,
+    ) -> Result<usize, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let count = self.for_each_nft(&mut conn, collection_id, |nft| {
+            serde_json::to_writer(&mut writer, &nft).map_err(export_error)?;
+            writer.write_all(b"\n").map_err(export_error)
+        })?;
+        writer.flush().map_err(export_error)?;
+        Ok(count)
+    }
+
+    /// Writes the metadata of every NFT of the collection to `dir`, in a
+    /// file named by its token id without extension under a folder per chain
+    /// and contract, `<chain>/<contract>/<token_id>` like the route serving
+    /// it, so the folder of a contract can be pinned and used as the base of
+    /// its `tokenURI`. The files are the ones `/metadata` serves once
+    /// revealed, so they match the provenance hash.
+    pub fn export_metadata_dir(
+        &self,
+        collection_id: Uuid,
+        dir: &Path,
+    ) -> Result<usize, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        fs::create_dir_all(dir).map_err(export_error)?;
+        self.for_each_nft(&mut conn, collection_id, |nft| {
+            let contract_dir = dir.join(nft.chain_id.to_string()).join(&nft.contract);
+            fs::create_dir_all(&contract_dir).map_err(export_error)?;
+            let json = serde_json::to_vec(&nft.metadata).map_err(export_error)?;
+            fs::write(contract_dir.join(nft.token_id.to_string()), json).map_err(export_error)
+        })
+    }
+
+    /// Calls `f` with every NFT of the collection sorted by token id, loading
+    /// a page of them at a time. The id breaks ties between contracts
+    /// minting the same token id.
+    fn for_each_nft<F>(
+        &self,
+        conn: &mut PgConnection,
+        collection_id: Uuid,
+        mut f: F,
+    ) -> Result<usize, EthosError>
+    where
+        F: FnMut(ExportedNft) -> Result<(), EthosError>,
+    {
+        let mut count = 0;
+        let mut last: Option<(i32, Uuid)> = None;
+        loop {
+            let mut query = nfts::table
+                .left_join(wallets::table)
+                .inner_join(collection_contracts::table.inner_join(networks::table))
+                .filter(nfts::collection_id.eq(collection_id))
+                .select((
+                    nfts::all_columns,
+                    wallets::address.nullable(),
+                    networks::chain_id,
+                    collection_contracts::address,
+                ))
+                .order((nfts::nft_id, nfts::id))
+                .limit(self.page_size)
+                .into_boxed();
+            if let Some((last_nft_id, last_id)) = last {
+                query = query.filter(
+                    nfts::nft_id
+                        .gt(last_nft_id)
+                        .or(nfts::nft_id.eq(last_nft_id).and(nfts::id.gt(last_id))),
+                );
+            }
+            let page = query.load::<ExportRow>(conn)?;
+            let Some((last_nft, ..)) = page.last() else {
+                return Ok(count);
+            };
+            last = Some((last_nft.nft_id, last_nft.id));
+
+            let ids = page.iter().map(|(nft, ..)| nft.id).collect::<Vec<_>>();
+            let mut attributes = attributes_on_nfts::table
+                .inner_join(nft_attributes::table)
+                .filter(attributes_on_nfts::nft_id.eq_any(ids))
+                .select((attributes_on_nfts::nft_id, nft_attributes::all_columns))
+                .order((nft_attributes::trait_type, nft_attributes::value))
+                .load::<(Uuid, NftAttribute)>(conn)?
+                .into_iter()
+                .fold(
+                    HashMap::<_, Vec<_>>::new(),
+                    |mut attributes, (nft, attribute)| {
+                        attributes.entry(nft).or_default().push(attribute);
+                        attributes
+                    },
+                );
+            for (nft, owner, chain_id, contract) in page {
+                let nft_attributes = attributes.remove(&nft.id).unwrap_or_default();
+                f(ExportedNft {
+                    token_id: nft.nft_id,
+                    chain_id,
+                    contract,
+                    owner,
+                    minted: nft.minted_at.is_some(),
+                    minted_at: nft.minted_at,
+                    metadata: token_metadata(nft, nft_attributes),
+                })?;
+                count += 1;
+            }
+        }
+    }
+}
+
+/// A single value as is, several as a JSON array the import splits back. A
+/// single value that looks like an array is written as one too.
+fn trait_cell(values: &[String]) -> String {
+    match values {
+        [value] if !value.starts_with('[') => value.clone(),
+        values => serde_json::Value::from(values).to_string(),
+    }
+}
+
+fn export_error(err: impl ToString) -> EthosError {
+    EthosError::ExportError(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, ops::RangeInclusive, sync::Arc};
+
+    use anyhow::Result;
+    use dotenvy::dotenv;
+    use ethers::{providers::Http, types::Address};
+    use uuid::Uuid;
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::{create_connection_pool, ConnectionPool},
+        events::EventBus,
+        services::{
+            import::{read_source, ImportAttribute, ImportRow, ImportSource},
+            nft::{Collection, CollectionContract, NewNft, Nft, NftService},
+            project::ProjectService,
+            wallet::WalletService,
+        },
+    };
+
+    use super::ExportService;
+
+    /// Creates a contract of the collection on a new chain, returned with
+    /// the chain its metadata is written under
+    fn create_contract(
+        service: &NftService,
+        collection: &Collection,
+    ) -> Result<(i32, CollectionContract)> {
+        let chain = (Uuid::new_v4().as_u128() % 1_000_000) as i32;
+        let network = service.create_network(chain)?;
+        let contract = service.create_collection_contract(
+            collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        Ok((chain, contract))
+    }
+
+    fn create_nfts(
+        service: &NftService,
+        contract: &CollectionContract,
+        nft_ids: RangeInclusive<i32>,
+    ) -> Result<Vec<Nft>> {
+        let new_nfts = nft_ids
+            .map(|nft_id| NewNft {
+                nft_id,
+                name: format!("Export #{}", nft_id),
+                image: format!("https://ethos.xyz/{}.png", nft_id),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: contract.collection_id,
+                network_contract_id: contract.id,
+            })
+            .collect();
+        Ok(service.create_nfts(new_nfts)?)
+    }
+
+    #[test]
+    fn test_export_collection() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let nft_service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let project = ProjectService::new(pool.clone()).create_project("Export", None)?;
+        let collection = nft_service.create_collection(&project, "Export", None)?;
+        let (chain, contract) = create_contract(&nft_service, &collection)?;
+        let mut nfts = create_nfts(&nft_service, &contract, 1..=3)?;
+        let nft = nfts.remove(0);
+        let attribute = |trait_type: &str, value: &str| {
+            nft_service.create_attribute(Some(trait_type), Some(value.to_string()), None, None)
+        };
+        let (red, tier) = (attribute("Color", "red")?, attribute("Tier", "1")?);
+        for (nft_id, attribute) in [(nft.id, &red), (nft.id, &tier), (nfts[0].id, &red)] {
+            nft_service.create_attribute_nft_relation(nft_id, attribute.id)?;
+        }
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+        nft_service.transfer_nft(nfts[0].id, &wallet)?;
+
+        let mut service = ExportService::new(pool);
+        // every page but the last is full
+        service.page_size = 2;
+
+        let mut csv = vec![];
+        assert_eq!(service.export_csv(nft.collection_id, &mut csv)?, 3);
+        let csv = String::from_utf8(csv)?;
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "token_id,name,description,image,external_url,animation_url,owner,minted,minted_at,Color,Tier"
+        );
+        assert_eq!(
+            lines[2],
+            format!(
+                "2,Export #2,,https://ethos.xyz/2.png,,,{},false,,red,",
+                wallet.address
+            )
+        );
+        assert_eq!(lines.len(), 4);
+
+        // the import reads the CSV back
+        let dir = std::env::temp_dir().join(format!("ethos-export-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        fs::write(dir.join("export.csv"), &csv)?;
+        let (rows, _) = read_source(&ImportSource::CsvManifest(dir.join("export.csv")))?;
+        let trait_value = |trait_type: &str, value: &str| ImportAttribute {
+            trait_type: Some(trait_type.to_string()),
+            value: Some(value.to_string()),
+            max_value: None,
+            display_type: None,
+        };
+        assert_eq!(
+            rows[0].1,
+            ImportRow {
+                nft_id: 1,
+                name: "Export #1".to_string(),
+                description: String::new(),
+                image: "https://ethos.xyz/1.png".to_string(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                attributes: vec![trait_value("Color", "red"), trait_value("Tier", "1")],
+            }
+        );
+
+        let mut ndjson = vec![];
+        assert_eq!(service.export_ndjson(nft.collection_id, &mut ndjson)?, 3);
+        let lines = String::from_utf8(ndjson)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1]["token_id"], 2);
+        assert_eq!(lines[1]["owner"], wallet.address.as_str());
+        assert_eq!(lines[1]["minted"], false);
+        assert_eq!(lines[1]["attributes"][0]["value"], "red");
+
+        // the files are the metadata served by the REST route
+        let metadata_dir = dir.join("metadata");
+        assert_eq!(
+            service.export_metadata_dir(nft.collection_id, &metadata_dir)?,
+            3
+        );
+        let contract_dir = metadata_dir.join(chain.to_string()).join(&contract.address);
+        for token_id in 1..=3 {
+            let metadata = nft_service.get_token_metadata(chain, &contract.address, token_id)?;
+            assert_eq!(
+                fs::read(contract_dir.join(token_id.to_string()))?,
+                serde_json::to_vec(&metadata)?
+            );
+        }
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_tokens_of_several_contracts() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let nft_service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool.clone()).create_project("Export", None)?;
+        let collection = nft_service.create_collection(&project, "Export", None)?;
+        // two chains mint the same token ids in the collection
+        let contracts = [
+            create_contract(&nft_service, &collection)?,
+            create_contract(&nft_service, &collection)?,
+        ];
+        let mut nfts = vec![];
+        for (_, contract) in &contracts {
+            nfts.extend(create_nfts(&nft_service, contract, 1..=2)?);
+        }
+        let attribute = |trait_type: &str, value: &str| {
+            nft_service.create_attribute(Some(trait_type), Some(value.to_string()), None, None)
+        };
+        let attributes = [
+            attribute("Color", "red")?,
+            attribute("Color", "blue; green")?,
+            attribute("Note", "[draft]")?,
+        ];
+        for nft in nfts.iter().filter(|nft| nft.nft_id == 1) {
+            for attribute in &attributes {
+                nft_service.create_attribute_nft_relation(nft.id, attribute.id)?;
+            }
+        }
+
+        let mut service = ExportService::new(pool);
+        // the tokens sharing an id are on different pages
+        service.page_size = 1;
+
+        let mut ndjson = vec![];
+        assert_eq!(service.export_ndjson(collection.id, &mut ndjson)?, 4);
+        let token_ids = String::from_utf8(ndjson)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .map(|line| Ok(line?["token_id"].clone()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(token_ids, vec![1, 1, 2, 2]);
+
+        // the values of a trait held more than once are read back apart
+        let mut csv = vec![];
+        assert_eq!(service.export_csv(collection.id, &mut csv)?, 4);
+        let dir = std::env::temp_dir().join(format!("ethos-export-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        fs::write(dir.join("export.csv"), &csv)?;
+        let (rows, _) = read_source(&ImportSource::CsvManifest(dir.join("export.csv")))?;
+        let trait_value = |trait_type: &str, value: &str| ImportAttribute {
+            trait_type: Some(trait_type.to_string()),
+            value: Some(value.to_string()),
+            max_value: None,
+            display_type: None,
+        };
+        assert_eq!(
+            rows[0].1.attributes,
+            vec![
+                trait_value("Color", "blue; green"),
+                trait_value("Color", "red"),
+                trait_value("Note", "[draft]"),
+            ]
+        );
+
+        // each contract has its own metadata folder
+        let metadata_dir = dir.join("metadata");
+        assert_eq!(
+            service.export_metadata_dir(collection.id, &metadata_dir)?,
+            4
+        );
+        for (chain, contract) in &contracts {
+            let contract_dir = metadata_dir.join(chain.to_string()).join(&contract.address);
+            for token_id in 1..=2 {
+                let metadata =
+                    nft_service.get_token_metadata(*chain, &contract.address, token_id)?;
+                assert_eq!(
+                    fs::read(contract_dir.join(token_id.to_string()))?,
+                    serde_json::to_vec(&metadata)?
+                );
+            }
+        }
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
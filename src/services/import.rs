@@ -0,0 +1,791 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::PgConnection;
+use r2d2::Pool;
+use serde::Deserialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::database::ConnectionPool;
+use crate::errors::EthosError;
+use crate::schema::{
+    attributes_on_nfts, collection_contracts, collections, networks, nft_attributes, nfts,
+};
+
+use super::benefit::link_collection_benefits;
+use super::nft::{Collection, CollectionContract, DisplayType, Network, NewNft, Nft};
+use super::project::Project;
+use super::rarity::refresh_rarity;
+use super::reveal::ensure_not_committed;
+
+sql_function!(fn lower(x: diesel::sql_types::Varchar) -> diesel::sql_types::Varchar);
+
+/// Where the NFTs of a collection are imported from
+#[derive(Debug, Clone)]
+pub enum ImportSource {
+    /// Directory of ERC-721 metadata JSON files named by their token id, like
+    /// `1.json` or `1`
+    MetadataDir(PathBuf),
+    /// CSV with `token_id`, `name`, `description`, `image`, `external_url`
+    /// and `animation_url` columns, any other column is a trait. A trait held
+    /// more than once is a JSON array of its values, like `["red","blue"]`.
+    CsvManifest(PathBuf),
+}
+
+impl ImportSource {
+    /// A directory is read as metadata files, a file as a CSV manifest
+    pub fn from_path(path: &Path) -> Self {
+        if path.is_dir() {
+            ImportSource::MetadataDir(path.to_path_buf())
+        } else {
+            ImportSource::CsvManifest(path.to_path_buf())
+        }
+    }
+}
+
+/// Collection the NFTs are imported into, it's created when the project has
+/// none with the name. So are the network and the contract.
+#[derive(Debug, Clone)]
+pub struct CollectionImport {
+    pub collection_name: String,
+    pub description: Option<String>,
+    pub chain_id: i32,
+    pub contract_address: String,
+    pub fee_recipient: String,
+    /// NFTs inserted per transaction
+    pub batch_size: usize,
+}
+
+/// An NFT of the source that passed validation
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportRow {
+    pub nft_id: i32,
+    pub name: String,
+    pub description: String,
+    pub image: String,
+    pub external_url: String,
+    pub animation_url: String,
+    pub attributes: Vec<ImportAttribute>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImportAttribute {
+    pub trait_type: Option<String>,
+    pub value: Option<String>,
+    pub max_value: Option<String>,
+    pub display_type: Option<DisplayType>,
+}
+
+/// A valid row with its file name or CSV line
+pub type LabeledRow = (String, ImportRow);
+
+/// A row that wasn't imported, `row` is the file name or the CSV line
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowError {
+    pub row: String,
+    pub message: String,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.row, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportReport {
+    pub collection_id: Uuid,
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+    /// differences between the import and the stored collection that didn't
+    /// stop it, such as the fee recipient of an existing contract
+    pub warnings: Vec<String>,
+}
+
+/// ERC-721 metadata JSON
+#[derive(Debug, Deserialize)]
+struct MetadataFile {
+    name: String,
+    #[serde(default)]
+    description: String,
+    image: String,
+    #[serde(default)]
+    external_url: String,
+    #[serde(default)]
+    animation_url: String,
+    #[serde(default)]
+    attributes: Vec<MetadataFileAttribute>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetadataFileAttribute {
+    trait_type: Option<String>,
+    value: Value,
+    display_type: Option<String>,
+    max_value: Option<Value>,
+}
+
+pub struct ImportService {
+    pool: ConnectionPool,
+}
+
+impl ImportService {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        Self {
+            pool: ConnectionPool::new(pool),
+        }
+    }
+
+    /// Imports the NFTs of the source into the collection. Rows failing
+    /// validation, already imported or in a failed batch are reported and
+    /// the others are imported. The collection is ranked once at the end.
+    pub fn import(
+        &self,
+        project: &Project,
+        import: &CollectionImport,
+        source: &ImportSource,
+    ) -> Result<ImportReport, EthosError> {
+        let (rows, mut errors) = read_source(source)?;
+        let mut conn = self.pool.get()?;
+
+        let (collection, contract) =
+            conn.transaction(|conn| collection_and_contract(conn, project, import))?;
+        ensure_not_committed(&mut conn, &[collection.id])?;
+        // the stored contract keeps its recipient, the NFTs are still imported
+        let mut warnings = vec![];
+        if !contract
+            .fee_recipient
+            .eq_ignore_ascii_case(&import.fee_recipient)
+        {
+            warnings.push(format!(
+                "fee recipient {} differs from the {} of contract {}, it's kept",
+                import.fee_recipient, contract.fee_recipient, contract.address
+            ));
+        }
+
+        let mut attribute_ids = existing_attributes(&mut conn, &rows)?;
+        let mut imported = 0;
+        for batch in rows.chunks(import.batch_size.max(1)) {
+            // ids created by a failed batch are rolled back with it
+            let mut batch_attribute_ids = attribute_ids.clone();
+            let result = conn.transaction(|conn| {
+                ensure_not_committed(conn, &[collection.id])?;
+                insert_batch(
+                    conn,
+                    collection.id,
+                    contract.id,
+                    batch,
+                    &mut batch_attribute_ids,
+                )
+            });
+            match result {
+                Ok((count, skipped)) => {
+                    imported += count;
+                    errors.extend(skipped);
+                    attribute_ids = batch_attribute_ids;
+                }
+                Err(err) => errors.extend(batch.iter().map(|(label, _)| RowError {
+                    row: label.clone(),
+                    message: err.to_string(),
+                })),
+            }
+        }
+        if imported > 0 {
+            refresh_rarity(&mut conn, collection.id)?;
+        }
+
+        Ok(ImportReport {
+            collection_id: collection.id,
+            imported,
+            errors,
+            warnings,
+        })
+    }
+}
+
+/// Valid rows of the source labeled by file name or CSV line, and the errors
+/// of the others
+pub fn read_source(source: &ImportSource) -> Result<(Vec<LabeledRow>, Vec<RowError>), EthosError> {
+    let read = match source {
+        ImportSource::MetadataDir(path) => read_metadata_dir(path)?,
+        ImportSource::CsvManifest(path) => read_csv_manifest(path)?,
+    };
+
+    let mut rows = vec![];
+    let mut errors = vec![];
+    let mut nft_ids = HashSet::new();
+    for (label, row) in read {
+        match row {
+            Ok(row) if !nft_ids.insert(row.nft_id) => errors.push(RowError {
+                row: label,
+                message: format!("token {} is repeated", row.nft_id),
+            }),
+            Ok(row) => rows.push((label, row)),
+            Err(message) => errors.push(RowError {
+                row: label,
+                message,
+            }),
+        }
+    }
+    Ok((rows, errors))
+}
+
+type ReadRow = (String, Result<ImportRow, String>);
+
+fn read_metadata_dir(path: &Path) -> Result<Vec<ReadRow>, EthosError> {
+    let mut files = fs::read_dir(path)
+        .map_err(|err| EthosError::ImportError(err.to_string()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension().is_none_or(|extension| extension == "json"))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let rows = files
+        .into_iter()
+        .filter_map(|file| {
+            let label = file.file_name()?.to_string_lossy().to_string();
+            // hidden files like `.DS_Store` aren't metadata
+            if label.starts_with('.') {
+                return None;
+            }
+            let stem = file.file_stem()?.to_string_lossy().to_string();
+            Some((label, read_metadata_file(&file, &stem)))
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn read_metadata_file(file: &Path, stem: &str) -> Result<ImportRow, String> {
+    let nft_id = parse_token_id(stem)?;
+    let json = fs::read(file).map_err(|err| err.to_string())?;
+    let metadata = serde_json::from_slice::<MetadataFile>(&json).map_err(|err| err.to_string())?;
+    let attributes = metadata
+        .attributes
+        .into_iter()
+        .map(|attribute| {
+            let display_type = attribute
+                .display_type
+                .as_deref()
+                .map(parse_display_type)
+                .transpose()?;
+            let value = attribute_value(attribute.value, display_type)?;
+            let max_value = attribute
+                .max_value
+                .map(|value| attribute_value(value, display_type))
+                .transpose()?;
+            Ok(ImportAttribute {
+                trait_type: attribute.trait_type,
+                value: Some(value),
+                max_value,
+                display_type,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    validate(ImportRow {
+        nft_id,
+        name: metadata.name,
+        description: metadata.description,
+        image: metadata.image,
+        external_url: metadata.external_url,
+        animation_url: metadata.animation_url,
+        attributes,
+    })
+}
+
+fn read_csv_manifest(path: &Path) -> Result<Vec<ReadRow>, EthosError> {
+    let mut reader =
+        csv::Reader::from_path(path).map_err(|err| EthosError::ImportError(err.to_string()))?;
+    let headers = reader
+        .headers()
+        .map_err(|err| EthosError::ImportError(err.to_string()))?
+        .iter()
+        .map(|header| header.trim().to_string())
+        .collect::<Vec<_>>();
+    if !headers.iter().any(|header| header == "token_id") {
+        return Err(EthosError::ImportError(
+            "CSV manifest needs a `token_id` column".to_string(),
+        ));
+    }
+
+    let rows = reader
+        .records()
+        .enumerate()
+        .map(|(index, record)| {
+            // the header is line 1
+            let line = record
+                .as_ref()
+                .ok()
+                .and_then(|record| record.position())
+                .map_or(index as u64 + 2, |position| position.line());
+            let row = record
+                .map_err(|err| err.to_string())
+                .and_then(|record| csv_row(&headers, &record));
+            (format!("line {}", line), row)
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn csv_row(headers: &[String], record: &csv::StringRecord) -> Result<ImportRow, String> {
+    let mut row = ImportRow {
+        nft_id: 0,
+        name: String::new(),
+        description: String::new(),
+        image: String::new(),
+        external_url: String::new(),
+        animation_url: String::new(),
+        attributes: vec![],
+    };
+    for (header, cell) in headers.iter().zip(record.iter()) {
+        let cell = cell.trim().to_string();
+        match header.as_str() {
+            "token_id" => row.nft_id = parse_token_id(&cell)?,
+            "name" => row.name = cell,
+            "description" => row.description = cell,
+            "image" => row.image = cell,
+            "external_url" => row.external_url = cell,
+            "animation_url" => row.animation_url = cell,
+            // columns of the export that aren't metadata
+            "owner" | "minted" | "minted_at" => {}
+            _ if cell.is_empty() => {}
+            trait_type => row
+                .attributes
+                .extend(trait_values(cell).into_iter().map(|value| ImportAttribute {
+                    trait_type: Some(trait_type.to_string()),
+                    value: Some(value),
+                    max_value: None,
+                    display_type: None,
+                })),
+        }
+    }
+    validate(row)
+}
+
+/// Values of a trait cell, a cell that isn't a JSON array of strings is a
+/// single value
+fn trait_values(cell: String) -> Vec<String> {
+    if !cell.starts_with('[') {
+        return vec![cell];
+    }
+    serde_json::from_str::<Vec<String>>(&cell).unwrap_or_else(|_| vec![cell])
+}
+
+fn parse_token_id(token_id: &str) -> Result<i32, String> {
+    token_id
+        .parse::<i32>()
+        .ok()
+        .filter(|token_id| *token_id >= 0)
+        .ok_or_else(|| format!("token id `{}` is not valid", token_id))
+}
+
+fn parse_display_type(display_type: &str) -> Result<DisplayType, String> {
+    match display_type {
+        "number" => Ok(DisplayType::Number),
+        "boost_percentage" => Ok(DisplayType::BoostPercentage),
+        "boost_number" => Ok(DisplayType::BoostNumber),
+        _ => Err(format!("display_type `{}` is not supported", display_type)),
+    }
+}
+
+/// Values are stored as text, the ones with a display type must be numbers
+fn attribute_value(value: Value, display_type: Option<DisplayType>) -> Result<String, String> {
+    match value {
+        Value::Number(number) => Ok(number.to_string()),
+        Value::String(value) if display_type.is_none() => Ok(value),
+        Value::Bool(value) if display_type.is_none() => Ok(value.to_string()),
+        value if display_type.is_some() => Err(format!("value {} is not a number", value)),
+        value => Err(format!("value {} is not a string or a number", value)),
+    }
+}
+
+fn validate(row: ImportRow) -> Result<ImportRow, String> {
+    if row.name.trim().is_empty() {
+        return Err("name is empty".to_string());
+    }
+    if row.image.trim().is_empty() {
+        return Err("image is empty".to_string());
+    }
+    Ok(row)
+}
+
+fn collection_and_contract(
+    conn: &mut PgConnection,
+    project: &Project,
+    import: &CollectionImport,
+) -> Result<(Collection, CollectionContract), EthosError> {
+    let collection = match collections::table
+        .filter(collections::project_id.eq(project.id))
+        .filter(collections::name.eq(&import.collection_name))
+        .first::<Collection>(conn)
+        .optional()?
+    {
+        Some(collection) => collection,
+        None => diesel::insert_into(collections::table)
+            .values((
+                collections::name.eq(&import.collection_name),
+                collections::description.eq(&import.description),
+                collections::project_id.eq(project.id),
+            ))
+            .get_result::<Collection>(conn)?,
+    };
+    let network = match networks::table
+        .filter(networks::chain_id.eq(import.chain_id))
+        .first::<Network>(conn)
+        .optional()?
+    {
+        Some(network) => network,
+        None => diesel::insert_into(networks::table)
+            .values(networks::chain_id.eq(import.chain_id))
+            .get_result::<Network>(conn)?,
+    };
+    let contract = match collection_contracts::table
+        .filter(collection_contracts::network_id.eq(network.id))
+        .filter(lower(collection_contracts::address).eq(import.contract_address.to_lowercase()))
+        .first::<CollectionContract>(conn)
+        .optional()?
+    {
+        Some(contract) if contract.collection_id != collection.id => {
+            return Err(EthosError::ImportError(format!(
+                "contract {} belongs to another collection",
+                import.contract_address
+            )))
+        }
+        Some(contract) => contract,
+        None => diesel::insert_into(collection_contracts::table)
+            .values((
+                collection_contracts::network_id.eq(network.id),
+                collection_contracts::collection_id.eq(collection.id),
+                collection_contracts::address.eq(&import.contract_address),
+                collection_contracts::fee_recipient.eq(&import.fee_recipient),
+            ))
+            .get_result::<CollectionContract>(conn)?,
+    };
+    Ok((collection, contract))
+}
+
+type AttributeColumns = (
+    Uuid,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    Option<DisplayType>,
+);
+
+/// Ids of the stored attributes the rows could reuse
+fn existing_attributes(
+    conn: &mut PgConnection,
+    rows: &[LabeledRow],
+) -> Result<HashMap<ImportAttribute, Uuid>, EthosError> {
+    let trait_types = rows
+        .iter()
+        .flat_map(|(_, row)| &row.attributes)
+        .filter_map(|attribute| attribute.trait_type.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let attributes = nft_attributes::table
+        .filter(nft_attributes::trait_type.eq_any(trait_types))
+        .or_filter(nft_attributes::trait_type.is_null())
+        .load::<AttributeColumns>(conn)?;
+    Ok(attributes.into_iter().map(attribute_entry).collect())
+}
+
+fn attribute_entry(
+    (id, trait_type, value, max_value, display_type): AttributeColumns,
+) -> (ImportAttribute, Uuid) {
+    let attribute = ImportAttribute {
+        trait_type,
+        value,
+        max_value,
+        display_type,
+    };
+    (attribute, id)
+}
+
+/// Inserts the NFTs with their attributes, creating the attributes missing
+/// from `attribute_ids`. Tokens already in the contract are skipped and
+/// returned as errors.
+fn insert_batch(
+    conn: &mut PgConnection,
+    collection_id: Uuid,
+    contract_id: Uuid,
+    batch: &[LabeledRow],
+    attribute_ids: &mut HashMap<ImportAttribute, Uuid>,
+) -> Result<(usize, Vec<RowError>), EthosError> {
+    let new_nfts = batch
+        .iter()
+        .map(|(_, row)| NewNft {
+            nft_id: row.nft_id,
+            name: row.name.clone(),
+            image: row.image.clone(),
+            description: row.description.clone(),
+            external_url: row.external_url.clone(),
+            animation_url: row.animation_url.clone(),
+            collection_id,
+            network_contract_id: contract_id,
+        })
+        .collect::<Vec<_>>();
+    // the unique index settles concurrent imports of the same token
+    let nft_ids = diesel::insert_into(nfts::table)
+        .values(new_nfts)
+        .on_conflict((nfts::network_contract_id, nfts::nft_id))
+        .do_nothing()
+        .get_results::<Nft>(conn)?
+        .into_iter()
+        .map(|nft| (nft.nft_id, nft.id))
+        .collect::<HashMap<_, _>>();
+    link_collection_benefits(conn, &nft_ids.values().copied().collect::<Vec<_>>())?;
+    let (inserted, skipped): (Vec<_>, Vec<_>) = batch
+        .iter()
+        .partition(|(_, row)| nft_ids.contains_key(&row.nft_id));
+    let skipped = skipped
+        .into_iter()
+        .map(|(label, row)| RowError {
+            row: label.clone(),
+            message: format!("token {} is already imported", row.nft_id),
+        })
+        .collect::<Vec<_>>();
+
+    let missing = inserted
+        .iter()
+        .flat_map(|(_, row)| &row.attributes)
+        .filter(|attribute| !attribute_ids.contains_key(*attribute))
+        .collect::<HashSet<_>>();
+    if !missing.is_empty() {
+        let values = missing
+            .into_iter()
+            .map(|attribute| {
+                (
+                    nft_attributes::trait_type.eq(attribute.trait_type.clone()),
+                    nft_attributes::value.eq(attribute.value.clone()),
+                    nft_attributes::max_value.eq(attribute.max_value.clone()),
+                    nft_attributes::display_type.eq(attribute.display_type),
+                )
+            })
+            .collect::<Vec<_>>();
+        let created = diesel::insert_into(nft_attributes::table)
+            .values(values)
+            .get_results::<AttributeColumns>(conn)?;
+        attribute_ids.extend(created.into_iter().map(attribute_entry));
+    }
+
+    let links = inserted
+        .iter()
+        .flat_map(|(_, row)| {
+            let nft_id = nft_ids[&row.nft_id];
+            // an NFT holds an attribute once
+            row.attributes
+                .iter()
+                .map(|attribute| attribute_ids[attribute])
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .map(move |attribute_id| {
+                    (
+                        attributes_on_nfts::nft_id.eq(nft_id),
+                        attributes_on_nfts::attribute_id.eq(attribute_id),
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+    if !links.is_empty() {
+        diesel::insert_into(attributes_on_nfts::table)
+            .values(links)
+            .execute(conn)?;
+    }
+    Ok((nft_ids.len(), skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs, path::PathBuf, sync::Arc};
+
+    use anyhow::Result;
+    use dotenvy::dotenv;
+    use ethers::types::Address;
+    use uuid::Uuid;
+
+    use crate::{
+        database::create_connection_pool,
+        events::EventBus,
+        services::{nft::NftService, project::ProjectService},
+    };
+
+    use super::{CollectionImport, ImportService, ImportSource, RowError};
+
+    fn import_dir(files: &[(&str, &str)]) -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("ethos-import-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        for (name, content) in files {
+            fs::write(dir.join(name), content)?;
+        }
+        Ok(dir)
+    }
+
+    fn collection_import(collection_name: &str) -> CollectionImport {
+        CollectionImport {
+            collection_name: collection_name.to_string(),
+            description: None,
+            chain_id: (Uuid::new_v4().as_u128() % 1_000_000) as i32,
+            contract_address: format!("{:?}", Address::random()),
+            fee_recipient: format!("{:?}", Address::random()),
+            batch_size: 2,
+        }
+    }
+
+    fn row_error(row: &str, message: &str) -> RowError {
+        RowError {
+            row: row.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_import_metadata_dir() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let nft_service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool.clone()).create_project("Import", None)?;
+        let dir = import_dir(&[
+            (
+                "1.json",
+                r#"{ "name": "Langoo! 1", "image": "ipfs://1.png", "attributes": [
+                    { "trait_type": "Tier", "value": "1" },
+                    { "trait_type": "Level", "value": 3, "display_type": "number", "max_value": 10 }
+                ] }"#,
+            ),
+            (
+                "2.json",
+                r#"{ "name": "Langoo! 2", "image": "ipfs://2.png", "attributes": [
+                    { "trait_type": "Tier", "value": "1" }
+                ] }"#,
+            ),
+            ("3", r#"{ "name": "Langoo! 3", "image": "ipfs://3.png" }"#),
+            ("4.json", r#"{ "name": "Langoo! 4" }"#),
+            ("5.json", r#"{ "name": "", "image": "ipfs://5.png" }"#),
+            (
+                "6.json",
+                r#"{ "name": "Langoo! 6", "image": "ipfs://6.png", "attributes": [
+                    { "trait_type": "Level", "value": "high", "display_type": "number" }
+                ] }"#,
+            ),
+            (
+                "cover.json",
+                r#"{ "name": "Cover", "image": "ipfs://cover.png" }"#,
+            ),
+            ("README.md", "not metadata"),
+        ])?;
+
+        let service = ImportService::new(pool);
+        let import = collection_import("Langoos");
+        let report = service.import(&project, &import, &ImportSource::from_path(&dir))?;
+        assert_eq!(report.imported, 3);
+        assert_eq!(
+            report.errors,
+            vec![
+                row_error("4.json", "missing field `image` at line 1 column 23"),
+                row_error("5.json", "name is empty"),
+                row_error("6.json", "value \"high\" is not a number"),
+                row_error("cover.json", "token id `cover` is not valid"),
+            ]
+        );
+
+        let mut nfts = nft_service.get_nfts_by_collection_id(report.collection_id)?;
+        nfts.sort_by_key(|nft| nft.nft_id);
+        assert_eq!(
+            nfts.iter().map(|nft| nft.nft_id).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        // #1 and #2 share the Tier attribute
+        let ids = nfts.iter().map(|nft| nft.id).collect::<Vec<_>>();
+        let attributes = nft_service.get_attributes_of_nfts(&ids)?;
+        assert_eq!(attributes.len(), 3);
+        let attribute_ids = attributes
+            .iter()
+            .map(|(_, attribute)| attribute.id)
+            .collect::<HashSet<_>>();
+        assert_eq!(attribute_ids.len(), 2);
+        // the collection is ranked once the NFTs are imported
+        assert_eq!(nft_service.get_rarities(&ids)?.len(), 3);
+
+        // importing again only reports the imported rows and the recipient
+        // the contract doesn't have
+        let reimport = CollectionImport {
+            fee_recipient: format!("{:?}", Address::random()),
+            ..import.clone()
+        };
+        let report = service.import(&project, &reimport, &ImportSource::from_path(&dir))?;
+        assert_eq!(report.imported, 0);
+        assert_eq!(
+            report.warnings,
+            vec![format!(
+                "fee recipient {} differs from the {} of contract {}, it's kept",
+                reimport.fee_recipient, import.fee_recipient, import.contract_address
+            )]
+        );
+        assert!(report
+            .errors
+            .iter()
+            .all(|error| !error.row.starts_with("contract")));
+        assert!(report
+            .errors
+            .contains(&row_error("1.json", "token 1 is already imported")));
+        assert!(report
+            .errors
+            .contains(&row_error("3", "token 3 is already imported")));
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_csv_manifest() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let nft_service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool.clone()).create_project("Import", None)?;
+        let dir = import_dir(&[(
+            "manifest.csv",
+            "token_id,name,image,Color,Tier\n\
+             1,Alpha,ipfs://1.png,red,1\n\
+             2,Beta,ipfs://2.png,red,\n\
+             x,Gamma,ipfs://3.png,blue,2\n\
+             1,Delta,ipfs://4.png,blue,2\n\
+             3,Echo,,blue,2\n\
+             4,Foxtrot,ipfs://4.png\n",
+        )])?;
+
+        let service = ImportService::new(pool);
+        let source = ImportSource::from_path(&dir.join("manifest.csv"));
+        let report = service.import(&project, &collection_import("Manifest"), &source)?;
+        assert_eq!(report.imported, 2);
+        let rows = report
+            .errors
+            .iter()
+            .map(|error| error.row.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec!["line 4", "line 5", "line 6", "line 7"]);
+        assert_eq!(report.errors[1].message, "token 1 is repeated");
+        assert_eq!(report.errors[2].message, "image is empty");
+
+        let nfts = nft_service.get_nfts_by_collection_id(report.collection_id)?;
+        let ids = nfts.iter().map(|nft| nft.id).collect::<Vec<_>>();
+        // both are red, only Alpha has a tier
+        let attributes = nft_service.get_attributes_of_nfts(&ids)?;
+        assert_eq!(attributes.len(), 3);
+        let attribute_ids = attributes
+            .iter()
+            .map(|(_, attribute)| attribute.id)
+            .collect::<HashSet<_>>();
+        assert_eq!(attribute_ids.len(), 2);
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap},
+    env,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::upsert::excluded;
+use ethers::{types::Address, utils::to_checksum};
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::{
+    chain::erc721::{decode_transfer, TransferLog, TransferLogSource},
+    database::ConnectionPool,
+    errors::EthosError,
+    events::{Event, EventBus},
+    schema::{collection_contracts, contract_checkpoints, networks, nfts, transfers},
+};
+
+use super::{
+    nft::{record_transfer, CollectionContract, Network},
+    wallet::WalletService,
+};
+
+// blocks a log must be buried under before it's indexed, so reorgs don't reach it
+const DEFAULT_CONFIRMATIONS: u64 = 12;
+// blocks requested per eth_getLogs call
+const DEFAULT_BLOCK_RANGE: u64 = 2000;
+
+/// Keeps the owner and mint state of NFTs in sync with the `Transfer` logs of
+/// their contracts.
+pub struct IndexerService {
+    pool: ConnectionPool,
+    logs: Arc<dyn TransferLogSource>,
+    wallet_service: Arc<WalletService>,
+    events: Arc<EventBus>,
+    confirmations: u64,
+    block_range: u64,
+}
+
+impl IndexerService {
+    pub fn new(
+        pool: Pool<ConnectionManager<PgConnection>>,
+        logs: Arc<dyn TransferLogSource>,
+        wallet_service: Arc<WalletService>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        let confirmations = env::var("INDEXER_CONFIRMATIONS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_CONFIRMATIONS);
+        let block_range = env::var("INDEXER_BLOCK_RANGE")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_BLOCK_RANGE);
+        Self {
+            pool: ConnectionPool::new(pool),
+            logs,
+            wallet_service,
+            events,
+            confirmations,
+            block_range: block_range.max(1),
+        }
+    }
+
+    /// Syncs every contract on each tick, until the task is dropped
+    pub async fn run(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = self.sync().await {
+                println!("Failed to index transfers: {}", err);
+            }
+        }
+    }
+
+    pub async fn sync(&self) -> Result<(), EthosError> {
+        let contracts = {
+            let mut conn = self.pool.get()?;
+            collection_contracts::table
+                .inner_join(networks::table)
+                .load::<(CollectionContract, Network)>(&mut conn)?
+        };
+
+        for (contract, network) in contracts {
+            match self.sync_contract(&contract, &network).await {
+                Ok(_) | Err(EthosError::NetworkNotConfigured(_)) => {}
+                Err(err) => println!(
+                    "Failed to index contract {} on chain {}: {}",
+                    contract.address, network.chain_id, err
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Indexes the confirmed blocks of a contract since its checkpoint,
+    /// returning the new checkpoint
+    pub async fn sync_contract(
+        &self,
+        contract: &CollectionContract,
+        network: &Network,
+    ) -> Result<Option<u64>, EthosError> {
+        let chain_id = network.chain_id as u64;
+        let address = Address::from_str(&contract.address)?;
+        let latest = self.logs.block_number(chain_id).await?;
+        let Some(safe) = latest.checked_sub(self.confirmations) else {
+            return Ok(None);
+        };
+
+        let mut checkpoint = self.get_checkpoint(contract)?;
+        let mut from = match checkpoint {
+            Some(block) => block + 1,
+            None => contract.deployed_at_block.unwrap_or(0) as u64,
+        };
+        while from <= safe {
+            let to = safe.min(from + self.block_range - 1);
+            let logs = self.logs.transfer_logs(chain_id, address, from, to).await?;
+            let mut transfers: Vec<TransferLog> = logs.iter().filter_map(decode_transfer).collect();
+            transfers.sort_by_key(|transfer| {
+                (transfer.position.block_number, transfer.position.log_index)
+            });
+            // NFTs are minted at the time of their block
+            let mint_blocks = transfers
+                .iter()
+                .filter(|transfer| transfer.is_mint())
+                .map(|transfer| transfer.position.block_number as u64)
+                .collect::<BTreeSet<u64>>();
+            let mut block_times = HashMap::new();
+            for block in mint_blocks {
+                let time = self.logs.block_timestamp(chain_id, block).await?;
+                block_times.insert(block as i64, time);
+            }
+            self.apply_transfers(contract, &transfers, &block_times, to)?;
+            checkpoint = Some(to);
+            from = to + 1;
+        }
+        Ok(checkpoint)
+    }
+
+    pub fn get_checkpoint(&self, contract: &CollectionContract) -> Result<Option<u64>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = contract_checkpoints::table
+            .find(contract.id)
+            .select(contract_checkpoints::block_number)
+            .first::<i64>(&mut conn)
+            .optional()?;
+        Ok(result.map(|block| block as u64))
+    }
+
+    /// Applies a batch of transfers and moves the checkpoint in one transaction,
+    /// so a failed batch is retried from the start
+    fn apply_transfers(
+        &self,
+        contract: &CollectionContract,
+        transfers: &[TransferLog],
+        block_times: &HashMap<i64, NaiveDateTime>,
+        checkpoint: u64,
+    ) -> Result<(), EthosError> {
+        // wallets are created before the transaction, they are harmless if it
+        // fails. burns are transfers to the wallet of the zero address
+        let mut owners = HashMap::new();
+        for transfer in transfers {
+            if let Entry::Vacant(entry) = owners.entry(transfer.to) {
+                entry.insert(self.wallet_service.upsert_wallet(transfer.to)?.id);
+            }
+        }
+
+        let mut conn = self.pool.get()?;
+        let events = conn.transaction::<_, EthosError, _>(|conn| {
+            let mut events = vec![];
+            for transfer in transfers {
+                let minted_at = block_times.get(&transfer.position.block_number).copied();
+                events.extend(apply_transfer(
+                    conn,
+                    contract,
+                    transfer,
+                    owners[&transfer.to],
+                    minted_at,
+                )?);
+            }
+            diesel::insert_into(contract_checkpoints::table)
+                .values((
+                    contract_checkpoints::collection_contract_id.eq(contract.id),
+                    contract_checkpoints::block_number.eq(checkpoint as i64),
+                ))
+                .on_conflict(contract_checkpoints::collection_contract_id)
+                .do_update()
+                .set(
+                    contract_checkpoints::block_number
+                        .eq(excluded(contract_checkpoints::block_number)),
+                )
+                .execute(conn)?;
+            Ok(events)
+        })?;
+
+        // only committed transfers are published
+        for event in events {
+            self.events.publish(event);
+        }
+        Ok(())
+    }
+}
+
+fn apply_transfer(
+    conn: &mut PgConnection,
+    contract: &CollectionContract,
+    transfer: &TransferLog,
+    owner: Uuid,
+    minted_at: Option<NaiveDateTime>,
+) -> Result<Option<Event>, EthosError> {
+    let Ok(token_id) = i32::try_from(transfer.token_id) else {
+        println!(
+            "Token id {} of {} is out of range",
+            transfer.token_id, contract.address
+        );
+        return Ok(None);
+    };
+    let nft = nfts::table
+        .filter(nfts::network_contract_id.eq(contract.id))
+        .filter(nfts::nft_id.eq(token_id))
+        .select(nfts::id)
+        .first::<Uuid>(conn)
+        .optional()?;
+    let Some(nft) = nft else {
+        println!(
+            "Token {} of {} is not registered",
+            token_id, contract.address
+        );
+        return Ok(None);
+    };
+
+    let indexed = diesel::select(diesel::dsl::exists(
+        transfers::table
+            .filter(transfers::tx_hash.eq(&transfer.position.tx_hash))
+            .filter(transfers::log_index.eq(transfer.position.log_index)),
+    ))
+    .get_result::<bool>(conn)?;
+    if indexed {
+        return Ok(None);
+    }
+
+    if let Some(minted_at) = minted_at.filter(|_| transfer.is_mint()) {
+        diesel::update(nfts::table.find(nft))
+            .filter(nfts::minted_at.is_null())
+            .set(nfts::minted_at.eq(minted_at))
+            .execute(conn)?;
+    }
+    let result = record_transfer(conn, nft, owner, Some(transfer.position.clone()))?;
+    // a burned NFT has no owner, its history ends with the zero address
+    if transfer.is_burn() {
+        diesel::update(nfts::table.find(nft))
+            .set(nfts::owner_id.eq(None::<Uuid>))
+            .execute(conn)?;
+    }
+    Ok(Some(result.to_event(&to_checksum(&transfer.to, None))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    use anyhow::Result;
+    use async_graphql::{async_trait, futures_util::StreamExt};
+    use chrono::NaiveDateTime;
+    use diesel::prelude::*;
+    use dotenvy::dotenv;
+    use ethers::{
+        providers::Http,
+        types::{Address, Log, H256, U256, U64},
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        chain::{erc721::transfer_topic, erc721::TransferLogSource, providers::ChainProviders},
+        database::{create_connection_pool, ConnectionPool},
+        errors::EthosError,
+        events::{Event, EventBus},
+        schema::nfts,
+        services::{
+            nft::{CollectionContract, Network, NewNft, Nft, NftService},
+            project::{Project, ProjectService},
+            wallet::WalletService,
+        },
+    };
+
+    use super::{IndexerService, DEFAULT_CONFIRMATIONS};
+
+    /// Replays recorded logs up to the current block
+    struct RecordedLogs {
+        latest: AtomicU64,
+        logs: Vec<Log>,
+    }
+
+    #[async_trait::async_trait]
+    impl TransferLogSource for RecordedLogs {
+        async fn block_number(&self, _chain_id: u64) -> Result<u64, EthosError> {
+            Ok(self.latest.load(Ordering::SeqCst))
+        }
+
+        async fn transfer_logs(
+            &self,
+            _chain_id: u64,
+            contract: Address,
+            from_block: u64,
+            to_block: u64,
+        ) -> Result<Vec<Log>, EthosError> {
+            Ok(self
+                .logs
+                .iter()
+                .filter(|log| log.address == contract)
+                .filter(|log| {
+                    let block = log.block_number.unwrap().as_u64();
+                    from_block <= block && block <= to_block
+                })
+                .cloned()
+                .collect())
+        }
+
+        async fn block_timestamp(
+            &self,
+            _chain_id: u64,
+            block: u64,
+        ) -> Result<NaiveDateTime, EthosError> {
+            Ok(block_time(block))
+        }
+    }
+
+    /// Blocks are mined every 12 seconds
+    fn block_time(block: u64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp_opt(1_700_000_000 + block as i64 * 12, 0).unwrap()
+    }
+
+    /// Creates token #1 of a contract on a new network
+    fn create_token(
+        service: &NftService,
+        project: &Project,
+    ) -> Result<(CollectionContract, Network, Nft)> {
+        let network = service.create_network((Uuid::new_v4().as_u128() % 1_000_000) as i32)?;
+        let collection = service.create_collection(project, "Indexer", None)?;
+        let contract = service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        let nft = NewNft {
+            nft_id: 1,
+            name: "Indexer #1".to_string(),
+            image: String::new(),
+            description: String::new(),
+            external_url: String::new(),
+            animation_url: String::new(),
+            collection_id: collection.id,
+            network_contract_id: contract.id,
+        };
+        let nft = service.create_nfts(vec![nft])?.remove(0);
+        Ok((contract, network, nft))
+    }
+
+    fn transfer_log(contract: Address, from: Address, to: Address, block: u64) -> Log {
+        Log {
+            address: contract,
+            topics: vec![
+                transfer_topic(),
+                H256::from(from),
+                H256::from(to),
+                H256::from_low_u64_be(1),
+            ],
+            block_number: Some(U64::from(block)),
+            transaction_hash: Some(H256::random()),
+            log_index: Some(U256::zero()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_index_transfers() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let events = Arc::new(EventBus::default());
+        let nft_service = NftService::new(pool.clone(), events.clone());
+        let wallet_service = Arc::new(WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        ));
+        let project = ProjectService::new(pool.clone()).create_project("Indexer", None)?;
+        let (contract, network, nft) = create_token(&nft_service, &project)?;
+
+        let mut conn = pool.get()?;
+        let address = contract.address.parse::<Address>()?;
+        let (alice, bob, carol) = (Address::random(), Address::random(), Address::random());
+
+        let source = Arc::new(RecordedLogs {
+            latest: AtomicU64::new(15 + DEFAULT_CONFIRMATIONS),
+            logs: vec![
+                transfer_log(address, Address::zero(), alice, 10),
+                transfer_log(address, alice, bob, 12),
+                transfer_log(address, bob, carol, 18),
+                transfer_log(address, carol, Address::zero(), 19),
+                // other contracts are not indexed
+                transfer_log(Address::random(), Address::zero(), carol, 11),
+            ],
+        });
+        let mut transfers = Box::pin(events.subscribe());
+        let indexer =
+            IndexerService::new(pool.clone(), source.clone(), wallet_service.clone(), events);
+
+        // the last transfer doesn't have enough confirmations yet
+        assert_eq!(indexer.sync_contract(&contract, &network).await?, Some(15));
+        let (owner, minted_at) = nfts::table
+            .find(nft.id)
+            .select((nfts::owner_id, nfts::minted_at))
+            .first::<(Option<Uuid>, Option<NaiveDateTime>)>(&mut conn)?;
+        assert_eq!(owner, Some(wallet_service.get_wallet(&bob)?.id));
+        // minted when the block of the mint was
+        assert_eq!(minted_at, Some(block_time(10)));
+        // both transfers were written by the same batch, newest first
+        let history = nft_service.get_nft_history(&nft)?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            history
+                .iter()
+                .map(|transfer| transfer.block_number)
+                .collect::<Vec<_>>(),
+            vec![Some(12), Some(10)]
+        );
+
+        source
+            .latest
+            .store(20 + DEFAULT_CONFIRMATIONS, Ordering::SeqCst);
+        assert_eq!(indexer.sync_contract(&contract, &network).await?, Some(20));
+        // the burned NFT has no owner, its last transfer is to the zero address
+        let owner = nfts::table
+            .find(nft.id)
+            .select(nfts::owner_id)
+            .first::<Option<Uuid>>(&mut conn)?;
+        assert_eq!(owner, None);
+        let history = nft_service
+            .get_nft_history(&nft)?
+            .into_iter()
+            .map(|transfer| transfer.to_id)
+            .collect::<Vec<_>>();
+        let wallet_id = |address| wallet_service.get_wallet(&address).map(|wallet| wallet.id);
+        assert_eq!(
+            history,
+            vec![
+                wallet_id(Address::zero())?,
+                wallet_id(carol)?,
+                wallet_id(bob)?,
+                wallet_id(alice)?
+            ]
+        );
+
+        // indexed transfers are published to subscribers
+        let mut owners = vec![];
+        for _ in 0..4 {
+            let Some(Event::Transfer(event)) = transfers.next().await else {
+                panic!("expected a transfer event");
+            };
+            owners.push(event.to_address.parse::<Address>()?);
+        }
+        assert_eq!(owners, vec![alice, bob, carol, Address::zero()]);
+
+        Ok(())
+    }
+}
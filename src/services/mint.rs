@@ -0,0 +1,802 @@
+use std::{env, str::FromStr, sync::Arc, time::Duration};
+
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::{Identifiable, PgConnection, Queryable};
+use diesel_derive_enum::DbEnum;
+use ethers::{
+    providers::{Http, JsonRpcClient, Middleware, Provider},
+    signers::Signer,
+    types::{
+        transaction::eip2718::TypedTransaction, Address, Bytes, TransactionReceipt,
+        TransactionRequest, H256, U256,
+    },
+};
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::{
+    chain::{
+        erc721::{decode_transfer, encode_mint},
+        nonce::NonceManager,
+        providers::ChainProviders,
+        signers::ChainSigners,
+    },
+    database::ConnectionPool,
+    errors::EthosError,
+    events::EventBus,
+    schema::{collection_contracts, mint_jobs, networks, nfts, transfers, wallets},
+};
+
+use super::nft::{record_transfer, Nft};
+
+// blocks the mint transaction must be buried under to be confirmed
+const DEFAULT_CONFIRMATIONS: u64 = 3;
+// gas price increase of a resubmitted transaction, nodes require at least 10%
+const DEFAULT_GAS_BUMP_PERCENT: u64 = 20;
+// time a transaction can stay unmined before it's resubmitted
+const DEFAULT_RESUBMIT_AFTER_SECONDS: i64 = 180;
+// jobs failing this many times are given up
+const MAX_ATTEMPTS: i32 = 5;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::MintJobState"]
+pub enum MintJobState {
+    /// Waiting for the transaction to be sent
+    Pending,
+    /// Transaction was sent, waiting for confirmations
+    Submitted,
+    /// NFT was minted to the wallet
+    Confirmed,
+    /// Transaction reverted or could not be sent
+    Failed,
+}
+
+#[derive(Debug, Queryable, Identifiable, Associations)]
+#[diesel(belongs_to(Nft))]
+#[diesel(table_name = mint_jobs)]
+pub struct MintJob {
+    pub id: Uuid,
+    pub state: MintJobState,
+    pub nonce: Option<i64>,
+    pub tx_hashes: Vec<Option<String>>,
+    pub gas_price: Option<String>,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub submitted_at: Option<chrono::NaiveDateTime>,
+    pub confirmed_at: Option<chrono::NaiveDateTime>,
+    pub nft_id: Uuid,
+    pub wallet_id: Uuid,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// What the mint transaction of a job is built from
+#[derive(Debug, Queryable)]
+pub struct MintTarget {
+    pub chain_id: i32,
+    pub contract: String,
+    pub token_id: i32,
+    pub recipient: String,
+}
+
+/// Queues the mint of the NFT to the wallet
+pub fn enqueue_mint(
+    conn: &mut PgConnection,
+    nft: Uuid,
+    wallet: Uuid,
+) -> Result<MintJob, EthosError> {
+    let result = diesel::insert_into(mint_jobs::table)
+        .values((mint_jobs::nft_id.eq(nft), mint_jobs::wallet_id.eq(wallet)))
+        .get_result::<MintJob>(conn)?;
+    Ok(result)
+}
+
+/// Sends the mint transactions of the queued jobs and follows them until they
+/// are confirmed
+pub struct MintService<P = Http> {
+    pool: ConnectionPool,
+    providers: Arc<ChainProviders<P>>,
+    signers: ChainSigners,
+    nonces: NonceManager,
+    events: Arc<EventBus>,
+    confirmations: u64,
+    gas_bump_percent: u64,
+    resubmit_after: chrono::Duration,
+}
+
+impl<P: JsonRpcClient> MintService<P> {
+    pub fn new(
+        pool: Pool<ConnectionManager<PgConnection>>,
+        providers: Arc<ChainProviders<P>>,
+        signers: ChainSigners,
+        events: Arc<EventBus>,
+    ) -> Self {
+        let confirmations = env::var("MINT_CONFIRMATIONS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_CONFIRMATIONS);
+        let gas_bump_percent = env::var("MINT_GAS_BUMP_PERCENT")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_GAS_BUMP_PERCENT);
+        let resubmit_after = env::var("MINT_RESUBMIT_AFTER_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_RESUBMIT_AFTER_SECONDS);
+        Self {
+            pool: ConnectionPool::new(pool),
+            providers,
+            signers,
+            nonces: NonceManager::new(),
+            events,
+            confirmations,
+            gas_bump_percent,
+            resubmit_after: chrono::Duration::seconds(resubmit_after),
+        }
+    }
+
+    /// Processes the jobs on each tick, until the task is dropped
+    pub async fn run(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = self.process().await {
+                println!("Failed to process mint jobs: {}", err);
+            }
+        }
+    }
+
+    pub async fn process(&self) -> Result<(), EthosError> {
+        self.submit_pending().await?;
+        self.check_submitted().await
+    }
+
+    pub async fn submit_pending(&self) -> Result<(), EthosError> {
+        for (job, target) in self.get_jobs(MintJobState::Pending)? {
+            if let Err(err) = self.submit(&job, &target).await {
+                println!("Failed to submit mint job {}: {}", job.id, err);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn check_submitted(&self) -> Result<(), EthosError> {
+        for (job, target) in self.get_jobs(MintJobState::Submitted)? {
+            if let Err(err) = self.check(&job, &target).await {
+                println!("Failed to check mint job {}: {}", job.id, err);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_job(&self, id: Uuid) -> Result<MintJob, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = mint_jobs::table.find(id).first::<MintJob>(&mut conn)?;
+        Ok(result)
+    }
+
+    fn get_jobs(&self, state: MintJobState) -> Result<Vec<(MintJob, MintTarget)>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = mint_jobs::table
+            .inner_join(wallets::table)
+            .inner_join(
+                nfts::table.inner_join(collection_contracts::table.inner_join(networks::table)),
+            )
+            .filter(mint_jobs::state.eq(state))
+            .order(mint_jobs::created_at.asc())
+            .select((
+                mint_jobs::all_columns,
+                (
+                    networks::chain_id,
+                    collection_contracts::address,
+                    nfts::nft_id,
+                    wallets::address,
+                ),
+            ))
+            .load::<(MintJob, MintTarget)>(&mut conn)?;
+        Ok(result)
+    }
+
+    async fn submit(&self, job: &MintJob, target: &MintTarget) -> Result<(), EthosError> {
+        let chain_id = target.chain_id as u64;
+        let provider = self.providers.get(chain_id)?;
+        let address = self.signers.get(chain_id)?.address();
+        let nonce = self.nonces.next(chain_id, address, &provider).await?;
+
+        let signed = async {
+            let gas_price = provider.get_gas_price().await?;
+            let (tx_hash, raw) = self
+                .sign(&provider, chain_id, target, nonce, gas_price)
+                .await?;
+            Ok::<_, EthosError>((tx_hash, raw, gas_price))
+        }
+        .await;
+        let (tx_hash, raw, gas_price) = match signed {
+            Ok(signed) => signed,
+            Err(err) => {
+                // the nonce was not used, the next transaction must take it
+                self.nonces.reset(chain_id);
+                return self.record_failure(job, err, true);
+            }
+        };
+
+        // the job takes the nonce and the hash before the transaction is
+        // sent, from then on `check` follows it and sends it again with the
+        // same nonce, so it's never sent with another one
+        let recorded = self.pool.get().and_then(|mut conn| {
+            diesel::update(job)
+                .set((
+                    mint_jobs::state.eq(MintJobState::Submitted),
+                    mint_jobs::nonce.eq(nonce.as_u64() as i64),
+                    mint_jobs::tx_hashes.eq(vec![Some(format!("{:?}", tx_hash))]),
+                    mint_jobs::gas_price.eq(gas_price.to_string()),
+                    mint_jobs::attempts.eq(job.attempts + 1),
+                    mint_jobs::error.eq(None::<String>),
+                    mint_jobs::submitted_at.eq(diesel::dsl::now),
+                ))
+                .execute(&mut conn)?;
+            Ok(conn)
+        });
+        let mut conn = match recorded {
+            Ok(conn) => conn,
+            Err(err) => {
+                self.nonces.reset(chain_id);
+                return Err(err);
+            }
+        };
+        if let Err(err) = provider.send_raw_transaction(raw).await {
+            let err = EthosError::from(err);
+            diesel::update(job)
+                .set(mint_jobs::error.eq(err.to_string()))
+                .execute(&mut conn)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Confirms the job once one of its transactions is mined, resubmitting it
+    /// with a higher gas price when it takes too long
+    async fn check(&self, job: &MintJob, target: &MintTarget) -> Result<(), EthosError> {
+        let chain_id = target.chain_id as u64;
+        let provider = self.providers.get(chain_id)?;
+
+        for tx_hash in job.tx_hashes.iter().flatten() {
+            let tx_hash = H256::from_str(tx_hash)?;
+            if let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? {
+                return self.settle(job, target, &provider, receipt).await;
+            }
+        }
+
+        let stale = job
+            .submitted_at
+            .map(|submitted_at| {
+                submitted_at + self.resubmit_after <= chrono::Utc::now().naive_utc()
+            })
+            .unwrap_or(true);
+        if stale {
+            return self.resubmit(job, target, &provider).await;
+        }
+        Ok(())
+    }
+
+    async fn settle(
+        &self,
+        job: &MintJob,
+        target: &MintTarget,
+        provider: &Provider<P>,
+        receipt: TransactionReceipt,
+    ) -> Result<(), EthosError> {
+        let Some(block) = receipt.block_number else {
+            return Ok(());
+        };
+        let latest = provider.get_block_number().await?;
+        if latest.as_u64() + 1 < block.as_u64() + self.confirmations {
+            return Ok(());
+        }
+
+        let mut conn = self.pool.get()?;
+        if receipt.status == Some(0.into()) {
+            diesel::update(job)
+                .set((
+                    mint_jobs::state.eq(MintJobState::Failed),
+                    mint_jobs::error.eq(format!(
+                        "transaction {:?} reverted",
+                        receipt.transaction_hash
+                    )),
+                ))
+                .execute(&mut conn)?;
+            return Ok(());
+        }
+
+        let log = receipt
+            .logs
+            .iter()
+            .filter_map(decode_transfer)
+            .find(|log| log.is_mint() && log.token_id == U256::from(target.token_id as u64));
+        let transfer = conn.transaction::<_, EthosError, _>(|conn| {
+            diesel::update(job)
+                .set((
+                    mint_jobs::state.eq(MintJobState::Confirmed),
+                    mint_jobs::confirmed_at.eq(diesel::dsl::now),
+                ))
+                .execute(conn)?;
+            diesel::update(nfts::table.find(job.nft_id))
+                .filter(nfts::minted_at.is_null())
+                .set(nfts::minted_at.eq(diesel::dsl::now))
+                .execute(conn)?;
+
+            // the indexer may have recorded the transfer already
+            if let Some(log) = &log {
+                let indexed = diesel::select(diesel::dsl::exists(
+                    transfers::table
+                        .filter(transfers::tx_hash.eq(&log.position.tx_hash))
+                        .filter(transfers::log_index.eq(log.position.log_index)),
+                ))
+                .get_result::<bool>(conn)?;
+                if indexed {
+                    return Ok(None);
+                }
+            }
+            let position = log.as_ref().map(|log| log.position.clone());
+            Ok(Some(record_transfer(
+                conn,
+                job.nft_id,
+                job.wallet_id,
+                position,
+            )?))
+        })?;
+
+        if let Some(transfer) = transfer {
+            self.events.publish(transfer.to_event(&target.recipient));
+        }
+        Ok(())
+    }
+
+    /// Sends the transaction again with the same nonce and a higher gas price
+    async fn resubmit(
+        &self,
+        job: &MintJob,
+        target: &MintTarget,
+        provider: &Provider<P>,
+    ) -> Result<(), EthosError> {
+        let Some(nonce) = job.nonce else {
+            return Ok(());
+        };
+        let previous = job
+            .gas_price
+            .as_deref()
+            .and_then(|gas_price| U256::from_dec_str(gas_price).ok())
+            .unwrap_or_default();
+        let bumped = previous * (100 + self.gas_bump_percent) / 100;
+        let gas_price = provider.get_gas_price().await?.max(bumped);
+
+        let chain_id = target.chain_id as u64;
+        let sent = async {
+            let (tx_hash, raw) = self
+                .sign(
+                    provider,
+                    chain_id,
+                    target,
+                    U256::from(nonce as u64),
+                    gas_price,
+                )
+                .await?;
+            // recorded before it's sent, so a mined transaction is always known
+            let mut tx_hashes = job.tx_hashes.clone();
+            tx_hashes.push(Some(format!("{:?}", tx_hash)));
+            let mut conn = self.pool.get()?;
+            diesel::update(job)
+                .set((
+                    mint_jobs::tx_hashes.eq(tx_hashes),
+                    mint_jobs::gas_price.eq(gas_price.to_string()),
+                    mint_jobs::attempts.eq(job.attempts + 1),
+                    mint_jobs::submitted_at.eq(diesel::dsl::now),
+                ))
+                .execute(&mut conn)?;
+            provider.send_raw_transaction(raw).await?;
+            Ok::<_, EthosError>(())
+        }
+        .await;
+        match sent {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                // e.g. `nonce too low` when a previous transaction was mined
+                // since the job was checked
+                for tx_hash in job.tx_hashes.iter().flatten() {
+                    let tx_hash = H256::from_str(tx_hash)?;
+                    if let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? {
+                        return self.settle(job, target, provider, receipt).await;
+                    }
+                }
+                let dropped = self.is_dropped(job, chain_id, provider).await?;
+                if dropped && job.attempts + 1 >= MAX_ATTEMPTS {
+                    self.nonces.reset(chain_id);
+                }
+                self.record_failure(job, err, dropped)
+            }
+        }
+    }
+
+    /// Builds and signs the mint transaction, returning its hash and the
+    /// signed transaction to send
+    async fn sign(
+        &self,
+        provider: &Provider<P>,
+        chain_id: u64,
+        target: &MintTarget,
+        nonce: U256,
+        gas_price: U256,
+    ) -> Result<(H256, Bytes), EthosError> {
+        let signer = self.signers.get(chain_id)?;
+        let recipient = Address::from_str(&target.recipient)?;
+        let contract = Address::from_str(&target.contract)?;
+        let mut tx: TypedTransaction = TransactionRequest::new()
+            .from(signer.address())
+            .to(contract)
+            .data(encode_mint(recipient, U256::from(target.token_id as u64)))
+            .nonce(nonce)
+            .gas_price(gas_price)
+            .chain_id(chain_id)
+            .into();
+        let gas = provider.estimate_gas(&tx, None).await?;
+        tx.set_gas(gas);
+
+        let signature = signer.sign_transaction(&tx).await?;
+        Ok((tx.hash(&signature), tx.rlp_signed(&signature)))
+    }
+
+    /// Whether none of the transactions sent for the job can be mined anymore:
+    /// the node doesn't know any of them and their nonce is still unused
+    async fn is_dropped(
+        &self,
+        job: &MintJob,
+        chain_id: u64,
+        provider: &Provider<P>,
+    ) -> Result<bool, EthosError> {
+        let Some(nonce) = job.nonce else {
+            return Ok(true);
+        };
+        let address = self.signers.get(chain_id)?.address();
+        let used = provider.get_transaction_count(address, None).await?;
+        if used > U256::from(nonce as u64) {
+            return Ok(false);
+        }
+        for tx_hash in job.tx_hashes.iter().flatten() {
+            let tx_hash = H256::from_str(tx_hash)?;
+            if provider.get_transaction(tx_hash).await?.is_some() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Counts a failed attempt of the job. Jobs whose transactions may still
+    /// be mined are never given up, otherwise the NFT could be minted twice.
+    fn record_failure(
+        &self,
+        job: &MintJob,
+        err: EthosError,
+        dropped: bool,
+    ) -> Result<(), EthosError> {
+        let mut conn = self.pool.get()?;
+        let attempts = job.attempts + 1;
+        let state = if attempts >= MAX_ATTEMPTS && dropped {
+            MintJobState::Failed
+        } else {
+            job.state
+        };
+        diesel::update(job)
+            .set((
+                mint_jobs::state.eq(state),
+                mint_jobs::attempts.eq(attempts),
+                mint_jobs::error.eq(err.to_string()),
+            ))
+            .execute(&mut conn)?;
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use async_graphql::futures_util::StreamExt;
+    use diesel::prelude::*;
+    use dotenvy::dotenv;
+    use ethers::{
+        providers::{Http, Provider},
+        signers::LocalWallet,
+        types::{Address, Log, TransactionReceipt, H256, U256, U64},
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        chain::{erc721::transfer_topic, providers::ChainProviders, signers::ChainSigners},
+        database::{create_connection_pool, ConnectionPool},
+        events::{Event, EventBus},
+        schema::mint_jobs,
+        services::{
+            nft::{NewNft, Nft, NftService},
+            project::{Project, ProjectService},
+            wallet::WalletService,
+        },
+    };
+
+    use super::{enqueue_mint, MintJobState, MintService, MAX_ATTEMPTS};
+
+    const MINTER_KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn minter(chain_id: i32) -> Result<ChainSigners> {
+        let mut signers = ChainSigners::new();
+        signers.insert(chain_id as u64, MINTER_KEY.parse::<LocalWallet>()?);
+        Ok(signers)
+    }
+
+    /// Creates `count` NFTs of a contract on a new chain, returned with the
+    /// chain they are minted on
+    fn create_nfts(service: &NftService, project: &Project, count: i32) -> Result<(i32, Vec<Nft>)> {
+        let chain_id = (Uuid::new_v4().as_u128() % 1_000_000) as i32;
+        let network = service.create_network(chain_id)?;
+        let collection = service.create_collection(project, "Minter", None)?;
+        let contract = service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        let new_nfts = (1..=count)
+            .map(|nft_id| NewNft {
+                nft_id,
+                name: format!("Minter #{}", nft_id),
+                image: String::new(),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: collection.id,
+                network_contract_id: contract.id,
+            })
+            .collect();
+        Ok((chain_id, service.create_nfts(new_nfts)?))
+    }
+
+    #[tokio::test]
+    async fn test_mint_lifecycle() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let events = Arc::new(EventBus::default());
+        let nft_service = NftService::new(pool.clone(), events.clone());
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let project = ProjectService::new(pool.clone()).create_project("Minter", None)?;
+        let (chain_id, mut nfts) = create_nfts(&nft_service, &project, 1)?;
+        let nft = nfts.remove(0);
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+
+        let mut conn = pool.get()?;
+        let job = enqueue_mint(&mut conn, nft.id, wallet.id)?;
+        // an NFT is minted only once
+        assert!(enqueue_mint(&mut conn, nft.id, wallet.id).is_err());
+
+        let (provider, mock) = Provider::mocked();
+        let mut providers = ChainProviders::new();
+        providers.insert(chain_id as u64, provider);
+        let mut service = MintService::new(
+            pool.clone(),
+            Arc::new(providers),
+            minter(chain_id)?,
+            events.clone(),
+        );
+        let mut transfers = Box::pin(events.subscribe());
+
+        // responses are served last in, first out
+        mock.push(H256::random())?;
+        mock.push(U256::from(21_000))?;
+        mock.push(U256::from(100))?;
+        mock.push(U256::from(7))?;
+        service.submit_pending().await?;
+        let submitted = service.get_job(job.id)?;
+        assert_eq!(submitted.state, MintJobState::Submitted);
+        assert_eq!(submitted.nonce, Some(7));
+        assert_eq!(submitted.tx_hashes.len(), 1);
+
+        // not mined and not stale yet
+        mock.push(None::<TransactionReceipt>)?;
+        service.check_submitted().await?;
+        assert_eq!(service.get_job(job.id)?.tx_hashes.len(), 1);
+
+        // stale transactions are resubmitted with a bumped gas price
+        service.resubmit_after = chrono::Duration::zero();
+        let second_hash = H256::random();
+        mock.push(second_hash)?;
+        mock.push(U256::from(21_000))?;
+        mock.push(U256::from(110))?;
+        mock.push(None::<TransactionReceipt>)?;
+        service.check_submitted().await?;
+        let resubmitted = service.get_job(job.id)?;
+        assert_eq!(resubmitted.state, MintJobState::Submitted);
+        assert_eq!(resubmitted.nonce, Some(7));
+        assert_eq!(resubmitted.gas_price.as_deref(), Some("120"));
+        assert_eq!(resubmitted.tx_hashes.len(), 2);
+
+        let receipt = TransactionReceipt {
+            transaction_hash: second_hash,
+            block_number: Some(U64::from(10)),
+            status: Some(U64::from(1)),
+            logs: vec![Log {
+                topics: vec![
+                    transfer_topic(),
+                    H256::zero(),
+                    H256::from(wallet.address.parse::<Address>()?),
+                    H256::from_low_u64_be(nft.nft_id as u64),
+                ],
+                block_number: Some(U64::from(10)),
+                transaction_hash: Some(second_hash),
+                log_index: Some(U256::zero()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        mock.push(U64::from(12))?;
+        mock.push(receipt)?;
+        mock.push(None::<TransactionReceipt>)?;
+        service.check_submitted().await?;
+
+        let confirmed = service.get_job(job.id)?;
+        assert_eq!(confirmed.state, MintJobState::Confirmed);
+        assert!(confirmed.confirmed_at.is_some());
+        let minted = nft_service.get_nft(nft.id)?;
+        assert!(minted.minted_at.is_some());
+        assert_eq!(minted.owner_id, Some(wallet.id));
+        let history = nft_service.get_nft_history(&nft)?;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].tx_hash, Some(format!("{:?}", second_hash)));
+
+        let Some(Event::Transfer(event)) = transfers.next().await else {
+            panic!("expected a transfer event");
+        };
+        assert_eq!(event.nft_id, nft.id);
+        assert_eq!(event.to, wallet.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resubmit_failure() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let events = Arc::new(EventBus::default());
+        let nft_service = NftService::new(pool.clone(), events.clone());
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let project = ProjectService::new(pool.clone()).create_project("Resubmit", None)?;
+        let (chain_id, mut nfts) = create_nfts(&nft_service, &project, 2)?;
+        let (nft, dropped_nft) = (nfts.remove(0), nfts.remove(0));
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+
+        let mut conn = pool.get()?;
+        let (provider, mock) = Provider::mocked();
+        let mut providers = ChainProviders::new();
+        providers.insert(chain_id as u64, provider);
+        let mut service =
+            MintService::new(pool.clone(), Arc::new(providers), minter(chain_id)?, events);
+        service.resubmit_after = chrono::Duration::zero();
+        let give_up = |job| -> Result<usize> {
+            Ok(diesel::update(mint_jobs::table.find(job))
+                .set(mint_jobs::attempts.eq(MAX_ATTEMPTS))
+                .execute(&mut pool.get()?)?)
+        };
+
+        // responses are served last in, first out
+        let job = enqueue_mint(&mut conn, nft.id, wallet.id)?;
+        let tx_hash = H256::random();
+        mock.push(tx_hash)?;
+        mock.push(U256::from(21_000))?;
+        mock.push(U256::from(100))?;
+        mock.push(U256::from(7))?;
+        service.submit_pending().await?;
+        give_up(job.id)?;
+
+        // the resubmission is refused but the nonce is used, so the first
+        // transaction may be mined
+        mock.push(U256::from(8))?;
+        mock.push(None::<TransactionReceipt>)?;
+        mock.push::<&str, _>("nonce too low")?;
+        mock.push(U256::from(21_000))?;
+        mock.push(U256::from(110))?;
+        mock.push(None::<TransactionReceipt>)?;
+        service.check_submitted().await?;
+        let waiting = service.get_job(job.id)?;
+        assert_eq!(waiting.state, MintJobState::Submitted);
+        assert!(waiting.error.is_some());
+
+        // it was mined since it was checked, the refused resubmission is
+        // known too
+        assert_eq!(waiting.tx_hashes.len(), 2);
+        let receipt = TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(U64::from(10)),
+            status: Some(U64::from(1)),
+            ..Default::default()
+        };
+        mock.push(U64::from(12))?;
+        mock.push(receipt)?;
+        mock.push::<&str, _>("nonce too low")?;
+        mock.push(U256::from(21_000))?;
+        mock.push(U256::from(120))?;
+        mock.push(None::<TransactionReceipt>)?;
+        mock.push(None::<TransactionReceipt>)?;
+        service.check_submitted().await?;
+        assert_eq!(service.get_job(job.id)?.state, MintJobState::Confirmed);
+        assert!(nft_service.get_nft(nft.id)?.minted_at.is_some());
+
+        // jobs are given up only once their transactions were dropped
+        let job = enqueue_mint(&mut conn, dropped_nft.id, wallet.id)?;
+        mock.push(H256::random())?;
+        mock.push(U256::from(21_000))?;
+        mock.push(U256::from(100))?;
+        service.submit_pending().await?;
+        assert_eq!(service.get_job(job.id)?.nonce, Some(8));
+        give_up(job.id)?;
+        mock.push(None::<ethers::types::Transaction>)?;
+        mock.push(U256::from(8))?;
+        mock.push(None::<TransactionReceipt>)?;
+        mock.push::<&str, _>("insufficient funds")?;
+        mock.push(U256::from(21_000))?;
+        mock.push(U256::from(110))?;
+        mock.push(None::<TransactionReceipt>)?;
+        service.check_submitted().await?;
+        assert_eq!(service.get_job(job.id)?.state, MintJobState::Failed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unsent_job_keeps_its_nonce() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let events = Arc::new(EventBus::default());
+        let nft_service = NftService::new(pool.clone(), events.clone());
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let project = ProjectService::new(pool.clone()).create_project("Unsent", None)?;
+        let (chain_id, mut nfts) = create_nfts(&nft_service, &project, 1)?;
+        let nft = nfts.remove(0);
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+
+        let mut conn = pool.get()?;
+        let (provider, mock) = Provider::mocked();
+        let mut providers = ChainProviders::new();
+        providers.insert(chain_id as u64, provider);
+        let service =
+            MintService::new(pool.clone(), Arc::new(providers), minter(chain_id)?, events);
+
+        // the node may have received the transaction, the job keeps its
+        // nonce and hash instead of being sent again with a new nonce
+        let job = enqueue_mint(&mut conn, nft.id, wallet.id)?;
+        mock.push::<&str, _>("connection reset")?;
+        mock.push(U256::from(21_000))?;
+        mock.push(U256::from(100))?;
+        mock.push(U256::from(7))?;
+        service.submit_pending().await?;
+        let unsent = service.get_job(job.id)?;
+        assert_eq!(unsent.state, MintJobState::Submitted);
+        assert_eq!(unsent.nonce, Some(7));
+        assert_eq!(unsent.tx_hashes.len(), 1);
+        assert!(unsent.error.is_some());
+
+        // it's not pending anymore, nothing is sent
+        service.submit_pending().await?;
+        assert_eq!(service.get_job(job.id)?.tx_hashes, unsent.tx_hashes);
+
+        Ok(())
+    }
+}
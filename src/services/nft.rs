@@ -1,48 +1,102 @@
-use async_graphql::{Enum, InputObject, SimpleObject};
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::{
+    dataloader::DataLoader, ComplexObject, Context, Enum, ErrorExtensions, InputObject, Interface,
+    SimpleObject, ID,
+};
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::r2d2::ConnectionManager;
+use diesel::sql_types::{Array, Bool, Integer, Nullable, Varchar};
 use diesel::{Identifiable, PgConnection, Queryable};
 use diesel_derive_enum::DbEnum;
 use r2d2::Pool;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::database::ConnectionPool;
 use crate::errors::EthosError;
+use crate::events::{Event, EventBus, TransferEvent};
+use crate::loaders::{
+    AttributesLoader, CollectionContractLoader, CollectionLoader, RarityLoader, RevealLoader,
+    WalletLoader,
+};
+use crate::metadata::{self, ContractMetadata, MetadataAttribute, TokenMetadata};
+use crate::pagination::{self, Cursor, Direction, Page, PageRequest};
 use crate::schema::{
     attributes_on_nfts, collection_contracts, collections, networks, nft_attributes, nfts,
+    transfers,
 };
 
+sql_function!(fn coalesce(x: Nullable<Varchar>, y: Varchar) -> Varchar);
+sql_function!(fn lower(x: Varchar) -> Varchar);
+
+use super::benefit::{link_collection_benefits, BenefitService, NftBenefit};
 use super::project::Project;
+use super::rarity::{get_rarities, refresh_rarity, NftRarity};
+use super::reveal::{
+    self, ensure_not_committed, get_reveals, revealed, CollectionReveal, SetRevealInput,
+};
 use super::wallet::Wallet;
 
 #[derive(Debug, Queryable, SimpleObject, Identifiable)]
 #[diesel(table_name = networks)]
 pub struct Network {
-    id: Uuid,
-    chain_id: i32,
+    pub id: Uuid,
+    pub chain_id: i32,
+    #[graphql(skip)]
+    pub rpc_url: Option<String>,
 }
 
-#[derive(Debug, Queryable, SimpleObject, Identifiable, Associations)]
+#[derive(Debug, Clone, Queryable, SimpleObject, Identifiable, Associations)]
 #[diesel(table_name = collection_contracts)]
 #[diesel(belongs_to(Collection))]
 #[diesel(belongs_to(Network))]
+#[graphql(complex)]
 pub struct CollectionContract {
     pub id: Uuid,
     // contract id on bifrost
     contract_id: Option<Uuid>,
-    // fee recipient address
-    fee_recipient: String,
+    pub address: String,
 
-    address: String,
+    // fee recipient address
+    pub fee_recipient: String,
 
     // relations
-    collection_id: Uuid,
-    network_id: Uuid,
+    pub collection_id: Uuid,
+    pub network_id: Uuid,
+
+    #[graphql(skip)]
+    pub deployed_at_block: Option<i64>,
 }
 
-#[derive(Debug, Queryable, SimpleObject, Associations, Identifiable)]
+#[ComplexObject]
+impl CollectionContract {
+    /// Base URI of the contract's `tokenURI`, the token id is appended to it
+    pub async fn token_uri_base(&self, ctx: &Context<'_>) -> Result<String, EthosError> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        let network = service.get_network(self.network_id)?;
+        Ok(metadata::token_uri_base(network.chain_id, &self.address))
+    }
+
+    /// URI the contract's `contractURI` returns
+    pub async fn contract_uri(&self, ctx: &Context<'_>) -> Result<String, EthosError> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        let network = service.get_network(self.network_id)?;
+        Ok(metadata::contract_uri(
+            self.collection_id,
+            network.chain_id,
+            &self.address,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Queryable, SimpleObject, Associations, Identifiable)]
 #[diesel(belongs_to(Project))]
 #[diesel(table_name = collections)]
+#[graphql(complex)]
 pub struct Collection {
     pub id: Uuid,
     name: String,
@@ -52,8 +106,22 @@ pub struct Collection {
     seller_fee_basis_points: Option<i32>,
     project_id: Uuid,
 
-    created_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
     updated_at: chrono::NaiveDateTime,
+    /// trait whose value is the tier of the NFT, drawn for tickets of that tier
+    pub tier_trait: String,
+}
+
+#[ComplexObject]
+impl Collection {
+    /// Delayed reveal of the NFTs, `null` when they are revealed right away
+    pub async fn reveal(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<Option<CollectionReveal>> {
+        let loader = ctx.data_unchecked::<DataLoader<RevealLoader>>();
+        loader.load_one(self.id).await
+    }
 }
 
 #[derive(Debug, Clone, Insertable)]
@@ -75,19 +143,238 @@ pub struct NewNft {
 #[diesel(belongs_to(CollectionContract, foreign_key = network_contract_id))]
 #[diesel(belongs_to(Collection))]
 #[diesel(table_name = nfts)]
+#[graphql(complex)]
 pub struct Nft {
     pub id: Uuid,
     pub nft_id: i32,
+    #[graphql(skip)]
     name: String,
+    #[graphql(skip)]
     description: String,
-    minted_at: Option<chrono::NaiveDateTime>,
+    pub minted_at: Option<chrono::NaiveDateTime>,
+    #[graphql(skip)]
     image: String,
+    #[graphql(skip)]
     external_url: String,
+    #[graphql(skip)]
     animation_url: String,
 
-    owner_id: Option<Uuid>,
-    collection_id: Uuid,
-    network_contract_id: Uuid,
+    #[graphql(skip)]
+    pub owner_id: Option<Uuid>,
+    #[graphql(skip)]
+    pub collection_id: Uuid,
+    #[graphql(skip)]
+    pub network_contract_id: Uuid,
+}
+
+impl Nft {
+    /// Reveal of its collection while the NFT isn't revealed
+    async fn unrevealed(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<Option<CollectionReveal>> {
+        let loader = ctx.data_unchecked::<DataLoader<RevealLoader>>();
+        let reveal = loader.load_one(self.collection_id).await?;
+        Ok(reveal.filter(|reveal| !reveal.is_revealed(self.minted_at)))
+    }
+}
+
+/// Until the NFT is revealed its metadata is the placeholder of the
+/// collection, without attributes or rarity
+#[ComplexObject]
+impl Nft {
+    pub async fn name(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        Ok(match self.unrevealed(ctx).await? {
+            Some(reveal) => reveal.placeholder_name,
+            None => self.name.clone(),
+        })
+    }
+
+    pub async fn description(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        Ok(match self.unrevealed(ctx).await? {
+            Some(reveal) => reveal.placeholder_description,
+            None => self.description.clone(),
+        })
+    }
+
+    pub async fn image(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        Ok(match self.unrevealed(ctx).await? {
+            Some(reveal) => reveal.placeholder_image,
+            None => self.image.clone(),
+        })
+    }
+
+    pub async fn external_url(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        Ok(match self.unrevealed(ctx).await? {
+            Some(_) => String::new(),
+            None => self.external_url.clone(),
+        })
+    }
+
+    pub async fn animation_url(&self, ctx: &Context<'_>) -> async_graphql::Result<String> {
+        Ok(match self.unrevealed(ctx).await? {
+            Some(reveal) => reveal.placeholder_animation_url,
+            None => self.animation_url.clone(),
+        })
+    }
+
+    pub async fn revealed(&self, ctx: &Context<'_>) -> async_graphql::Result<bool> {
+        Ok(self.unrevealed(ctx).await?.is_none())
+    }
+
+    /// Wallet holding the NFT, `null` until it's assigned
+    pub async fn owner(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Wallet>> {
+        let Some(owner_id) = self.owner_id else {
+            return Ok(None);
+        };
+        let loader = ctx.data_unchecked::<DataLoader<WalletLoader>>();
+        loader.load_one(owner_id).await
+    }
+
+    pub async fn collection(&self, ctx: &Context<'_>) -> async_graphql::Result<Collection> {
+        let loader = ctx.data_unchecked::<DataLoader<CollectionLoader>>();
+        loader
+            .load_one(self.collection_id)
+            .await?
+            .ok_or_else(|| EthosError::DatabaseError(diesel::NotFound).extend())
+    }
+
+    /// Contract the NFT is minted by
+    pub async fn network_contract(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<CollectionContract> {
+        let loader = ctx.data_unchecked::<DataLoader<CollectionContractLoader>>();
+        loader
+            .load_one(self.network_contract_id)
+            .await?
+            .ok_or_else(|| EthosError::DatabaseError(diesel::NotFound).extend())
+    }
+
+    /// Attributes of the NFT, sorted by trait
+    pub async fn attributes(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<NftAttribute>> {
+        if self.unrevealed(ctx).await?.is_some() {
+            return Ok(vec![]);
+        }
+        let loader = ctx.data_unchecked::<DataLoader<AttributesLoader>>();
+        Ok(loader.load_one(self.id).await?.unwrap_or_default())
+    }
+
+    pub async fn minted(&self) -> bool {
+        self.minted_at.is_some()
+    }
+
+    /// Sum of the inverse frequencies of its trait values in the collection,
+    /// `null` until the collection is ranked and the NFT is revealed
+    pub async fn rarity_score(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<f64>> {
+        if self.unrevealed(ctx).await?.is_some() {
+            return Ok(None);
+        }
+        let loader = ctx.data_unchecked::<DataLoader<RarityLoader>>();
+        let rarity = loader.load_one(self.id).await?;
+        Ok(rarity.map(|rarity| rarity.rarity_score))
+    }
+
+    /// Position of the NFT by `rarityScore`, 1 is the rarest
+    pub async fn rarity_rank(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<i32>> {
+        if self.unrevealed(ctx).await?.is_some() {
+            return Ok(None);
+        }
+        let loader = ctx.data_unchecked::<DataLoader<RarityLoader>>();
+        let rarity = loader.load_one(self.id).await?;
+        Ok(rarity.map(|rarity| rarity.rarity_rank))
+    }
+
+    /// Position of the NFT by the product of the frequencies of its trait
+    /// values, 1 is the rarest
+    pub async fn statistical_rank(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<i32>> {
+        if self.unrevealed(ctx).await?.is_some() {
+            return Ok(None);
+        }
+        let loader = ctx.data_unchecked::<DataLoader<RarityLoader>>();
+        let rarity = loader.load_one(self.id).await?;
+        Ok(rarity.map(|rarity| rarity.statistical_rank))
+    }
+
+    pub async fn benefits(&self, ctx: &Context<'_>) -> Result<Vec<NftBenefit>, EthosError> {
+        let service = ctx.data::<Arc<BenefitService>>().unwrap();
+        service.get_nft_benefits(self)
+    }
+
+    /// Ownership changes of the NFT, newest first
+    pub async fn history(&self, ctx: &Context<'_>) -> Result<Vec<EventHistory>, EthosError> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        let transfers = service.get_nft_history(self)?;
+        Ok(transfers.into_iter().map(EventHistory::Transfer).collect())
+    }
+}
+
+#[derive(Debug, Queryable, SimpleObject, Associations, Identifiable)]
+#[diesel(belongs_to(Nft))]
+#[diesel(table_name = transfers)]
+#[graphql(complex)]
+pub struct Transfer {
+    pub id: Uuid,
+    #[graphql(skip)]
+    pub nft_id: Uuid,
+    #[graphql(skip)]
+    pub from_id: Option<Uuid>,
+    #[graphql(skip)]
+    pub to_id: Uuid,
+    pub created_at: chrono::NaiveDateTime,
+    #[graphql(skip)]
+    pub block_number: Option<i64>,
+    pub tx_hash: Option<String>,
+    #[graphql(skip)]
+    pub log_index: Option<i64>,
+}
+
+/// Where an on-chain transfer was emitted
+#[derive(Debug, Clone)]
+pub struct LogPosition {
+    pub block_number: i64,
+    pub tx_hash: String,
+    pub log_index: i64,
+}
+
+impl Transfer {
+    pub fn to_event(&self, to_address: &str) -> Event {
+        Event::Transfer(TransferEvent {
+            nft_id: self.nft_id,
+            from: self.from_id,
+            to: self.to_id,
+            to_address: to_address.to_string(),
+        })
+    }
+}
+
+#[ComplexObject]
+impl Transfer {
+    /// Previous owner, `null` when the NFT was first assigned
+    pub async fn from(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Wallet>> {
+        let Some(from_id) = self.from_id else {
+            return Ok(None);
+        };
+        let loader = ctx.data_unchecked::<DataLoader<WalletLoader>>();
+        loader.load_one(from_id).await
+    }
+
+    pub async fn to(&self, ctx: &Context<'_>) -> async_graphql::Result<Wallet> {
+        let loader = ctx.data_unchecked::<DataLoader<WalletLoader>>();
+        loader
+            .load_one(self.to_id)
+            .await?
+            .ok_or_else(|| EthosError::DatabaseError(diesel::NotFound).extend())
+    }
+}
+
+#[derive(Interface)]
+#[graphql(
+    field(name = "id", type = "&Uuid"),
+    field(name = "created_at", type = "&chrono::NaiveDateTime")
+)]
+pub enum EventHistory {
+    Transfer(Transfer),
 }
 
 #[derive(Debug, Queryable, SimpleObject, Associations, Identifiable)]
@@ -100,7 +387,7 @@ pub struct AttributesOnNft {
     attribute_id: Uuid,
 }
 
-#[derive(Debug, Queryable, SimpleObject, Identifiable)]
+#[derive(Debug, Clone, Queryable, SimpleObject, Identifiable)]
 #[diesel(table_name = nft_attributes)]
 pub struct NftAttribute {
     pub id: Uuid,
@@ -109,7 +396,21 @@ pub struct NftAttribute {
     max_value: Option<String>,
     display_type: Option<DisplayType>,
 }
-#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, DbEnum)]
+impl NftAttribute {
+    /// Position of the attribute in the attribute lists
+    pub fn sort_key(&self) -> AttributeSortKey {
+        AttributeSortKey(
+            self.trait_type.clone().unwrap_or_default(),
+            self.value.clone().unwrap_or_default(),
+        )
+    }
+}
+
+/// Trait type and value of an attribute in the cursors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttributeSortKey(String, String);
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Hash, Debug, DbEnum)]
 #[ExistingTypePath = "crate::schema::sql_types::DisplayType"]
 pub enum DisplayType {
     Number,
@@ -117,44 +418,153 @@ pub enum DisplayType {
     BoostNumber,
 }
 
+/// A page of NFTs. `nextCursor` continues after the last edge and
+/// `previousCursor`, passed as `before`, pages back from the first one.
 #[derive(SimpleObject)]
+#[graphql(complex)]
 pub struct PaginatedNFTs {
+    total_count: i64,
     edges: Vec<Nft>,
-    next_cursor: Option<Uuid>,
+    next_cursor: Option<ID>,
+    previous_cursor: Option<ID>,
+    has_next_page: bool,
+    has_previous_page: bool,
+    #[graphql(skip)]
+    filter: NftFilter,
+}
+
+#[ComplexObject]
+impl PaginatedNFTs {
+    /// Values of every trait with the number of NFTs having them, counting
+    /// the revealed NFTs of every page of the filtered NFTs
+    pub async fn facets(&self, ctx: &Context<'_>) -> Result<Vec<TraitFacet>, EthosError> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        service.get_facets(&self.filter)
+    }
+}
+
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct TraitFacet {
+    pub trait_type: String,
+    /// sorted by value
+    pub values: Vec<FacetValue>,
+}
+
+#[derive(Debug, PartialEq, SimpleObject)]
+pub struct FacetValue {
+    pub value: String,
+    pub count: i64,
 }
 
 #[derive(Debug, InputObject)]
+pub struct OnTransferInput {
+    pub nft_id: Option<Uuid>,
+    /// address receiving the NFT
+    pub address: String,
+}
+
+/// Identifies an NFT of the collection by its `id` or its `nftId`
+#[derive(Debug, InputObject)]
+pub struct FilterNFTInput {
+    pub id: Option<Uuid>,
+    pub nft_id: Option<i32>,
+    pub collection_id: Uuid,
+}
+
+#[derive(Debug, Default, InputObject)]
 pub struct FilterNFTsInput {
     nft_id: Option<i32>,
+    /// size of the page, at most 100
     take: Option<i32>,
-    cursor: Option<Uuid>,
+    /// returns the NFTs after this cursor
+    cursor: Option<ID>,
+    /// returns the NFTs before this cursor
+    before: Option<ID>,
     collection_id: Option<Uuid>,
-
+    /// NFTs whose tier trait of their collection has this value, kept for the
+    /// clients of the contract, `attributes` is the general filter
     tier: Option<i32>,
+
+    /// NFTs having every trait, with any of its values
+    attributes: Option<Vec<AttributeFilterInput>>,
     minted: Option<bool>,
+    /// a single key, NFTs are sorted by `nftId` by default
     order_by: Option<NFTOrderBy>,
 }
 
+/// Filters of the NFTs of a wallet
+#[derive(Debug, Default, InputObject)]
+pub struct FilterNftsWalletInput {
+    pub nft_id: Option<i32>,
+    /// size of the page, at most 100
+    pub take: Option<i32>,
+    /// returns the NFTs after this cursor
+    pub cursor: Option<ID>,
+}
+
+#[derive(Debug, Clone, InputObject)]
+pub struct AttributeFilterInput {
+    pub trait_type: String,
+    pub values: Vec<String>,
+}
+
 #[derive(Debug, Enum, Copy, Clone, Eq, PartialEq)]
 pub enum Sort {
     Asc,
     Desc,
 }
 
-#[derive(Debug, InputObject)]
+#[derive(Debug, Default, InputObject)]
 pub struct NFTOrderBy {
     nft_id: Option<Sort>,
     minted: Option<Sort>,
+    /// by rarity rank, `ASC` lists the rarest first. Unranked NFTs come last.
+    rarity: Option<Sort>,
+}
+
+/// Conditions the listed NFTs match
+#[derive(Debug, Clone, Default)]
+struct NftFilter {
+    nft_id: Option<i32>,
+    collection_id: Option<Uuid>,
+    owner_id: Option<Uuid>,
+    tier: Option<i32>,
+    attributes: Vec<AttributeFilterInput>,
+    minted: Option<bool>,
+}
+
+/// Value of the sort key in the cursors
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NftSortKey {
+    NftId(i32),
+    MintedAt(Option<chrono::NaiveDateTime>),
+    RarityRank(Option<i32>),
+}
+
+impl NftSortKey {
+    fn of(nft: &Nft, rarity_rank: Option<i32>, by: &NftSortKey) -> Self {
+        match by {
+            NftSortKey::NftId(_) => NftSortKey::NftId(nft.nft_id),
+            NftSortKey::MintedAt(_) => NftSortKey::MintedAt(nft.minted_at),
+            NftSortKey::RarityRank(_) => NftSortKey::RarityRank(rarity_rank),
+        }
+    }
+
+    fn same_field(&self, other: &NftSortKey) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
 }
 
 pub struct NftService {
     pool: ConnectionPool,
+    events: Arc<EventBus>,
 }
 
 impl NftService {
-    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>, events: Arc<EventBus>) -> Self {
         Self {
             pool: ConnectionPool::new(pool),
+            events,
         }
     }
 
@@ -177,11 +587,36 @@ impl NftService {
         Ok(result)
     }
 
-    pub fn get_collections(&self, project: &Project) -> Result<Vec<Collection>, EthosError> {
+    /// Collections of the project, oldest first
+    pub fn get_collections(
+        &self,
+        project: &Project,
+        request: &PageRequest<chrono::NaiveDateTime>,
+    ) -> Result<Page<Collection>, EthosError> {
+        use crate::schema::collections::dsl::*;
         let mut conn = self.pool.get()?;
 
-        let result = Collection::belonging_to(&project).load::<Collection>(&mut conn)?;
-        Ok(result)
+        let total_count = Collection::belonging_to(project)
+            .count()
+            .get_result::<i64>(&mut conn)?;
+
+        let mut query = Collection::belonging_to(project).into_boxed();
+        query = if request.is_backward() {
+            query.order((created_at.desc(), id.desc()))
+        } else {
+            query.order((created_at.asc(), id.asc()))
+        };
+        if let Some(Cursor { key, id: last }) = &request.cursor {
+            query = if request.is_backward() {
+                query.filter(created_at.lt(key).or(created_at.eq(key).and(id.lt(last))))
+            } else {
+                query.filter(created_at.gt(key).or(created_at.eq(key).and(id.gt(last))))
+            };
+        }
+        let rows = query
+            .limit(request.take + 1)
+            .load::<Collection>(&mut conn)?;
+        Ok(request.page(rows, total_count))
     }
 
     pub fn get_collection(&self, id: Uuid) -> Result<Collection, EthosError> {
@@ -192,6 +627,15 @@ impl NftService {
         Ok(result)
     }
 
+    pub fn get_collections_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Collection>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = collections::table
+            .filter(collections::id.eq_any(ids))
+            .load::<Collection>(&mut conn)?;
+        Ok(result)
+    }
+
     pub fn get_collection_by_name(
         &self,
         project: &Project,
@@ -227,6 +671,21 @@ impl NftService {
         Ok(result)
     }
 
+    pub fn get_network(&self, id: Uuid) -> Result<Network, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = networks::table.find(id).first::<Network>(&mut conn)?;
+        Ok(result)
+    }
+
+    pub fn get_networks(&self) -> Result<Vec<Network>, EthosError> {
+        use crate::schema::networks::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let result = networks.load::<Network>(&mut conn)?;
+        Ok(result)
+    }
+
     pub fn create_collection_contract(
         &self,
         collection: &Collection,
@@ -250,6 +709,18 @@ impl NftService {
         Ok(result)
     }
 
+    pub fn get_collection_contracts_by_ids(
+        &self,
+        ids: &[Uuid],
+    ) -> Result<Vec<CollectionContract>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = collection_contracts::table
+            .filter(collection_contracts::id.eq_any(ids))
+            .load::<CollectionContract>(&mut conn)?;
+        Ok(result)
+    }
+
     pub fn get_collection_contract_by_address(
         &self,
         network: &Network,
@@ -265,13 +736,30 @@ impl NftService {
         Ok(result)
     }
 
+    /// Creates the NFTs, ranking them with the other NFTs of their collections.
+    /// Collections whose provenance is committed can't get new NFTs.
     pub fn create_nfts(&self, nft_list: Vec<NewNft>) -> Result<Vec<Nft>, EthosError> {
         use crate::schema::nfts::dsl::*;
         let mut conn = self.pool.get()?;
 
-        let result = diesel::insert_into(nfts)
-            .values(nft_list)
-            .get_results::<Nft>(&mut conn)?;
+        let mut collection_ids = nft_list
+            .iter()
+            .map(|nft| nft.collection_id)
+            .collect::<Vec<Uuid>>();
+        collection_ids.sort();
+        collection_ids.dedup();
+        let result = conn.transaction(|conn| {
+            ensure_not_committed(conn, &collection_ids)?;
+            let result = diesel::insert_into(nfts)
+                .values(nft_list)
+                .get_results::<Nft>(conn)?;
+            let ids = result.iter().map(|nft| nft.id).collect::<Vec<_>>();
+            link_collection_benefits(conn, &ids)?;
+            for collection in &collection_ids {
+                refresh_rarity(conn, *collection)?;
+            }
+            Ok::<_, EthosError>(result)
+        })?;
         Ok(result)
     }
 
@@ -319,23 +807,146 @@ impl NftService {
         Ok(result)
     }
 
+    /// Attributes of the NFTs of the collection, sorted by trait and value
+    pub fn get_collection_attributes(
+        &self,
+        project: &Project,
+        collection_id: Uuid,
+        request: &PageRequest<AttributeSortKey>,
+    ) -> Result<Page<NftAttribute>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        // collections of other projects are not found
+        collections::table
+            .find(collection_id)
+            .filter(collections::project_id.eq(project.id))
+            .select(collections::id)
+            .first::<Uuid>(&mut conn)?;
+        // the traits of unrevealed NFTs are hidden
+        let in_collection = || {
+            nft_attributes::id.eq_any(
+                attributes_on_nfts::table
+                    .inner_join(nfts::table)
+                    .filter(nfts::collection_id.eq(collection_id))
+                    .filter(revealed())
+                    .select(attributes_on_nfts::attribute_id),
+            )
+        };
+        let total_count = nft_attributes::table
+            .filter(in_collection())
+            .count()
+            .get_result::<i64>(&mut conn)?;
+
+        // traits and values without a name sort first
+        let trait_type = || coalesce(nft_attributes::trait_type, "");
+        let value = || coalesce(nft_attributes::value, "");
+        let mut query = nft_attributes::table.filter(in_collection()).into_boxed();
+        query = if request.is_backward() {
+            query.order((
+                trait_type().desc(),
+                value().desc(),
+                nft_attributes::id.desc(),
+            ))
+        } else {
+            query.order((trait_type().asc(), value().asc(), nft_attributes::id.asc()))
+        };
+        if let Some(Cursor { key, id: last }) = &request.cursor {
+            let (key_trait, key_value) = (key.0.clone(), key.1.clone());
+            query = if request.is_backward() {
+                query.filter(
+                    trait_type()
+                        .lt(key_trait.clone())
+                        .or(trait_type().eq(key_trait).and(
+                            value()
+                                .lt(key_value.clone())
+                                .or(value().eq(key_value).and(nft_attributes::id.lt(*last))),
+                        )),
+                )
+            } else {
+                query.filter(
+                    trait_type()
+                        .gt(key_trait.clone())
+                        .or(trait_type().eq(key_trait).and(
+                            value()
+                                .gt(key_value.clone())
+                                .or(value().eq(key_value).and(nft_attributes::id.gt(*last))),
+                        )),
+                )
+            };
+        }
+        let rows = query
+            .limit(request.take + 1)
+            .load::<NftAttribute>(&mut conn)?;
+        Ok(request.page(rows, total_count))
+    }
+
+    /// Gives the attribute to the NFT, see
+    /// [`NftService::create_attribute_nft_relations`]
     pub fn create_attribute_nft_relation(
         &self,
         nft_id: Uuid,
         attribute_id: Uuid,
     ) -> Result<AttributesOnNft, EthosError> {
+        let mut result = self.create_attribute_nft_relations(&[(nft_id, attribute_id)])?;
+        Ok(result.remove(0))
+    }
+
+    /// Gives the attributes to the NFTs, as `(nft_id, attribute_id)` pairs,
+    /// unless the provenance of one of their collections is committed. The
+    /// rarity of each collection is refreshed once, with the attributes.
+    pub fn create_attribute_nft_relations(
+        &self,
+        relations: &[(Uuid, Uuid)],
+    ) -> Result<Vec<AttributesOnNft>, EthosError> {
         use crate::schema::attributes_on_nfts::columns;
         let mut conn = self.pool.get()?;
 
-        let result = diesel::insert_into(attributes_on_nfts::table)
-            .values((
-                columns::nft_id.eq(nft_id),
-                columns::attribute_id.eq(attribute_id),
-            ))
-            .get_result::<AttributesOnNft>(&mut conn)?;
+        let nft_ids = relations
+            .iter()
+            .map(|(nft_id, _)| *nft_id)
+            .collect::<Vec<_>>();
+        let result = conn.transaction(|conn| {
+            let collection_ids = nfts::table
+                .filter(nfts::id.eq_any(&nft_ids))
+                .select(nfts::collection_id)
+                .distinct()
+                .order(nfts::collection_id)
+                .load::<Uuid>(conn)?;
+            ensure_not_committed(conn, &collection_ids)?;
+            let values = relations
+                .iter()
+                .map(|(nft_id, attribute_id)| {
+                    (
+                        columns::nft_id.eq(*nft_id),
+                        columns::attribute_id.eq(*attribute_id),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let result = diesel::insert_into(attributes_on_nfts::table)
+                .values(values)
+                .get_results::<AttributesOnNft>(conn)?;
+            for collection in &collection_ids {
+                refresh_rarity(conn, *collection)?;
+            }
+            Ok::<_, EthosError>(result)
+        })?;
         Ok(result)
     }
 
+    /// Recomputes the rarity of the NFTs of the collection, returning how
+    /// many were ranked
+    pub fn refresh_rarity(&self, collection: &Collection) -> Result<usize, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        refresh_rarity(&mut conn, collection.id)
+    }
+
+    pub fn get_rarities(&self, nft_ids: &[Uuid]) -> Result<Vec<NftRarity>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        get_rarities(&mut conn, nft_ids)
+    }
+
     pub fn get_nft_attributes(&self, nft_id: Uuid) -> Result<Vec<AttributesOnNft>, EthosError> {
         use crate::schema::attributes_on_nfts::columns;
 
@@ -347,64 +958,1184 @@ impl NftService {
         Ok(result)
     }
 
+    /// Attributes of the NFTs with the id of their NFT, sorted by trait
+    pub fn get_attributes_of_nfts(
+        &self,
+        nft_ids: &[Uuid],
+    ) -> Result<Vec<(Uuid, NftAttribute)>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = attributes_on_nfts::table
+            .inner_join(nft_attributes::table)
+            .filter(attributes_on_nfts::nft_id.eq_any(nft_ids))
+            .select((attributes_on_nfts::nft_id, nft_attributes::all_columns))
+            .order((nft_attributes::trait_type, nft_attributes::value))
+            .load::<(Uuid, NftAttribute)>(&mut conn)?;
+        Ok(result)
+    }
+
     pub fn get_nfts(&self, input: FilterNFTsInput) -> Result<PaginatedNFTs, EthosError> {
-        use crate::schema::nfts::dsl::*;
+        let order = input.order_by.unwrap_or_default();
+        let (by, sort) = match (order.nft_id, order.minted, order.rarity) {
+            (None, Some(sort), None) => (NftSortKey::MintedAt(None), sort),
+            (None, None, Some(sort)) => (NftSortKey::RarityRank(None), sort),
+            (nft_id, None, None) => (NftSortKey::NftId(0), nft_id.unwrap_or(Sort::Asc)),
+            _ => {
+                return Err(EthosError::InvalidPagination(
+                    "NFTs can only be sorted by one key",
+                ))
+            }
+        };
+        let filter = NftFilter {
+            nft_id: input.nft_id,
+            collection_id: input.collection_id,
+            owner_id: None,
+            tier: input.tier,
+            attributes: input.attributes.unwrap_or_default(),
+            minted: input.minted,
+        };
+        let request =
+            pagination::page_args(input.cursor.as_ref(), input.before.as_ref(), input.take)?;
+        self.paginated_nfts(filter, &by, sort, &request)
+    }
+
+    fn paginated_nfts(
+        &self,
+        filter: NftFilter,
+        by: &NftSortKey,
+        sort: Sort,
+        request: &PageRequest<NftSortKey>,
+    ) -> Result<PaginatedNFTs, EthosError> {
+        let page = self.paginate(&filter, by, sort, request)?;
+        let cursor_of = |(nft, key): &(Nft, NftSortKey)| Cursor::new(key.clone(), nft.id).encode();
+        Ok(PaginatedNFTs {
+            total_count: page.total_count,
+            next_cursor: page.rows.last().map(cursor_of),
+            previous_cursor: page.rows.first().map(cursor_of),
+            edges: page.rows.into_iter().map(|(nft, _)| nft).collect(),
+            has_next_page: page.has_next_page,
+            has_previous_page: page.has_previous_page,
+            filter,
+        })
+    }
+
+    /// Counts the NFTs matching the filter by trait and value. The values of a
+    /// filtered trait are counted without its own filter, so they are the
+    /// values the NFTs could also be filtered by.
+    fn get_facets(&self, filter: &NftFilter) -> Result<Vec<TraitFacet>, EthosError> {
         let mut conn = self.pool.get()?;
 
-        let mut query = nfts
-            .left_join(attributes_on_nfts::table.left_join(nft_attributes::table))
-            .select(nfts::all_columns())
-            .into_boxed();
+        let mut filtered = filter
+            .attributes
+            .iter()
+            .map(|attribute| attribute.trait_type.clone())
+            .collect::<Vec<String>>();
+        filtered.sort();
+        filtered.dedup();
+        let mut counts = facet_counts(&mut conn, filter, FacetTraits::Except(&filtered))?;
+        for trait_type in &filtered {
+            let others = NftFilter {
+                attributes: filter
+                    .attributes
+                    .iter()
+                    .filter(|attribute| &attribute.trait_type != trait_type)
+                    .cloned()
+                    .collect(),
+                ..filter.clone()
+            };
+            counts.extend(facet_counts(
+                &mut conn,
+                &others,
+                FacetTraits::Only(trait_type),
+            )?);
+        }
+        counts.sort();
 
-        if let Some(cursor) = input.cursor {
-            query = query.filter(id.gt(cursor));
+        let mut facets: Vec<TraitFacet> = vec![];
+        for (trait_type, value, count) in counts {
+            let value = FacetValue { value, count };
+            match facets.last_mut() {
+                Some(facet) if facet.trait_type == trait_type => facet.values.push(value),
+                _ => facets.push(TraitFacet {
+                    trait_type,
+                    values: vec![value],
+                }),
+            }
         }
+        Ok(facets)
+    }
+
+    /// NFTs owned by the wallet, sorted by `nftId`
+    pub fn get_wallet_nfts(
+        &self,
+        wallet: &Wallet,
+        input: FilterNftsWalletInput,
+    ) -> Result<PaginatedNFTs, EthosError> {
+        let filter = NftFilter {
+            nft_id: input.nft_id,
+            owner_id: Some(wallet.id),
+            ..Default::default()
+        };
+        let request = pagination::page_args(input.cursor.as_ref(), None, input.take)?;
+        self.paginated_nfts(filter, &NftSortKey::NftId(0), Sort::Asc, &request)
+    }
 
-        query = query.limit(input.take.unwrap_or(20).into());
+    /// Keyset pagination over the sort key, ties broken by the id. The NFTs
+    /// are returned with their key.
+    fn paginate(
+        &self,
+        filter: &NftFilter,
+        by: &NftSortKey,
+        sort: Sort,
+        request: &PageRequest<NftSortKey>,
+    ) -> Result<Page<(Nft, NftSortKey)>, EthosError> {
+        let mut conn = self.pool.get()?;
 
-        if let Some(nft) = input.nft_id {
-            query = query.filter(nft_id.eq(nft));
+        let (direction, cursor) = (request.direction, &request.cursor);
+        if let Some(cursor) = cursor {
+            if !cursor.key.same_field(by) {
+                return Err(EthosError::InvalidPagination(
+                    "cursor was created for another sort",
+                ));
+            }
         }
+        // a page before the cursor is read backwards, then reversed
+        let descending = (sort == Sort::Desc) != (direction == Direction::Backward);
+
+        let total_count = filtered_nfts(filter).count().get_result::<i64>(&mut conn)?;
 
-        if let Some(order) = input.order_by {
-            if let Some(nft_id_order) = order.nft_id {
-                match nft_id_order {
-                    Sort::Asc => query = query.order(nft_id.asc()),
-                    Sort::Desc => query = query.order(nft_id.desc()),
-                }
+        let mut query = filtered_nfts(filter);
+        // unminted and unranked NFTs come last in both sorts, so first when
+        // read backwards
+        let nulls_last = direction == Direction::Forward;
+        query = match (by, descending, nulls_last) {
+            (NftSortKey::NftId(_), false, _) => query.order((nfts::nft_id.asc(), nfts::id.asc())),
+            (NftSortKey::NftId(_), true, _) => query.order((nfts::nft_id.desc(), nfts::id.desc())),
+            (NftSortKey::MintedAt(_), false, true) => {
+                query.order((nfts::minted_at.asc().nulls_last(), nfts::id.asc()))
             }
-            if let Some(minter_order) = order.minted {
-                match minter_order {
-                    Sort::Asc => query = query.order(minted_at.asc()),
-                    Sort::Desc => query = query.order(minted_at.desc()),
-                }
+            (NftSortKey::MintedAt(_), false, false) => {
+                query.order((nfts::minted_at.asc().nulls_first(), nfts::id.asc()))
             }
-        }
-        if let Some(collection) = input.collection_id {
-            query = query.filter(collection_id.eq(collection));
+            (NftSortKey::MintedAt(_), true, true) => {
+                query.order((nfts::minted_at.desc().nulls_last(), nfts::id.desc()))
+            }
+            (NftSortKey::MintedAt(_), true, false) => {
+                query.order((nfts::minted_at.desc().nulls_first(), nfts::id.desc()))
+            }
+            (NftSortKey::RarityRank(_), false, true) => {
+                query.order((rarity_rank().asc().nulls_last(), nfts::id.asc()))
+            }
+            (NftSortKey::RarityRank(_), false, false) => {
+                query.order((rarity_rank().asc().nulls_first(), nfts::id.asc()))
+            }
+            (NftSortKey::RarityRank(_), true, true) => {
+                query.order((rarity_rank().desc().nulls_last(), nfts::id.desc()))
+            }
+            (NftSortKey::RarityRank(_), true, false) => {
+                query.order((rarity_rank().desc().nulls_first(), nfts::id.desc()))
+            }
+        };
+        if let Some(Cursor { key, id: last }) = cursor {
+            let last = *last;
+            query = match (key, descending) {
+                (NftSortKey::NftId(value), false) => query.filter(
+                    nfts::nft_id
+                        .gt(value)
+                        .or(nfts::nft_id.eq(value).and(nfts::id.gt(last))),
+                ),
+                (NftSortKey::NftId(value), true) => query.filter(
+                    nfts::nft_id
+                        .lt(value)
+                        .or(nfts::nft_id.eq(value).and(nfts::id.lt(last))),
+                ),
+                (NftSortKey::MintedAt(Some(value)), false) => query.filter(
+                    nfts::minted_at
+                        .gt(value)
+                        .or(nfts::minted_at.eq(value).and(nfts::id.gt(last)))
+                        .or(nfts::minted_at.is_null().and(nulls_last.into_sql::<Bool>())),
+                ),
+                (NftSortKey::MintedAt(Some(value)), true) => query.filter(
+                    nfts::minted_at
+                        .lt(value)
+                        .or(nfts::minted_at.eq(value).and(nfts::id.lt(last)))
+                        .or(nfts::minted_at.is_null().and(nulls_last.into_sql::<Bool>())),
+                ),
+                (NftSortKey::MintedAt(None), false) => query.filter(
+                    nfts::minted_at
+                        .is_null()
+                        .and(nfts::id.gt(last))
+                        .or(nfts::minted_at
+                            .is_not_null()
+                            .and((!nulls_last).into_sql::<Bool>())),
+                ),
+                (NftSortKey::MintedAt(None), true) => query.filter(
+                    nfts::minted_at
+                        .is_null()
+                        .and(nfts::id.lt(last))
+                        .or(nfts::minted_at
+                            .is_not_null()
+                            .and((!nulls_last).into_sql::<Bool>())),
+                ),
+                (NftSortKey::RarityRank(Some(value)), false) => query.filter(
+                    rarity_rank()
+                        .gt(value)
+                        .or(rarity_rank().eq(value).and(nfts::id.gt(last)))
+                        .or(rarity_rank().is_null().and(nulls_last.into_sql::<Bool>())),
+                ),
+                (NftSortKey::RarityRank(Some(value)), true) => query.filter(
+                    rarity_rank()
+                        .lt(value)
+                        .or(rarity_rank().eq(value).and(nfts::id.lt(last)))
+                        .or(rarity_rank().is_null().and(nulls_last.into_sql::<Bool>())),
+                ),
+                (NftSortKey::RarityRank(None), false) => query.filter(
+                    rarity_rank()
+                        .is_null()
+                        .and(nfts::id.gt(last))
+                        .or(rarity_rank()
+                            .is_not_null()
+                            .and((!nulls_last).into_sql::<Bool>())),
+                ),
+                (NftSortKey::RarityRank(None), true) => query.filter(
+                    rarity_rank()
+                        .is_null()
+                        .and(nfts::id.lt(last))
+                        .or(rarity_rank()
+                            .is_not_null()
+                            .and((!nulls_last).into_sql::<Bool>())),
+                ),
+            };
         }
 
-        if let Some(minted) = input.minted {
-            if minted {
-                query = query.filter(minted_at.is_not_null());
-            } else {
-                query = query.filter(minted_at.is_null());
+        let rows = query
+            .select((nfts::all_columns, rarity_rank()))
+            .limit(request.take + 1)
+            .load::<(Nft, Option<i32>)>(&mut conn)?;
+        let rows = rows
+            .into_iter()
+            .map(|(nft, rank)| {
+                let key = NftSortKey::of(&nft, rank, by);
+                (nft, key)
+            })
+            .collect();
+        Ok(request.page(rows, total_count))
+    }
+
+    /// Moves the NFT to a new owner, recording the transfer
+    pub fn transfer_nft(&self, nft: Uuid, to: &Wallet) -> Result<Transfer, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = conn.transaction(|conn| transfer(conn, nft, to.id))?;
+        self.events.publish(result.to_event(&to.address));
+        Ok(result)
+    }
+
+    pub fn get_nft(&self, id: Uuid) -> Result<Nft, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = nfts::table.find(id).first::<Nft>(&mut conn)?;
+        Ok(result)
+    }
+
+    /// NFT by its `id`, or by its `nftId` in the collection
+    pub fn find_nft(&self, input: FilterNFTInput) -> Result<Nft, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let query = nfts::table.filter(nfts::collection_id.eq(input.collection_id));
+        let result = match (input.id, input.nft_id) {
+            (Some(id), None) => query.filter(nfts::id.eq(id)).first::<Nft>(&mut conn)?,
+            (None, Some(nft_id)) => query
+                .filter(nfts::nft_id.eq(nft_id))
+                .first::<Nft>(&mut conn)?,
+            _ => return Err(EthosError::InvalidNftLookup),
+        };
+        Ok(result)
+    }
+
+    /// Metadata of the token `token_id` of the contract at `address` on the
+    /// chain, the address is matched ignoring its checksum
+    pub fn get_token_metadata(
+        &self,
+        chain_id: i32,
+        address: &str,
+        token_id: i32,
+    ) -> Result<TokenMetadata, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let nft = nfts::table
+            .inner_join(collection_contracts::table.inner_join(networks::table))
+            .filter(networks::chain_id.eq(chain_id))
+            .filter(lower(collection_contracts::address).eq(address.to_lowercase()))
+            .filter(nfts::nft_id.eq(token_id))
+            .select(nfts::all_columns)
+            .first::<Nft>(&mut conn)?;
+        if let Some(reveal) = get_reveals(&mut conn, &[nft.collection_id])?.pop() {
+            if !reveal.is_revealed(nft.minted_at) {
+                return Ok(reveal.placeholder());
             }
         }
+        let attributes = attributes_on_nfts::table
+            .inner_join(nft_attributes::table)
+            .filter(attributes_on_nfts::nft_id.eq(nft.id))
+            .select(nft_attributes::all_columns)
+            .order((nft_attributes::trait_type, nft_attributes::value))
+            .load::<NftAttribute>(&mut conn)?;
+        Ok(token_metadata(nft, attributes))
+    }
 
-        if let Some(tier) = input.tier {
-            query = query
-                .filter(nft_attributes::dsl::trait_type.eq("Tier"))
-                .filter(nft_attributes::dsl::value.eq(tier.to_string()));
+    /// Contract-level metadata of the collection. The fee recipient is the
+    /// one of its contract at `address`, on the chain when it's given, or of
+    /// any of its contracts.
+    pub fn get_contract_metadata(
+        &self,
+        collection_id: Uuid,
+        chain_id: Option<i32>,
+        address: Option<&str>,
+    ) -> Result<ContractMetadata, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let collection = collections::table
+            .find(collection_id)
+            .first::<Collection>(&mut conn)?;
+        let mut contracts = collection_contracts::table
+            .inner_join(networks::table)
+            .filter(collection_contracts::collection_id.eq(collection_id))
+            .order(collection_contracts::address)
+            .select(collection_contracts::all_columns)
+            .into_boxed();
+        if let Some(address) = address {
+            contracts =
+                contracts.filter(lower(collection_contracts::address).eq(address.to_lowercase()));
+        }
+        if let Some(chain_id) = chain_id {
+            contracts = contracts.filter(networks::chain_id.eq(chain_id));
+        }
+        let contract = contracts
+            .first::<CollectionContract>(&mut conn)
+            .optional()?;
+        if address.is_some() && contract.is_none() {
+            return Err(EthosError::DatabaseError(diesel::NotFound));
         }
+        Ok(ContractMetadata {
+            name: collection.name,
+            description: collection.description,
+            image: collection.image,
+            external_link: collection.external_link,
+            seller_fee_basis_points: collection.seller_fee_basis_points,
+            fee_recipient: contract.map(|contract| contract.fee_recipient),
+        })
+    }
 
-        let edges = query.load::<Nft>(&mut conn)?;
-        let last = edges.last().map(|nft| nft.id);
+    /// Sets the delayed reveal of a collection of the project
+    pub fn set_reveal(
+        &self,
+        project: &Project,
+        input: SetRevealInput,
+    ) -> Result<CollectionReveal, EthosError> {
+        let mut conn = self.pool.get()?;
 
-        // let result = query.load::<Nft>(&mut conn)?;
-        Ok(PaginatedNFTs {
-            edges,
-            next_cursor: last,
+        collections::table
+            .filter(collections::id.eq(input.collection_id))
+            .filter(collections::project_id.eq(project.id))
+            .first::<Collection>(&mut conn)?;
+        reveal::set_reveal(&mut conn, input)
+    }
+
+    /// Commits the provenance hash of the final metadata of the collection
+    pub fn commit_provenance(
+        &self,
+        project: &Project,
+        collection_id: Uuid,
+    ) -> Result<CollectionReveal, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        conn.transaction(|conn| {
+            let collection = collections::table
+                .filter(collections::id.eq(collection_id))
+                .filter(collections::project_id.eq(project.id))
+                .first::<Collection>(conn)?;
+            reveal::lock_reveal(conn, collection.id)?;
+            let metadata = collection_metadata(conn, collection.id)?;
+            reveal::commit_provenance(conn, collection.id, &metadata)
+        })
+    }
+
+    pub fn get_reveals(
+        &self,
+        collection_ids: &[Uuid],
+    ) -> Result<Vec<CollectionReveal>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        get_reveals(&mut conn, collection_ids)
+    }
+
+    /// Transfers of the NFT, newest first. The transfers indexed in the same
+    /// batch share `created_at`, so they are sorted by their position in the
+    /// chain.
+    pub fn get_nft_history(&self, nft: &Nft) -> Result<Vec<Transfer>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = Transfer::belonging_to(nft)
+            .order((
+                transfers::created_at.desc(),
+                transfers::block_number.desc(),
+                transfers::log_index.desc(),
+                transfers::id,
+            ))
+            .load::<Transfer>(&mut conn)?;
+        Ok(result)
+    }
+}
+
+/// Updates the owner of the NFT and records the transfer. Must be called
+/// inside a transaction so both writes are kept consistent.
+pub fn transfer(conn: &mut PgConnection, nft: Uuid, to: Uuid) -> Result<Transfer, EthosError> {
+    record_transfer(conn, nft, to, None)
+}
+
+/// Same as [`transfer`] for transfers read from the chain, which are recorded
+/// once per log.
+pub fn record_transfer(
+    conn: &mut PgConnection,
+    nft: Uuid,
+    to: Uuid,
+    log: Option<LogPosition>,
+) -> Result<Transfer, EthosError> {
+    let from = nfts::table
+        .find(nft)
+        .select(nfts::owner_id)
+        .for_update()
+        .first::<Option<Uuid>>(conn)?;
+
+    diesel::update(nfts::table.find(nft))
+        .set(nfts::owner_id.eq(to))
+        .execute(conn)?;
+
+    let result = diesel::insert_into(transfers::table)
+        .values((
+            transfers::nft_id.eq(nft),
+            transfers::from_id.eq(from),
+            transfers::to_id.eq(to),
+            transfers::block_number.eq(log.as_ref().map(|log| log.block_number)),
+            transfers::tx_hash.eq(log.as_ref().map(|log| log.tx_hash.clone())),
+            transfers::log_index.eq(log.as_ref().map(|log| log.log_index)),
+        ))
+        .get_result::<Transfer>(conn)?;
+    Ok(result)
+}
+
+/// Rank of the NFT in the rarity of its collection, `NULL` until it's ranked
+/// and revealed
+fn rarity_rank() -> SqlLiteral<Nullable<Integer>> {
+    sql::<Nullable<Integer>>(&format!(
+        "(SELECT nft_rarities.rarity_rank FROM nft_rarities \
+         WHERE nft_rarities.nft_id = nfts.id AND {})",
+        reveal::REVEALED
+    ))
+}
+
+/// Metadata of an NFT as served by `/metadata`, attributes sorted by trait
+pub(crate) fn token_metadata(nft: Nft, attributes: Vec<NftAttribute>) -> TokenMetadata {
+    let non_empty = |url: String| Some(url).filter(|url| !url.is_empty());
+    TokenMetadata {
+        name: nft.name,
+        description: nft.description,
+        image: nft.image,
+        external_url: non_empty(nft.external_url),
+        animation_url: non_empty(nft.animation_url),
+        attributes: attributes
+            .into_iter()
+            .map(|attribute| {
+                MetadataAttribute::new(
+                    attribute.trait_type,
+                    attribute.value,
+                    attribute.max_value,
+                    attribute.display_type,
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Revealed metadata of every NFT of the collection, sorted by `nftId`
+fn collection_metadata(
+    conn: &mut PgConnection,
+    collection_id: Uuid,
+) -> Result<Vec<TokenMetadata>, EthosError> {
+    let nfts = nfts::table
+        .filter(nfts::collection_id.eq(collection_id))
+        .order(nfts::nft_id)
+        .load::<Nft>(conn)?;
+    let mut attributes = attributes_on_nfts::table
+        .inner_join(nft_attributes::table)
+        .inner_join(nfts::table)
+        .filter(nfts::collection_id.eq(collection_id))
+        .select((attributes_on_nfts::nft_id, nft_attributes::all_columns))
+        .order((nft_attributes::trait_type, nft_attributes::value))
+        .load::<(Uuid, NftAttribute)>(conn)?
+        .into_iter()
+        .fold(
+            HashMap::<_, Vec<_>>::new(),
+            |mut attributes, (nft, attribute)| {
+                attributes.entry(nft).or_default().push(attribute);
+                attributes
+            },
+        );
+    Ok(nfts
+        .into_iter()
+        .map(|nft| {
+            let nft_attributes = attributes.remove(&nft.id).unwrap_or_default();
+            token_metadata(nft, nft_attributes)
         })
+        .collect())
+}
+
+/// Trait, value and number of NFTs having it
+type FacetCount = (String, String, i64);
+
+/// Traits whose values are counted
+enum FacetTraits<'a> {
+    Only(&'a str),
+    Except(&'a [String]),
+}
+
+/// Counts of the values of the revealed NFTs matching the filter, sorted by
+/// trait and value
+fn facet_counts(
+    conn: &mut PgConnection,
+    filter: &NftFilter,
+    traits: FacetTraits,
+) -> Result<Vec<FacetCount>, EthosError> {
+    let mut query = attributes_on_nfts::table
+        .inner_join(nft_attributes::table)
+        .filter(
+            attributes_on_nfts::nft_id
+                .eq_any(filtered_nfts(filter).filter(revealed()).select(nfts::id)),
+        )
+        .filter(nft_attributes::trait_type.is_not_null())
+        .filter(nft_attributes::value.is_not_null())
+        .group_by((nft_attributes::trait_type, nft_attributes::value))
+        .select((
+            nft_attributes::trait_type.assume_not_null(),
+            nft_attributes::value.assume_not_null(),
+            diesel::dsl::count_star(),
+        ))
+        .order((nft_attributes::trait_type, nft_attributes::value))
+        .into_boxed();
+    query = match traits {
+        FacetTraits::Only(trait_type) => query.filter(nft_attributes::trait_type.eq(trait_type)),
+        FacetTraits::Except(trait_types) => {
+            query.filter(nft_attributes::trait_type.ne_all(trait_types))
+        }
+    };
+    Ok(query.load::<FacetCount>(conn)?)
+}
+
+/// NFTs matching the filter. Attribute filters are ANDed across traits and
+/// ORed across the values of a trait, and only match revealed NFTs.
+fn filtered_nfts(filter: &NftFilter) -> nfts::BoxedQuery<'static, Pg> {
+    let mut query = nfts::table.into_boxed();
+    if let Some(nft_id) = filter.nft_id {
+        query = query.filter(nfts::nft_id.eq(nft_id));
+    }
+    if let Some(collection_id) = filter.collection_id {
+        query = query.filter(nfts::collection_id.eq(collection_id));
+    }
+    if let Some(owner_id) = filter.owner_id {
+        query = query.filter(nfts::owner_id.eq(owner_id));
+    }
+    match filter.minted {
+        Some(true) => query = query.filter(nfts::minted_at.is_not_null()),
+        Some(false) => query = query.filter(nfts::minted_at.is_null()),
+        None => {}
+    }
+    if !filter.attributes.is_empty() || filter.tier.is_some() {
+        query = query.filter(revealed());
+    }
+    if let Some(tier) = filter.tier {
+        query = query.filter(
+            sql::<Bool>(
+                "EXISTS (SELECT 1 FROM attributes_on_nfts \
+                 INNER JOIN nft_attributes ON nft_attributes.id = attributes_on_nfts.attribute_id \
+                 INNER JOIN collections ON collections.id = nfts.collection_id \
+                 WHERE attributes_on_nfts.nft_id = nfts.id \
+                 AND nft_attributes.trait_type = collections.tier_trait \
+                 AND nft_attributes.value = ",
+            )
+            .bind::<Varchar, _>(tier.to_string())
+            .sql(")"),
+        );
+    }
+    for attribute in &filter.attributes {
+        // diesel can't correlate a subquery with the outer query, so the
+        // EXISTS is written in SQL, with its values bound
+        query = query.filter(
+            sql::<Bool>(
+                "EXISTS (SELECT 1 FROM attributes_on_nfts \
+                 INNER JOIN nft_attributes ON nft_attributes.id = attributes_on_nfts.attribute_id \
+                 WHERE attributes_on_nfts.nft_id = nfts.id AND nft_attributes.trait_type = ",
+            )
+            .bind::<Varchar, _>(attribute.trait_type.clone())
+            .sql(" AND nft_attributes.value = ANY(")
+            .bind::<Array<Varchar>, _>(attribute.values.clone())
+            .sql("))"),
+        );
+    }
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ops::RangeInclusive, sync::Arc};
+
+    use anyhow::Result;
+    use diesel::prelude::*;
+    use dotenvy::dotenv;
+    use ethers::{providers::Http, types::Address};
+    use uuid::Uuid;
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::{create_connection_pool, ConnectionPool},
+        errors::EthosError,
+        events::EventBus,
+        pagination::{Cursor, PageRequest},
+        schema::{nft_rarities, nfts},
+        services::{
+            project::ProjectService,
+            reveal::{RevealMode, SetRevealInput},
+            wallet::WalletService,
+        },
+    };
+
+    use super::{
+        AttributeFilterInput, Collection, FacetValue, FilterNFTInput, FilterNFTsInput,
+        FilterNftsWalletInput, NFTOrderBy, NewNft, Nft, NftAttribute, NftService, PaginatedNFTs,
+        Project, Sort, TraitFacet,
+    };
+
+    /// Creates an NFT in a new collection of the project
+    fn create_nft(service: &NftService, project: &Project) -> Result<Nft> {
+        let network = service.create_network((Uuid::new_v4().as_u128() % 1_000_000) as i32)?;
+        let collection = service.create_collection(project, "Test collection", None)?;
+        let contract = service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        let nft = service
+            .create_nfts(vec![NewNft {
+                nft_id: 1,
+                name: "Test #1".to_string(),
+                image: "https://ethos.xyz/1.png".to_string(),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: collection.id,
+                network_contract_id: contract.id,
+            }])?
+            .remove(0);
+        Ok(nft)
+    }
+
+    /// Creates NFTs with the ids in the collection of `nft`
+    fn create_collection_nfts(
+        service: &NftService,
+        nft: &Nft,
+        nft_ids: RangeInclusive<i32>,
+    ) -> Result<Vec<Nft>> {
+        let new_nfts = nft_ids
+            .map(|nft_id| NewNft {
+                nft_id,
+                name: format!("Test #{}", nft_id),
+                image: String::new(),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: nft.collection_id,
+                network_contract_id: nft.network_contract_id,
+            })
+            .collect();
+        Ok(service.create_nfts(new_nfts)?)
+    }
+
+    #[test]
+    fn test_transfer_history() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let nft_service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let project = ProjectService::new(pool).create_project("Transfers", None)?;
+        let nft = create_nft(&nft_service, &project)?;
+        let alice = wallet_service.upsert_wallet(Address::random())?;
+        let bob = wallet_service.upsert_wallet(Address::random())?;
+
+        let first = nft_service.transfer_nft(nft.id, &alice)?;
+        assert_eq!(first.from_id, None);
+        let second = nft_service.transfer_nft(nft.id, &bob)?;
+        assert_eq!(second.from_id, Some(alice.id));
+        assert_eq!(second.to_id, bob.id);
+
+        let history = nft_service.get_nft_history(&nft)?;
+        let holders: Vec<_> = history.iter().map(|transfer| transfer.to_id).collect();
+        assert_eq!(holders, vec![bob.id, alice.id]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wallet_nft_list() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let nft_service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let project = ProjectService::new(pool).create_project("My Langoos", None)?;
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+        let mut owned = vec![];
+        for _ in 0..3 {
+            let nft = create_nft(&nft_service, &project)?;
+            nft_service.transfer_nft(nft.id, &wallet)?;
+            owned.push(nft.id);
+        }
+        // not owned by the wallet
+        create_nft(&nft_service, &project)?;
+        owned.sort();
+
+        let mut listed = vec![];
+        let mut cursor = None;
+        loop {
+            let input = FilterNftsWalletInput {
+                nft_id: None,
+                take: Some(2),
+                cursor,
+            };
+            let page = nft_service.get_wallet_nfts(&wallet, input)?;
+            assert_eq!(page.total_count, 3);
+            listed.extend(page.edges.iter().map(|nft| nft.id));
+            if !page.has_next_page {
+                break;
+            }
+            cursor = page.next_cursor;
+        }
+        assert_eq!(listed, owned);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_nft_pagination() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool.clone()).create_project("Gallery", None)?;
+        let nft = create_nft(&service, &project)?;
+        let mut nfts = create_collection_nfts(&service, &nft, 2..=6)?;
+        nfts.insert(0, nft);
+        // #4 and #2 are minted the same day, #5 before them, the others are not
+        let day = chrono::NaiveDate::from_ymd_opt(2023, 4, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let mut conn = pool.get()?;
+        for (index, minted_at) in [(3, day), (1, day), (4, day - chrono::Duration::days(1))] {
+            diesel::update(nfts::table.find(nfts[index].id))
+                .set(nfts::minted_at.eq(minted_at))
+                .execute(&mut conn)?;
+        }
+        let mut tied = [nfts[1].id, nfts[3].id];
+        tied.sort();
+        let mut unminted = [nfts[0].id, nfts[2].id, nfts[5].id];
+        unminted.sort();
+        let expected = [
+            tied[1],
+            tied[0],
+            nfts[4].id,
+            unminted[2],
+            unminted[1],
+            unminted[0],
+        ];
+
+        let page = |cursor, before| {
+            service.get_nfts(FilterNFTsInput {
+                take: Some(4),
+                cursor,
+                before,
+                collection_id: Some(nfts[0].collection_id),
+                order_by: Some(NFTOrderBy {
+                    minted: Some(Sort::Desc),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+        let ids = |edges: &[Nft]| edges.iter().map(|nft| nft.id).collect::<Vec<_>>();
+
+        let first = page(None, None)?;
+        assert_eq!(first.total_count, 6);
+        assert_eq!(ids(&first.edges), expected[..4]);
+        assert!(first.has_next_page);
+        assert!(!first.has_previous_page);
+
+        let second = page(first.next_cursor, None)?;
+        assert_eq!(ids(&second.edges), expected[4..]);
+        assert!(!second.has_next_page);
+        assert!(second.has_previous_page);
+
+        let back = page(None, second.previous_cursor)?;
+        assert_eq!(ids(&back.edges), expected[..4]);
+        assert!(!back.has_previous_page);
+        assert!(back.has_next_page);
+
+        // cursors only work with the sort they were created for
+        let by_nft_id = service.get_nfts(FilterNFTsInput {
+            cursor: back.next_cursor,
+            ..Default::default()
+        });
+        assert!(by_nft_id.is_err());
+
+        let minted = service.get_nfts(FilterNFTsInput {
+            collection_id: Some(nfts[0].collection_id),
+            minted: Some(true),
+            ..Default::default()
+        })?;
+        assert_eq!(minted.total_count, 3);
+        let sorted = minted
+            .edges
+            .iter()
+            .map(|nft| nft.nft_id)
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, vec![2, 4, 5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collection_connections() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool).create_project("Connections", None)?;
+        let nft = create_nft(&service, &project)?;
+        service.create_collection(&project, "Second", None)?;
+        service.create_collection(&project, "Third", None)?;
+
+        let first =
+            service.get_collections(&project, &PageRequest::new(None, None, Some(2), None)?)?;
+        assert_eq!(first.total_count, 3);
+        assert!(first.has_next_page);
+        assert_eq!(first.rows[0].id, nft.collection_id);
+        let cursor_of = |collection: &Collection| Cursor::new(collection.created_at, collection.id);
+        let after = first.rows.last().map(cursor_of);
+        let second =
+            service.get_collections(&project, &PageRequest::new(after, None, Some(2), None)?)?;
+        assert_eq!(second.rows.len(), 1);
+        assert!(!second.has_next_page && second.has_previous_page);
+        let before = second.rows.first().map(cursor_of);
+        let back =
+            service.get_collections(&project, &PageRequest::new(None, before, None, Some(5))?)?;
+        let ids = |rows: &[Collection]| {
+            rows.iter()
+                .map(|collection| collection.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&back.rows), ids(&first.rows));
+        assert!(!back.has_previous_page);
+
+        let mut attributes = vec![];
+        for (trait_type, value) in [
+            (Some("Color"), "red"),
+            (None, "x"),
+            (Some("Tier"), "1"),
+            (Some("Color"), "blue"),
+        ] {
+            let attribute =
+                service.create_attribute(trait_type, Some(value.to_string()), None, None)?;
+            service.create_attribute_nft_relation(nft.id, attribute.id)?;
+            attributes.push(attribute.id);
+        }
+        // not used by the collection
+        service.create_attribute(Some("Color"), Some("green".to_string()), None, None)?;
+        let expected = [attributes[1], attributes[3], attributes[0], attributes[2]];
+
+        let first = service.get_collection_attributes(
+            &project,
+            nft.collection_id,
+            &PageRequest::new(None, None, Some(3), None)?,
+        )?;
+        assert_eq!(first.total_count, 4);
+        let ids = |rows: &[NftAttribute]| {
+            rows.iter()
+                .map(|attribute| attribute.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&first.rows), expected[..3]);
+        let cursor_of = |attribute: &NftAttribute| Cursor::new(attribute.sort_key(), attribute.id);
+        let after = first.rows.last().map(cursor_of);
+        let second = service.get_collection_attributes(
+            &project,
+            nft.collection_id,
+            &PageRequest::new(after, None, Some(3), None)?,
+        )?;
+        assert_eq!(ids(&second.rows), expected[3..]);
+        let before = second.rows.first().map(cursor_of);
+        let back = service.get_collection_attributes(
+            &project,
+            nft.collection_id,
+            &PageRequest::new(None, before, None, Some(2))?,
+        )?;
+        assert_eq!(ids(&back.rows), expected[1..3]);
+        assert!(back.has_previous_page && back.has_next_page);
+        let other = ProjectService::new(create_connection_pool()).create_project("Other", None)?;
+        assert!(service
+            .get_collection_attributes(
+                &other,
+                nft.collection_id,
+                &PageRequest::new(None, None, Some(3), None)?
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_nft() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool).create_project("Lookup", None)?;
+        let nft = create_nft(&service, &project)?;
+
+        let by_id = service.find_nft(FilterNFTInput {
+            id: Some(nft.id),
+            nft_id: None,
+            collection_id: nft.collection_id,
+        })?;
+        assert_eq!(by_id.id, nft.id);
+        let by_nft_id = service.find_nft(FilterNFTInput {
+            id: None,
+            nft_id: Some(nft.nft_id),
+            collection_id: nft.collection_id,
+        })?;
+        assert_eq!(by_nft_id.id, nft.id);
+        let ambiguous = service.find_nft(FilterNFTInput {
+            id: Some(nft.id),
+            nft_id: Some(nft.nft_id),
+            collection_id: nft.collection_id,
+        });
+        assert!(matches!(ambiguous, Err(EthosError::InvalidNftLookup)));
+        // the NFT isn't found in another collection
+        let other = service.create_collection(&project, "Elsewhere", None)?;
+        let elsewhere = service.find_nft(FilterNFTInput {
+            id: Some(nft.id),
+            nft_id: None,
+            collection_id: other.id,
+        });
+        assert!(elsewhere.is_err());
+
+        let tier = service.create_attribute(Some("Tier"), Some("1".to_string()), None, None)?;
+        let color = service.create_attribute(Some("Color"), Some("red".to_string()), None, None)?;
+        service.create_attribute_nft_relation(nft.id, tier.id)?;
+        service.create_attribute_nft_relation(nft.id, color.id)?;
+        let attributes = service.get_attributes_of_nfts(&[nft.id])?;
+        let ids = attributes
+            .iter()
+            .map(|(nft, attribute)| (*nft, attribute.id))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![(nft.id, color.id), (nft.id, tier.id)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_attribute_filters() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool).create_project("Traits", None)?;
+        let nft = create_nft(&service, &project)?;
+        let mut nfts = create_collection_nfts(&service, &nft, 2..=4)?;
+        nfts.insert(0, nft);
+
+        let attribute = |trait_type: &str, value: &str| {
+            service.create_attribute(Some(trait_type), Some(value.to_string()), None, None)
+        };
+        let (red, blue) = (attribute("Color", "red")?, attribute("Color", "blue")?);
+        let (tier_1, tier_2) = (attribute("Tier", "1")?, attribute("Tier", "2")?);
+        // #1 red tier 1, #2 red and blue tier 2, #3 blue tier 1, #4 without traits
+        for (index, attribute) in [
+            (0, &red),
+            (0, &tier_1),
+            (1, &red),
+            (1, &blue),
+            (1, &tier_2),
+            (2, &blue),
+            (2, &tier_1),
+        ] {
+            service.create_attribute_nft_relation(nfts[index].id, attribute.id)?;
+        }
+
+        let filter = |attributes: Vec<(&str, Vec<&str>)>| {
+            let attributes = attributes
+                .into_iter()
+                .map(|(trait_type, values)| AttributeFilterInput {
+                    trait_type: trait_type.to_string(),
+                    values: values.into_iter().map(String::from).collect(),
+                })
+                .collect();
+            service.get_nfts(FilterNFTsInput {
+                collection_id: Some(nfts[0].collection_id),
+                attributes: Some(attributes),
+                ..Default::default()
+            })
+        };
+        let nft_ids =
+            |page: &PaginatedNFTs| page.edges.iter().map(|nft| nft.nft_id).collect::<Vec<_>>();
+
+        // values of a trait are ORed, #2 is listed once
+        let page = filter(vec![("Color", vec!["red", "blue"])])?;
+        assert_eq!(nft_ids(&page), vec![1, 2, 3]);
+        assert_eq!(page.total_count, 3);
+        // traits are ANDed
+        let page = filter(vec![("Color", vec!["blue"]), ("Tier", vec!["1"])])?;
+        assert_eq!(nft_ids(&page), vec![3]);
+        assert!(filter(vec![("Color", vec!["green"])])?.edges.is_empty());
+
+        let facet = |trait_type: &str, values: &[(&str, i64)]| TraitFacet {
+            trait_type: trait_type.to_string(),
+            values: values
+                .iter()
+                .map(|(value, count)| FacetValue {
+                    value: value.to_string(),
+                    count: *count,
+                })
+                .collect(),
+        };
+        let page = filter(vec![])?;
+        assert_eq!(page.total_count, 4);
+        assert_eq!(
+            service.get_facets(&page.filter)?,
+            vec![
+                facet("Color", &[("blue", 2), ("red", 2)]),
+                facet("Tier", &[("1", 2), ("2", 1)]),
+            ]
+        );
+        // a trait's facet ignores its own filter
+        let page = filter(vec![("Tier", vec!["1"])])?;
+        assert_eq!(
+            service.get_facets(&page.filter)?,
+            vec![
+                facet("Color", &[("blue", 1), ("red", 1)]),
+                facet("Tier", &[("1", 2), ("2", 1)]),
+            ]
+        );
+        let page = filter(vec![("Tier", vec!["1"]), ("Color", vec!["red"])])?;
+        assert_eq!(nft_ids(&page), vec![1]);
+        assert_eq!(
+            service.get_facets(&page.filter)?,
+            vec![
+                facet("Color", &[("blue", 1), ("red", 1)]),
+                facet("Tier", &[("1", 1), ("2", 1)]),
+            ]
+        );
+
+        // the tier of the contract filters the tier trait of the collection
+        let page = service.get_nfts(FilterNFTsInput {
+            collection_id: Some(nfts[0].collection_id),
+            tier: Some(2),
+            ..Default::default()
+        })?;
+        assert_eq!(nft_ids(&page), vec![2]);
+
+        // once revealed on mint, the traits of the unminted NFTs are hidden
+        service.set_reveal(
+            &project,
+            SetRevealInput {
+                collection_id: nfts[0].collection_id,
+                mode: RevealMode::OnMint,
+                reveal_at: None,
+                placeholder_name: "Unrevealed".to_string(),
+                placeholder_description: String::new(),
+                placeholder_image: String::new(),
+                placeholder_animation_url: String::new(),
+            },
+        )?;
+        service.commit_provenance(&project, nfts[0].collection_id)?;
+        diesel::update(nfts::table.find(nfts[0].id))
+            .set(nfts::minted_at.eq(diesel::dsl::now))
+            .execute(&mut service.pool.get()?)?;
+        let page = filter(vec![("Color", vec!["red", "blue"])])?;
+        assert_eq!(nft_ids(&page), vec![1]);
+        let page = filter(vec![])?;
+        assert_eq!(page.total_count, 4);
+        assert_eq!(
+            service.get_facets(&page.filter)?,
+            vec![facet("Color", &[("red", 1)]), facet("Tier", &[("1", 1)])]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rarity_sort() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool).create_project("Rarest", None)?;
+        let nft = create_nft(&service, &project)?;
+        let mut nfts = create_collection_nfts(&service, &nft, 2..=4)?;
+        nfts.insert(0, nft);
+        let attribute = |trait_type: &str, value: &str| {
+            service.create_attribute(Some(trait_type), Some(value.to_string()), None, None)
+        };
+        let (cap, crown, eyes) = (
+            attribute("Hat", "cap")?,
+            attribute("Hat", "crown")?,
+            attribute("Eyes", "blue")?,
+        );
+        // #2 is the rarest, then #3, #1 and #4 without traits
+        for (index, attribute) in [(0, &cap), (1, &cap), (1, &eyes), (2, &crown)] {
+            service.create_attribute_nft_relation(nfts[index].id, attribute.id)?;
+        }
+        // not ranked, like an NFT inserted since the last refresh
+        let unranked = create_collection_nfts(&service, &nfts[0], 5..=5)?.remove(0);
+        diesel::delete(nft_rarities::table.find(unranked.id)).execute(&mut service.pool.get()?)?;
+
+        let page = |cursor, before| {
+            service.get_nfts(FilterNFTsInput {
+                take: Some(2),
+                cursor,
+                before,
+                collection_id: Some(nfts[0].collection_id),
+                order_by: Some(NFTOrderBy {
+                    rarity: Some(Sort::Asc),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        };
+        let nft_ids =
+            |page: &PaginatedNFTs| page.edges.iter().map(|nft| nft.nft_id).collect::<Vec<_>>();
+        let first = page(None, None)?;
+        assert_eq!(nft_ids(&first), vec![2, 3]);
+        let second = page(first.next_cursor, None)?;
+        assert_eq!(nft_ids(&second), vec![1, 4]);
+        let third = page(second.next_cursor, None)?;
+        assert_eq!(nft_ids(&third), vec![5]);
+        assert!(!third.has_next_page);
+        let back = page(None, third.previous_cursor)?;
+        assert_eq!(nft_ids(&back), vec![1, 4]);
+        let back = page(None, back.previous_cursor)?;
+        assert_eq!(nft_ids(&back), vec![2, 3]);
+        assert!(!back.has_previous_page);
+
+        Ok(())
     }
 }
@@ -1,20 +1,30 @@
-use async_graphql::SimpleObject;
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+
+use async_graphql::{Enum, InputObject, MaybeUndefined, SimpleObject};
 use diesel::{prelude::*, r2d2::ConnectionManager};
+use diesel_derive_enum::DbEnum;
 use r2d2::Pool;
 
-use crate::{database::ConnectionPool, errors::EthosError, schema::projects};
+use crate::{
+    database::ConnectionPool,
+    errors::EthosError,
+    pagination::{Cursor, Page, PageRequest},
+    schema::{project_members, projects, wallets},
+};
 use diesel::{Insertable, Queryable};
 use uuid::Uuid;
 
+use super::wallet::Wallet;
+
 #[derive(Debug, Queryable, SimpleObject, Identifiable)]
 #[diesel(table_name = projects)]
 pub struct Project {
     pub id: Uuid,
-    name: String,
+    pub name: String,
     description: Option<String>,
-    url: Option<String>,
+    pub url: Option<String>,
     cors: Option<Vec<Option<String>>>,
-    created_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
     updated_at: chrono::NaiveDateTime,
 }
 
@@ -26,14 +36,87 @@ pub struct NewProject {
     url: Option<String>,
     cors: Vec<String>,
 }
+impl Project {
+    /// Whether browsers on `origin` can call the api on behalf of the project
+    pub fn allows_origin(&self, origin: &str) -> bool {
+        let Ok(origin) = normalize_origin(origin) else {
+            return false;
+        };
+        self.origins().contains(&origin)
+    }
+
+    fn origins(&self) -> Vec<String> {
+        self.cors.iter().flatten().flatten().cloned().collect()
+    }
+}
+
+#[derive(InputObject)]
+pub struct UpdateProjectInput {
+    pub project_id: Uuid,
+    pub name: Option<String>,
+    pub description: MaybeUndefined<String>,
+    #[graphql(validator(url))]
+    pub url: MaybeUndefined<String>,
+    /// origins allowed to call the api, e.g. `https://festadotaipe.xyz`
+    pub cors: Option<Vec<String>>,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = projects)]
+struct ProjectChangeset {
+    name: Option<String>,
+    description: Option<Option<String>>,
+    url: Option<Option<String>>,
+    cors: Option<Vec<String>>,
+    updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::ProjectRole"]
+pub enum Role {
+    Admin,
+    User,
+}
+
+impl Role {
+    /// Admins can do everything members with the `User` role can
+    pub fn grants(&self, required: Role) -> bool {
+        *self == Role::Admin || *self == required
+    }
+}
+
+#[derive(Debug, Queryable, Associations, Identifiable)]
+#[diesel(belongs_to(Project))]
+#[diesel(belongs_to(Wallet))]
+#[diesel(primary_key(project_id, wallet_id))]
+#[diesel(table_name = project_members)]
+pub struct ProjectMember {
+    pub project_id: Uuid,
+    pub wallet_id: Uuid,
+    pub role: Role,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(InputObject)]
+pub struct UpdateAdminsProject {
+    /// wallets that become admins of the project
+    pub connect: Vec<Uuid>,
+    /// admins that are downgraded to users of the project
+    pub disconnect: Vec<Uuid>,
+}
+
 pub struct ProjectService {
     pool: ConnectionPool,
+    /// allowed origins of every project, read by the CORS layer on each
+    /// request so it doesn't hit the database
+    origins: RwLock<HashMap<Uuid, Vec<String>>>,
 }
 
 impl ProjectService {
     pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
         ProjectService {
             pool: ConnectionPool::new(pool),
+            origins: RwLock::default(),
         }
     }
 
@@ -54,6 +137,175 @@ impl ProjectService {
             .get_result::<Project>(&mut conn)?)
     }
 
+    /// Creates a project having `wallet` as its first admin
+    pub fn create_project_with_admin(
+        &self,
+        wallet: &Wallet,
+        name: &str,
+        description: Option<String>,
+    ) -> Result<Project, EthosError> {
+        let insert = NewProject {
+            name: name.to_string(),
+            description,
+            url: None,
+            cors: vec![],
+        };
+        let mut conn = self.pool.get()?;
+        conn.transaction(|conn| {
+            let project = diesel::insert_into(projects::table)
+                .values(&insert)
+                .get_result::<Project>(conn)?;
+            diesel::insert_into(project_members::table)
+                .values((
+                    project_members::project_id.eq(project.id),
+                    project_members::wallet_id.eq(wallet.id),
+                    project_members::role.eq(Role::Admin),
+                ))
+                .execute(conn)?;
+            Ok(project)
+        })
+    }
+
+    pub fn update_project(
+        &self,
+        project: &Project,
+        input: UpdateProjectInput,
+    ) -> Result<Project, EthosError> {
+        let cors = match input.cors {
+            Some(origins) => Some(
+                origins
+                    .iter()
+                    .map(|origin| normalize_origin(origin))
+                    .collect::<Result<Vec<String>, EthosError>>()?,
+            ),
+            None => None,
+        };
+        let changeset = ProjectChangeset {
+            name: input.name,
+            description: input.description.into(),
+            url: input.url.into(),
+            cors,
+            updated_at: chrono::Utc::now().naive_utc(),
+        };
+        let mut conn = self.pool.get()?;
+        let project = diesel::update(project)
+            .set(&changeset)
+            .get_result::<Project>(&mut conn)?;
+        self.origins
+            .write()
+            .expect("origins lock poisoned")
+            .insert(project.id, project.origins());
+        Ok(project)
+    }
+
+    /// Replaces the origins in memory with the ones of every project
+    pub fn load_origins(&self) -> Result<(), EthosError> {
+        use crate::schema::projects::dsl::*;
+        let mut conn = self.pool.get()?;
+        let loaded = projects
+            .select((id, cors))
+            .load::<(Uuid, Option<Vec<Option<String>>>)>(&mut conn)?
+            .into_iter()
+            .map(|(project, origins)| (project, origins.into_iter().flatten().flatten().collect()))
+            .collect();
+        *self.origins.write().expect("origins lock poisoned") = loaded;
+        Ok(())
+    }
+
+    /// Reloads the origins every `interval`, picking up the projects updated
+    /// by other instances
+    pub async fn refresh_origins(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = self.load_origins() {
+                println!("Failed to load project origins: {}", err);
+            }
+        }
+    }
+
+    /// Whether browsers on `origin` can call the api on behalf of `project`,
+    /// or of any project when it's not known. Only reads the origins in memory.
+    pub fn allows_origin(&self, project: Option<Uuid>, origin: &str) -> bool {
+        let Ok(origin) = normalize_origin(origin) else {
+            return false;
+        };
+        let origins = self.origins.read().expect("origins lock poisoned");
+        let allows = |allowed: &Vec<String>| allowed.contains(&origin);
+        match project {
+            Some(project) => origins.get(&project).is_some_and(allows),
+            None => origins.values().any(allows),
+        }
+    }
+
+    pub fn get_member_role(
+        &self,
+        project: &Project,
+        wallet: &Wallet,
+    ) -> Result<Option<Role>, EthosError> {
+        use crate::schema::project_members::dsl::*;
+        let mut conn = self.pool.get()?;
+        let member_role = project_members
+            .find((project.id, wallet.id))
+            .select(role)
+            .first::<Role>(&mut conn)
+            .optional()?;
+        Ok(member_role)
+    }
+
+    pub fn get_admins(&self, project: &Project) -> Result<Vec<Wallet>, EthosError> {
+        let mut conn = self.pool.get()?;
+        let admins = ProjectMember::belonging_to(project)
+            .inner_join(wallets::table)
+            .filter(project_members::role.eq(Role::Admin))
+            .select(wallets::all_columns)
+            .load::<Wallet>(&mut conn)?;
+        Ok(admins)
+    }
+
+    /// Promotes the `connect` wallets to admins and downgrades the `disconnect`
+    /// ones to users. A project can't be left without admins.
+    pub fn update_admins(
+        &self,
+        project: &Project,
+        input: UpdateAdminsProject,
+    ) -> Result<Vec<Wallet>, EthosError> {
+        use crate::schema::project_members::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        conn.transaction::<_, EthosError, _>(|conn| {
+            for wallet in input.connect {
+                diesel::insert_into(project_members)
+                    .values((
+                        project_id.eq(project.id),
+                        wallet_id.eq(wallet),
+                        role.eq(Role::Admin),
+                    ))
+                    .on_conflict((project_id, wallet_id))
+                    .do_update()
+                    .set(role.eq(Role::Admin))
+                    .execute(conn)?;
+            }
+            diesel::update(project_members)
+                .filter(project_id.eq(project.id))
+                .filter(wallet_id.eq_any(input.disconnect))
+                .set(role.eq(Role::User))
+                .execute(conn)?;
+
+            let admins = project_members
+                .filter(project_id.eq(project.id))
+                .filter(role.eq(Role::Admin))
+                .count()
+                .get_result::<i64>(conn)?;
+            if admins == 0 {
+                return Err(EthosError::LastProjectAdmin);
+            }
+            Ok(())
+        })?;
+
+        self.get_admins(project)
+    }
+
     pub fn get_project(&self, project: Uuid) -> Result<Project, EthosError> {
         use crate::schema::projects::dsl::*;
         let mut conn = self.pool.get()?;
@@ -68,10 +320,184 @@ impl ProjectService {
         Ok(project)
     }
 
-    pub fn get_projects(&self) -> Result<Vec<Project>, EthosError> {
+    /// Projects the wallet is an admin of, oldest first
+    pub fn get_projects(
+        &self,
+        wallet: &Wallet,
+        request: &PageRequest<chrono::NaiveDateTime>,
+    ) -> Result<Page<Project>, EthosError> {
         use crate::schema::projects::dsl::*;
-
         let mut conn = self.pool.get()?;
-        Ok(projects.limit(10).load::<Project>(&mut *conn)?)
+
+        let administered = || {
+            id.eq_any(
+                project_members::table
+                    .filter(project_members::wallet_id.eq(wallet.id))
+                    .filter(project_members::role.eq(Role::Admin))
+                    .select(project_members::project_id),
+            )
+        };
+        let total_count = projects
+            .filter(administered())
+            .count()
+            .get_result::<i64>(&mut conn)?;
+
+        let mut query = projects.filter(administered()).into_boxed();
+        query = if request.is_backward() {
+            query.order((created_at.desc(), id.desc()))
+        } else {
+            query.order((created_at.asc(), id.asc()))
+        };
+        if let Some(Cursor { key, id: last }) = &request.cursor {
+            query = if request.is_backward() {
+                query.filter(created_at.lt(key).or(created_at.eq(key).and(id.lt(last))))
+            } else {
+                query.filter(created_at.gt(key).or(created_at.eq(key).and(id.gt(last))))
+            };
+        }
+        let rows = query.limit(request.take + 1).load::<Project>(&mut conn)?;
+        Ok(request.page(rows, total_count))
+    }
+}
+
+/// Browsers send origins as `scheme://host[:port]`, so that's how they are stored
+pub fn normalize_origin(origin: &str) -> Result<String, EthosError> {
+    let invalid = || EthosError::InvalidOrigin(origin.to_string());
+    let parsed = url::Url::parse(origin).map_err(|_| invalid())?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.path() != "/" {
+        return Err(invalid());
+    }
+    Ok(parsed.origin().ascii_serialization())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use dotenvy::dotenv;
+    use ethers::{providers::Http, types::Address};
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::{create_connection_pool, ConnectionPool},
+        errors::EthosError,
+        pagination::PageRequest,
+        services::wallet::{Wallet, WalletService},
+    };
+
+    use async_graphql::MaybeUndefined;
+    use uuid::Uuid;
+
+    use super::{normalize_origin, ProjectService, Role, UpdateAdminsProject, UpdateProjectInput};
+
+    #[test]
+    fn test_normalize_origin() {
+        assert_eq!(
+            normalize_origin("https://festadotaipe.xyz/").unwrap(),
+            "https://festadotaipe.xyz"
+        );
+        assert_eq!(
+            normalize_origin("http://localhost:3001").unwrap(),
+            "http://localhost:3001"
+        );
+        assert!(normalize_origin("https://festadotaipe.xyz/app").is_err());
+        assert!(normalize_origin("ftp://festadotaipe.xyz").is_err());
+        assert!(normalize_origin("*").is_err());
+    }
+
+    #[test]
+    fn test_allowed_origins() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let project_service = ProjectService::new(pool.clone());
+        let project = project_service.create_project("Origins", None)?;
+        let other = project_service.create_project("Other origins", None)?;
+        let origin = format!("https://{}.ethos.xyz", project.id);
+
+        project_service.update_project(
+            &project,
+            UpdateProjectInput {
+                project_id: project.id,
+                name: None,
+                description: MaybeUndefined::Undefined,
+                url: MaybeUndefined::Undefined,
+                cors: Some(vec![format!("{}/", origin)]),
+            },
+        )?;
+        assert!(project_service.allows_origin(Some(project.id), &origin));
+        assert!(project_service.allows_origin(None, &origin));
+        // the scheme and port are part of the origin
+        let http = origin.replace("https://", "http://");
+        assert!(!project_service.allows_origin(Some(project.id), &http));
+        assert!(!project_service.allows_origin(None, &http));
+        assert!(!project_service.allows_origin(None, &format!("{}:8443", origin)));
+        assert!(project_service.allows_origin(None, &format!("{}:443", origin)));
+        let updated = project_service.get_project(project.id)?;
+        assert!(updated.allows_origin(&origin));
+        assert!(!updated.allows_origin(&http));
+        assert!(!project_service.allows_origin(Some(other.id), &origin));
+
+        // other instances see the update once they reload
+        let instance = ProjectService::new(pool);
+        assert!(!instance.allows_origin(Some(project.id), &origin));
+        instance.load_origins()?;
+        assert!(instance.allows_origin(Some(project.id), &origin));
+        assert!(!instance.allows_origin(None, "https://unknown.ethos.xyz"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_admins() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let project_service = ProjectService::new(pool.clone());
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let creator = wallet_service.upsert_wallet(Address::random())?;
+        let other = wallet_service.upsert_wallet(Address::random())?;
+
+        let project = project_service.create_project_with_admin(&creator, "Roles", None)?;
+        assert_eq!(
+            project_service.get_member_role(&project, &creator)?,
+            Some(Role::Admin)
+        );
+        assert_eq!(project_service.get_member_role(&project, &other)?, None);
+        let listed = |wallet: &Wallet| -> Result<Vec<Uuid>> {
+            let request = PageRequest::new(None, None, Some(10), None)?;
+            let page = project_service.get_projects(wallet, &request)?;
+            Ok(page.rows.iter().map(|project| project.id).collect())
+        };
+        assert_eq!(listed(&creator)?, vec![project.id]);
+        assert!(listed(&other)?.is_empty());
+
+        let admins = project_service.update_admins(
+            &project,
+            UpdateAdminsProject {
+                connect: vec![other.id],
+                disconnect: vec![creator.id],
+            },
+        )?;
+        assert_eq!(admins, vec![other.clone()]);
+        assert_eq!(
+            project_service.get_member_role(&project, &creator)?,
+            Some(Role::User)
+        );
+        assert!(listed(&creator)?.is_empty());
+        assert_eq!(listed(&other)?, vec![project.id]);
+
+        let last_admin = project_service.update_admins(
+            &project,
+            UpdateAdminsProject {
+                connect: vec![],
+                disconnect: vec![admins[0].id],
+            },
+        );
+        assert!(matches!(last_admin, Err(EthosError::LastProjectAdmin)));
+
+        Ok(())
     }
 }
@@ -0,0 +1,714 @@
+use std::{env, str::FromStr, sync::Arc};
+
+use async_graphql::{async_trait, ComplexObject, Context, SimpleObject};
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::sql_types::{BigInt, Bool, Varchar};
+use diesel::{Identifiable, PgConnection, Queryable};
+use ethers::{
+    types::{H256, U256},
+    utils::keccak256,
+};
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::{
+    database::ConnectionPool,
+    errors::EthosError,
+    events::{Event, EventBus, RequestFulfilledEvent},
+    schema::{collections, nfts, random_requests, tickets},
+};
+
+use super::{
+    mint::enqueue_mint,
+    nft::{Nft, NftService},
+    project::Project,
+    ticket::{transition, Ticket, TicketPurpose, TicketState},
+    wallet::{Wallet, WalletService},
+};
+
+sql_function!(fn pg_advisory_xact_lock(key: BigInt));
+
+#[derive(Debug, Clone, Queryable, SimpleObject, Associations, Identifiable)]
+#[diesel(belongs_to(Ticket))]
+#[diesel(table_name = random_requests)]
+#[graphql(complex)]
+pub struct RandomRequest {
+    pub id: Uuid,
+    pub request_id: String,
+    pub tier: i32,
+    pub fulfilled: bool,
+    fulfilled_at: Option<chrono::NaiveDateTime>,
+    /// the NFT drawn is the `randomNumber % candidates` unminted NFT of the tier,
+    /// ordered by token id and then id
+    random_number: Option<String>,
+    /// evidence the number was generated fairly, never a secret of the source
+    proof: Option<String>,
+    candidates: Option<i32>,
+    #[graphql(skip)]
+    pub ticket_id: Uuid,
+    #[graphql(skip)]
+    pub wallet_id: Uuid,
+    #[graphql(skip)]
+    pub nft_id: Option<Uuid>,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+}
+
+#[ComplexObject]
+impl RandomRequest {
+    pub async fn wallet(&self, ctx: &Context<'_>) -> Result<Wallet, EthosError> {
+        let service = ctx.data::<Arc<WalletService>>().unwrap();
+        service.get_wallet_by_id(self.wallet_id)
+    }
+
+    pub async fn nft(&self, ctx: &Context<'_>) -> Result<Option<Nft>, EthosError> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        self.nft_id.map(|nft| service.get_nft(nft)).transpose()
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct RedeemTicketResponse {
+    random_request: RandomRequest,
+    /// seconds until the random number is expected to be delivered
+    estimated_time: i32,
+}
+
+/// A random number delivered by a [`RandomnessSource`]
+#[derive(Debug, Clone)]
+pub struct Randomness {
+    pub value: U256,
+    /// evidence the number was generated fairly, such as a VRF proof
+    pub proof: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RandomnessRequest {
+    /// id the fulfillment will be delivered with
+    pub request_id: String,
+    /// sources that answer right away return the random number with the request
+    pub fulfillment: Option<Randomness>,
+}
+
+/// Provides the random numbers of the draws. Asynchronous sources, such as
+/// Chainlink VRF, deliver them later through [`RandomRequestService::fulfill`].
+#[async_trait::async_trait]
+pub trait RandomnessSource: Send + Sync {
+    async fn request(&self, id: Uuid) -> Result<RandomnessRequest, EthosError>;
+
+    /// Seconds the source usually takes to fulfill a request
+    fn estimated_time(&self) -> i32;
+}
+
+/// Derives random numbers from a seed, `keccak256(seed ++ id)`, fulfilling
+/// requests right away. Anyone holding the seed can predict the draws, so
+/// the proof only commits to it with `keccak256(seed)`; meant for
+/// development and tests.
+pub struct LocalRandomness {
+    seed: H256,
+}
+
+impl LocalRandomness {
+    pub fn new(seed: H256) -> Self {
+        Self { seed }
+    }
+
+    /// Reads the seed from `RANDOMNESS_SEED`, generating one when it's not
+    /// set. Only available in development.
+    pub fn from_env() -> Result<Self, EthosError> {
+        if env::var("ENVIRONMENT").as_deref() != Ok("development") {
+            return Err(EthosError::DevelopmentOnly);
+        }
+        let seed = env::var("RANDOMNESS_SEED")
+            .ok()
+            .map(|seed| H256::from_str(&seed).expect("RANDOMNESS_SEED must be 32 bytes hex"))
+            .unwrap_or_else(H256::random);
+        Ok(Self::new(seed))
+    }
+}
+
+#[async_trait::async_trait]
+impl RandomnessSource for LocalRandomness {
+    async fn request(&self, id: Uuid) -> Result<RandomnessRequest, EthosError> {
+        let value = keccak256([self.seed.as_bytes(), id.as_bytes()].concat());
+        Ok(RandomnessRequest {
+            request_id: id.to_string(),
+            fulfillment: Some(Randomness {
+                value: U256::from_big_endian(&value),
+                proof: Some(format!("{:?}", H256(keccak256(self.seed)))),
+            }),
+        })
+    }
+
+    fn estimated_time(&self) -> i32 {
+        0
+    }
+}
+
+/// Used when no source is configured, tickets can't be redeemed
+pub struct NoRandomness;
+
+#[async_trait::async_trait]
+impl RandomnessSource for NoRandomness {
+    async fn request(&self, _id: Uuid) -> Result<RandomnessRequest, EthosError> {
+        Err(EthosError::RandomnessNotConfigured)
+    }
+
+    fn estimated_time(&self) -> i32 {
+        0
+    }
+}
+
+pub struct RandomRequestService {
+    pool: ConnectionPool,
+    source: Arc<dyn RandomnessSource>,
+    events: Arc<EventBus>,
+}
+
+impl RandomRequestService {
+    pub fn new(
+        pool: Pool<ConnectionManager<PgConnection>>,
+        source: Arc<dyn RandomnessSource>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        Self {
+            pool: ConnectionPool::new(pool),
+            source,
+            events,
+        }
+    }
+
+    /// Redeems the ticket with the given code for the wallet, requesting the
+    /// random number that draws its NFT. Sources answering right away draw it
+    /// in the same transaction, so the ticket stays redeemable when it fails.
+    pub async fn redeem_ticket(
+        &self,
+        project: &Project,
+        wallet: &Wallet,
+        ticket_code: &str,
+    ) -> Result<RedeemTicketResponse, EthosError> {
+        let ticket = self.get_redeemable_ticket(project, ticket_code)?;
+        let id = Uuid::new_v4();
+        let request = self.source.request(id).await?;
+
+        let mut conn = self.pool.get()?;
+        let random_request = conn.transaction::<_, EthosError, _>(|conn| {
+            let ticket = transition(conn, &ticket, TicketState::RandomNumber)?;
+            let ticket = diesel::update(&ticket)
+                .set(tickets::wallet_id.eq(wallet.id))
+                .get_result::<Ticket>(conn)?;
+            let result = diesel::insert_into(random_requests::table)
+                .values((
+                    random_requests::id.eq(id),
+                    random_requests::request_id.eq(&request.request_id),
+                    random_requests::tier.eq(ticket.tier),
+                    random_requests::ticket_id.eq(ticket.id),
+                    random_requests::wallet_id.eq(wallet.id),
+                ))
+                .get_result::<RandomRequest>(conn)?;
+            match request.fulfillment {
+                Some(randomness) => draw(conn, &ticket, &result, randomness),
+                None => Ok(result),
+            }
+        })?;
+
+        if random_request.fulfilled {
+            self.publish_fulfilled(&random_request);
+        }
+        Ok(RedeemTicketResponse {
+            random_request,
+            estimated_time: self.source.estimated_time(),
+        })
+    }
+
+    fn get_redeemable_ticket(&self, project: &Project, code: &str) -> Result<Ticket, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let ticket = tickets::table
+            .filter(tickets::project_id.eq(project.id))
+            .filter(tickets::code.eq(code))
+            .first::<Ticket>(&mut conn)?;
+        if ticket.purpose == TicketPurpose::Redeem
+            || ticket.state != TicketState::Request
+            || ticket.is_expired()
+        {
+            return Err(EthosError::TicketNotRedeemable);
+        }
+        Ok(ticket)
+    }
+
+    /// Draws the NFT of a request with the random number delivered by the
+    /// source. When no NFT of the tier is left the request is dropped and its
+    /// ticket can be redeemed again, other failures keep it pending so the
+    /// fulfillment can be retried with the same number.
+    pub fn fulfill(
+        &self,
+        request_id: &str,
+        randomness: Randomness,
+    ) -> Result<RandomRequest, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = conn.transaction::<_, EthosError, _>(|conn| {
+            let request = random_requests::table
+                .filter(random_requests::request_id.eq(request_id))
+                .for_update()
+                .first::<RandomRequest>(conn)?;
+            if request.fulfilled {
+                return Err(EthosError::RandomRequestAlreadyFulfilled);
+            }
+            let ticket = tickets::table
+                .find(request.ticket_id)
+                .first::<Ticket>(conn)?;
+            draw(conn, &ticket, &request, randomness)
+        });
+
+        match result {
+            Ok(result) => {
+                self.publish_fulfilled(&result);
+                Ok(result)
+            }
+            Err(err @ EthosError::NoNftAvailable(_)) => {
+                release(&mut conn, request_id)?;
+                Err(err)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn publish_fulfilled(&self, request: &RandomRequest) {
+        self.events
+            .publish(Event::RequestFulfilled(RequestFulfilledEvent {
+                request_id: request.request_id.clone(),
+            }));
+    }
+
+    /// Queues the mint of the NFT drawn for the request to the wallet that
+    /// redeemed the ticket
+    pub fn unpack(&self, wallet: &Wallet, request_id: &str, tier: i32) -> Result<Nft, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = conn.transaction::<_, EthosError, _>(|conn| {
+            let request = random_requests::table
+                .filter(random_requests::request_id.eq(request_id))
+                .filter(random_requests::wallet_id.eq(wallet.id))
+                .filter(random_requests::tier.eq(tier))
+                .for_update()
+                .first::<RandomRequest>(conn)?;
+            let Some(nft) = request.nft_id else {
+                return Err(EthosError::RandomRequestNotFulfilled);
+            };
+            let ticket = tickets::table
+                .find(request.ticket_id)
+                .first::<Ticket>(conn)?;
+
+            transition(conn, &ticket, TicketState::Minted)?;
+            enqueue_mint(conn, nft, wallet.id)?;
+            let nft = nfts::table.find(nft).first::<Nft>(conn)?;
+            Ok(nft)
+        })?;
+        Ok(result)
+    }
+
+    pub fn get_random_request(&self, request_id: &str) -> Result<RandomRequest, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = random_requests::table
+            .filter(random_requests::request_id.eq(request_id))
+            .first::<RandomRequest>(&mut conn)?;
+        Ok(result)
+    }
+
+    pub fn get_ticket_request(&self, ticket: &Ticket) -> Result<Option<RandomRequest>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = RandomRequest::belonging_to(ticket)
+            .first::<RandomRequest>(&mut conn)
+            .optional()?;
+        Ok(result)
+    }
+}
+
+/// Draws the NFT of the request and moves its ticket, in the `RandomNumber`
+/// state, to `Unpack`. Must be called inside a transaction.
+fn draw(
+    conn: &mut PgConnection,
+    ticket: &Ticket,
+    request: &RandomRequest,
+    randomness: Randomness,
+) -> Result<RandomRequest, EthosError> {
+    // draws of a tier wait for each other, so each one sees the NFTs drawn
+    // before it and no NFT is drawn twice
+    diesel::select(pg_advisory_xact_lock(draw_lock_key(
+        ticket.project_id,
+        request.tier,
+    )))
+    .execute(conn)?;
+    let candidates = draw_candidates(conn, ticket.project_id, request.tier)?;
+    if candidates.is_empty() {
+        return Err(EthosError::NoNftAvailable(request.tier));
+    }
+    let index = randomness.value % U256::from(candidates.len());
+    let nft = candidates[index.as_usize()];
+
+    let ticket = transition(conn, ticket, TicketState::Unpack)?;
+    diesel::update(&ticket)
+        .set(tickets::purpose.eq(TicketPurpose::Redeem))
+        .execute(conn)?;
+    let result = diesel::update(request)
+        .set((
+            random_requests::fulfilled.eq(true),
+            random_requests::fulfilled_at.eq(diesel::dsl::now),
+            random_requests::random_number.eq(randomness.value.to_string()),
+            random_requests::proof.eq(randomness.proof),
+            random_requests::candidates.eq(candidates.len() as i32),
+            random_requests::nft_id.eq(nft),
+        ))
+        .get_result::<RandomRequest>(conn)?;
+    Ok(result)
+}
+
+/// Drops the request if it's not fulfilled, putting its ticket back in the
+/// `Request` state
+fn release(conn: &mut PgConnection, request_id: &str) -> Result<(), EthosError> {
+    conn.transaction(|conn| {
+        let Some(request) = random_requests::table
+            .filter(random_requests::request_id.eq(request_id))
+            .filter(random_requests::fulfilled.eq(false))
+            .for_update()
+            .first::<RandomRequest>(conn)
+            .optional()?
+        else {
+            return Ok(());
+        };
+        diesel::delete(&request).execute(conn)?;
+        diesel::update(tickets::table.find(request.ticket_id))
+            .filter(tickets::state.eq(TicketState::RandomNumber))
+            .set((
+                tickets::state.eq(TicketState::Request),
+                tickets::wallet_id.eq(None::<Uuid>),
+            ))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Unminted NFTs of the tier that were not drawn yet, ordered by token id and
+/// id so the draw can be reproduced from the random number and the number of
+/// candidates. The tier of an NFT is the value of the `tier_trait` of its
+/// collection. NFTs locked by another transaction, such as a transfer, are
+/// waited for rather than skipped.
+fn draw_candidates(
+    conn: &mut PgConnection,
+    project: Uuid,
+    tier: i32,
+) -> Result<Vec<Uuid>, EthosError> {
+    let drawn = random_requests::table
+        .filter(random_requests::nft_id.is_not_null())
+        .select(random_requests::nft_id);
+    let project_collections = collections::table
+        .filter(collections::project_id.eq(project))
+        .select(collections::id);
+    let of_tier = sql::<Bool>(
+        "EXISTS (SELECT 1 FROM attributes_on_nfts \
+         INNER JOIN nft_attributes ON nft_attributes.id = attributes_on_nfts.attribute_id \
+         INNER JOIN collections ON collections.id = nfts.collection_id \
+         WHERE attributes_on_nfts.nft_id = nfts.id \
+         AND nft_attributes.trait_type = collections.tier_trait \
+         AND nft_attributes.value = ",
+    )
+    .bind::<Varchar, _>(tier.to_string())
+    .sql(")");
+
+    let result = nfts::table
+        .filter(nfts::collection_id.eq_any(project_collections))
+        .filter(of_tier)
+        .filter(nfts::minted_at.is_null())
+        .filter(nfts::owner_id.is_null())
+        .filter(diesel::dsl::not(nfts::id.nullable().eq_any(drawn)))
+        .select(nfts::id)
+        .order((nfts::nft_id.asc(), nfts::id.asc()))
+        .for_update()
+        .load::<Uuid>(conn)?;
+    Ok(result)
+}
+
+/// Advisory lock of the draws of a tier of the project
+fn draw_lock_key(project: Uuid, tier: i32) -> i64 {
+    let hash = keccak256(format!("draw:{}:{}", project, tier));
+    i64::from_be_bytes(hash[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use async_graphql::async_trait;
+    use diesel::prelude::*;
+    use dotenvy::dotenv;
+    use ethers::{
+        providers::Http,
+        types::{Address, H256, U256},
+        utils::keccak256,
+    };
+    use uuid::Uuid;
+
+    use crate::{
+        chain::providers::ChainProviders,
+        database::{create_connection_pool, ConnectionPool},
+        errors::EthosError,
+        events::EventBus,
+        schema::{collections, mint_jobs, tickets},
+        services::{
+            mint::{MintJob, MintJobState},
+            nft::{NewNft, Nft, NftService},
+            project::{Project, ProjectService},
+            ticket::{NewTicket, Ticket, TicketService, TicketState},
+            wallet::WalletService,
+        },
+    };
+
+    use super::{
+        LocalRandomness, RandomRequestService, Randomness, RandomnessRequest, RandomnessSource,
+    };
+
+    /// Fulfills the requests later, like Chainlink VRF
+    struct PendingRandomness;
+
+    #[async_trait::async_trait]
+    impl RandomnessSource for PendingRandomness {
+        async fn request(&self, id: Uuid) -> Result<RandomnessRequest, EthosError> {
+            Ok(RandomnessRequest {
+                request_id: id.to_string(),
+                fulfillment: None,
+            })
+        }
+
+        fn estimated_time(&self) -> i32 {
+            60
+        }
+    }
+
+    /// Creates a collection of the project with `count` NFTs on a new network
+    fn create_nfts(service: &NftService, project: &Project, count: i32) -> Result<Vec<Nft>> {
+        let network = service.create_network((Uuid::new_v4().as_u128() % 1_000_000) as i32)?;
+        let collection = service.create_collection(project, "Langoos", None)?;
+        let contract = service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        let new_nfts = (1..=count)
+            .map(|nft_id| NewNft {
+                nft_id,
+                name: format!("Langoo #{}", nft_id),
+                image: String::new(),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: collection.id,
+                network_contract_id: contract.id,
+            })
+            .collect();
+        Ok(service.create_nfts(new_nfts)?)
+    }
+
+    fn insert_ticket(service: &TicketService, project: &Project, tier: i32) -> Result<Ticket> {
+        let ticket = service
+            .insert_tickets(vec![NewTicket {
+                token: Uuid::new_v4().simple().to_string(),
+                code: Uuid::new_v4().simple().to_string(),
+                email: "holder@taipe.xyz".to_string(),
+                name: "Holder".to_string(),
+                tier,
+                event: None,
+                expires_at: None,
+                project_id: project.id,
+            }])?
+            .remove(0);
+        Ok(ticket)
+    }
+
+    #[tokio::test]
+    async fn test_random_request_lifecycle() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let events = Arc::new(EventBus::default());
+        let nft_service = NftService::new(pool.clone(), events.clone());
+        let ticket_service = TicketService::new(pool.clone());
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let seed = H256::random();
+        let service =
+            RandomRequestService::new(pool.clone(), Arc::new(LocalRandomness::new(seed)), events);
+        let project = ProjectService::new(pool.clone()).create_project("Langoos", None)?;
+
+        let tier = nft_service.create_attribute(Some("Tier"), Some("2".to_string()), None, None)?;
+        // the last NFT has no tier and isn't drawn
+        let mut langoos = vec![];
+        for nft in &create_nfts(&nft_service, &project, 3)?[..2] {
+            nft_service.create_attribute_nft_relation(nft.id, tier.id)?;
+            langoos.push(nft.id);
+        }
+        // the tier is read from the trait of each collection
+        let rank = nft_service.create_attribute(Some("Rank"), Some("2".to_string()), None, None)?;
+        let ranked = create_nfts(&nft_service, &project, 1)?.remove(0);
+        diesel::update(collections::table.find(ranked.collection_id))
+            .set(collections::tier_trait.eq("Rank"))
+            .execute(&mut pool.get()?)?;
+        nft_service.create_attribute_nft_relation(ranked.id, rank.id)?;
+        nft_service.create_attribute_nft_relation(ranked.id, tier.id)?;
+        langoos.push(ranked.id);
+
+        let ticket = insert_ticket(&ticket_service, &project, 2)?;
+        let code = ticket.code.clone();
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+
+        let response = service.redeem_ticket(&project, &wallet, &code).await?;
+        let request = response.random_request;
+        assert!(request.fulfilled);
+        assert_eq!(request.candidates, Some(3));
+        assert!(langoos.contains(&request.nft_id.unwrap()));
+        // the proof commits to the seed without revealing it
+        assert_eq!(request.proof, Some(format!("{:?}", H256(keccak256(seed)))));
+        assert_eq!(
+            ticket_service.get_ticket(ticket.id)?.state,
+            TicketState::Unpack
+        );
+
+        let again = service.redeem_ticket(&project, &wallet, &code).await;
+        assert!(matches!(again, Err(EthosError::TicketNotRedeemable)));
+        let other_wallet = wallet_service.upsert_wallet(Address::random())?;
+        assert!(service
+            .unpack(&other_wallet, &request.request_id, 2)
+            .is_err());
+        assert!(service.unpack(&wallet, &request.request_id, 1).is_err());
+
+        let nft = service.unpack(&wallet, &request.request_id, 2)?;
+        assert_eq!(Some(nft.id), request.nft_id);
+        // the NFT is owned once the mint is confirmed
+        assert_eq!(nft.owner_id, None);
+        let job = mint_jobs::table
+            .filter(mint_jobs::nft_id.eq(nft.id))
+            .first::<MintJob>(&mut service.pool.get()?)?;
+        assert_eq!(job.state, MintJobState::Pending);
+        assert_eq!(job.wallet_id, wallet.id);
+        assert_eq!(
+            ticket_service.get_ticket(ticket.id)?.state,
+            TicketState::Minted
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_draw() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let events = Arc::new(EventBus::default());
+        let ticket_service = TicketService::new(pool.clone());
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let project = ProjectService::new(pool.clone()).create_project("Sold out", None)?;
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+        let ticket = insert_ticket(&ticket_service, &project, 3)?;
+
+        // the draw of a source answering right away is rolled back with the ticket
+        let local = RandomRequestService::new(
+            pool.clone(),
+            Arc::new(LocalRandomness::new(H256::random())),
+            events.clone(),
+        );
+        let result = local.redeem_ticket(&project, &wallet, &ticket.code).await;
+        assert!(matches!(result, Err(EthosError::NoNftAvailable(3))));
+        let unchanged = ticket_service.get_ticket(ticket.id)?;
+        assert_eq!(unchanged.state, TicketState::Request);
+        assert_eq!(unchanged.wallet_id, None);
+        assert!(local.get_ticket_request(&ticket)?.is_none());
+
+        // a request fulfilled later releases the ticket when its draw fails
+        let pending = RandomRequestService::new(pool, Arc::new(PendingRandomness), events);
+        let response = pending
+            .redeem_ticket(&project, &wallet, &ticket.code)
+            .await?;
+        assert!(!response.random_request.fulfilled);
+        assert_eq!(response.estimated_time, 60);
+        assert_eq!(
+            ticket_service.get_ticket(ticket.id)?.state,
+            TicketState::RandomNumber
+        );
+        let randomness = Randomness {
+            value: U256::from(7),
+            proof: None,
+        };
+        let result = pending.fulfill(&response.random_request.request_id, randomness);
+        assert!(matches!(result, Err(EthosError::NoNftAvailable(3))));
+        assert_eq!(
+            ticket_service.get_ticket(ticket.id)?.state,
+            TicketState::Request
+        );
+        assert!(pending.get_ticket_request(&ticket)?.is_none());
+        pending
+            .redeem_ticket(&project, &wallet, &ticket.code)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fulfill_keeps_request_on_other_errors() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let events = Arc::new(EventBus::default());
+        let nft_service = NftService::new(pool.clone(), events.clone());
+        let ticket_service = TicketService::new(pool.clone());
+        let wallet_service = WalletService::new(
+            ConnectionPool::new(pool.clone()),
+            Arc::new(ChainProviders::<Http>::new()),
+        );
+        let service = RandomRequestService::new(pool.clone(), Arc::new(PendingRandomness), events);
+        let project = ProjectService::new(pool.clone()).create_project("Retry", None)?;
+        let tier = nft_service.create_attribute(Some("Tier"), Some("4".to_string()), None, None)?;
+        let nft = create_nfts(&nft_service, &project, 1)?.remove(0);
+        nft_service.create_attribute_nft_relation(nft.id, tier.id)?;
+        let ticket = insert_ticket(&ticket_service, &project, 4)?;
+        let wallet = wallet_service.upsert_wallet(Address::random())?;
+        let response = service
+            .redeem_ticket(&project, &wallet, &ticket.code)
+            .await?;
+        let request_id = response.random_request.request_id;
+
+        // the ticket can't move on, the request waits for a retry
+        let set_state = |state: TicketState| -> Result<usize> {
+            let count = diesel::update(tickets::table.find(ticket.id))
+                .set(tickets::state.eq(state))
+                .execute(&mut pool.get()?)?;
+            Ok(count)
+        };
+        set_state(TicketState::Request)?;
+        let randomness = Randomness {
+            value: U256::from(7),
+            proof: None,
+        };
+        let result = service.fulfill(&request_id, randomness.clone());
+        assert!(matches!(
+            result,
+            Err(EthosError::InvalidTicketTransition(..))
+        ));
+        let request = service.get_random_request(&request_id)?;
+        assert!(!request.fulfilled);
+
+        set_state(TicketState::RandomNumber)?;
+        let request = service.fulfill(&request_id, randomness)?;
+        assert_eq!(request.nft_id, Some(nft.id));
+
+        Ok(())
+    }
+}
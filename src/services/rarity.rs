@@ -0,0 +1,216 @@
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Uuid as SqlUuid};
+use diesel::{Identifiable, PgConnection, Queryable};
+use ethers::utils::keccak256;
+use uuid::Uuid;
+
+use crate::{errors::EthosError, schema::nft_rarities};
+
+sql_function!(fn pg_advisory_xact_lock(key: BigInt));
+
+/// Rarity of an NFT in its collection. A trait value held by few NFTs of the
+/// collection makes the NFTs holding it rare.
+#[derive(Debug, Clone, Queryable, Identifiable)]
+#[diesel(table_name = nft_rarities)]
+#[diesel(primary_key(nft_id))]
+pub struct NftRarity {
+    pub nft_id: Uuid,
+    pub collection_id: Uuid,
+    /// sum of `1 / frequency` of the values of its traits
+    pub rarity_score: f64,
+    /// 1 for the highest score, NFTs with the same score share the rank
+    pub rarity_rank: i32,
+    /// product of the frequencies of the values of its traits
+    pub statistical_rarity: f64,
+    /// 1 for the lowest statistical rarity
+    pub statistical_rank: i32,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// Recomputes the rarity of every NFT of the collection, returning how many
+/// were ranked. The frequency of a trait value is the share of the NFTs of
+/// the collection holding it, attributes without a trait type are ignored.
+/// The refreshes of a collection wait for each other, so they don't both
+/// insert its rankings.
+pub fn refresh_rarity(conn: &mut PgConnection, collection_id: Uuid) -> Result<usize, EthosError> {
+    conn.transaction(|conn| {
+        diesel::select(pg_advisory_xact_lock(rarity_lock_key(collection_id))).execute(conn)?;
+        diesel::delete(nft_rarities::table.filter(nft_rarities::collection_id.eq(collection_id)))
+            .execute(conn)?;
+        let ranked = diesel::sql_query(
+            "WITH collection_nfts AS (
+                SELECT id FROM nfts WHERE collection_id = $1
+            ), traits AS (
+                SELECT attributes_on_nfts.nft_id, nft_attributes.trait_type, nft_attributes.value
+                FROM attributes_on_nfts
+                INNER JOIN nft_attributes ON nft_attributes.id = attributes_on_nfts.attribute_id
+                WHERE attributes_on_nfts.nft_id IN (SELECT id FROM collection_nfts)
+                AND nft_attributes.trait_type IS NOT NULL
+            ), frequencies AS (
+                SELECT trait_type, value,
+                    COUNT(DISTINCT nft_id)::float8 / (SELECT COUNT(*) FROM collection_nfts) AS frequency
+                FROM traits
+                GROUP BY trait_type, value
+            ), scores AS (
+                SELECT collection_nfts.id AS nft_id,
+                    COALESCE(SUM(1 / frequencies.frequency), 0) AS rarity_score,
+                    COALESCE(EXP(SUM(LN(frequencies.frequency))), 1) AS statistical_rarity
+                FROM collection_nfts
+                LEFT JOIN traits ON traits.nft_id = collection_nfts.id
+                LEFT JOIN frequencies ON frequencies.trait_type = traits.trait_type
+                    AND frequencies.value IS NOT DISTINCT FROM traits.value
+                GROUP BY collection_nfts.id
+            )
+            INSERT INTO nft_rarities
+                (nft_id, collection_id, rarity_score, rarity_rank, statistical_rarity, statistical_rank)
+            SELECT nft_id, $1, rarity_score,
+                RANK() OVER (ORDER BY rarity_score DESC),
+                statistical_rarity,
+                RANK() OVER (ORDER BY statistical_rarity ASC)
+            FROM scores",
+        )
+        .bind::<SqlUuid, _>(collection_id)
+        .execute(conn)?;
+        Ok(ranked)
+    })
+}
+
+/// Advisory lock of the refreshes of the collection
+fn rarity_lock_key(collection_id: Uuid) -> i64 {
+    let hash = keccak256(format!("rarity:{}", collection_id));
+    i64::from_be_bytes(hash[..8].try_into().unwrap())
+}
+
+pub fn get_rarities(
+    conn: &mut PgConnection,
+    nft_ids: &[Uuid],
+) -> Result<Vec<NftRarity>, EthosError> {
+    let result = nft_rarities::table
+        .filter(nft_rarities::nft_id.eq_any(nft_ids))
+        .load::<NftRarity>(conn)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use dotenvy::dotenv;
+    use ethers::types::Address;
+    use uuid::Uuid;
+
+    use crate::{
+        database::create_connection_pool,
+        events::EventBus,
+        services::{
+            nft::{NewNft, Nft, NftService},
+            project::{Project, ProjectService},
+        },
+    };
+
+    /// Creates a collection of the project with `count` NFTs, only their
+    /// attributes are ranked
+    fn create_ranked_nfts(service: &NftService, project: &Project, count: i32) -> Result<Vec<Nft>> {
+        let network = service.create_network((Uuid::new_v4().as_u128() % 1_000_000) as i32)?;
+        let collection = service.create_collection(project, "Rarity", None)?;
+        let contract = service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        let new_nfts = (1..=count)
+            .map(|nft_id| NewNft {
+                nft_id,
+                name: format!("Rarity #{}", nft_id),
+                image: String::new(),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: collection.id,
+                network_contract_id: contract.id,
+            })
+            .collect();
+        Ok(service.create_nfts(new_nfts)?)
+    }
+
+    #[test]
+    fn test_refresh_rarity() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool).create_project("Rarity", None)?;
+        let nfts = create_ranked_nfts(&service, &project, 4)?;
+
+        let attribute = |trait_type: &str, value: &str| {
+            service.create_attribute(Some(trait_type), Some(value.to_string()), None, None)
+        };
+        let (red, blue, hat) = (
+            attribute("Color", "red")?,
+            attribute("Color", "blue")?,
+            attribute("Hat", "yes")?,
+        );
+        // red is held by 3 of 4 NFTs, blue and the hat by 1
+        for (index, attribute) in [(0, &red), (0, &hat), (1, &red), (2, &red), (3, &blue)] {
+            service.create_attribute_nft_relation(nfts[index].id, attribute.id)?;
+        }
+        let collection = service.get_collection(nfts[0].collection_id)?;
+        assert_eq!(service.refresh_rarity(&collection)?, 4);
+
+        let ids = nfts.iter().map(|nft| nft.id).collect::<Vec<_>>();
+        let rarities = service.get_rarities(&ids)?;
+        let rarity = |index: usize| {
+            rarities
+                .iter()
+                .find(|rarity| rarity.nft_id == ids[index])
+                .unwrap()
+        };
+        let scores = (0..4)
+            .map(|index| (rarity(index).rarity_score * 1000.0).round() / 1000.0)
+            .collect::<Vec<_>>();
+        assert_eq!(scores, vec![5.333, 1.333, 1.333, 4.0]);
+        let ranks = (0..4)
+            .map(|index| rarity(index).rarity_rank)
+            .collect::<Vec<_>>();
+        assert_eq!(ranks, vec![1, 3, 3, 2]);
+        let statistical = (0..4)
+            .map(|index| rarity(index).statistical_rank)
+            .collect::<Vec<_>>();
+        assert_eq!(statistical, vec![1, 3, 3, 2]);
+        assert!((rarity(0).statistical_rarity - 0.1875).abs() < 1e-9);
+
+        // concurrent refreshes wait for each other and give the same ranks
+        std::thread::scope(|scope| {
+            let refreshes = (0..4)
+                .map(|_| scope.spawn(|| service.refresh_rarity(&collection)))
+                .collect::<Vec<_>>();
+            for refresh in refreshes {
+                assert_eq!(refresh.join().unwrap().unwrap(), 4);
+            }
+        });
+        assert_eq!(service.get_rarities(&ids)?.len(), 4);
+        assert_eq!(
+            service
+                .get_rarities(&ids)?
+                .iter()
+                .find(|rarity| rarity.nft_id == ids[0])
+                .unwrap()
+                .rarity_rank,
+            1
+        );
+
+        // giving an attribute ranks the collection again, #4 now holds the
+        // only crown
+        let crown = attribute("Crown", "gold")?;
+        service.create_attribute_nft_relation(nfts[3].id, crown.id)?;
+        let rank = |index: usize| -> Result<i32> {
+            let rarity = service.get_rarities(&ids[index..=index])?.remove(0);
+            Ok(rarity.rarity_rank)
+        };
+        assert_eq!(rank(3)?, 1);
+        assert_eq!(rank(0)?, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,396 @@
+use async_graphql::{Enum, InputObject, SimpleObject};
+use chrono::NaiveDateTime;
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
+use diesel::prelude::*;
+use diesel::sql_types::Bool;
+use diesel::{Identifiable, PgConnection, Queryable};
+use diesel_derive_enum::DbEnum;
+use ethers::utils::{hex, keccak256};
+use uuid::Uuid;
+
+use crate::{errors::EthosError, metadata::TokenMetadata, schema::collection_reveals};
+
+/// SQL condition of the NFTs of `nfts` whose metadata is revealed, the
+/// counterpart of `CollectionReveal::is_revealed`
+pub(crate) const REVEALED: &str = "NOT EXISTS (SELECT 1 FROM collection_reveals \
+     WHERE collection_reveals.collection_id = nfts.collection_id \
+     AND NOT COALESCE(collection_reveals.committed_at IS NOT NULL AND \
+     CASE collection_reveals.mode \
+     WHEN 'at_time' THEN collection_reveals.reveal_at <= timezone('utc', now()) \
+     ELSE nfts.minted_at IS NOT NULL END, false))";
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::RevealMode"]
+pub enum RevealMode {
+    /// Every NFT of the collection is revealed at `revealAt`
+    AtTime,
+    /// Each NFT is revealed once it's minted
+    OnMint,
+}
+
+/// Delayed reveal of a collection. Its NFTs show the placeholder metadata
+/// until the provenance hash is committed and they are revealed, collections
+/// without one are revealed right away.
+#[derive(Debug, Clone, Queryable, SimpleObject, Identifiable)]
+#[diesel(table_name = collection_reveals)]
+#[diesel(primary_key(collection_id))]
+pub struct CollectionReveal {
+    pub collection_id: Uuid,
+    pub mode: RevealMode,
+    pub reveal_at: Option<NaiveDateTime>,
+    pub placeholder_name: String,
+    pub placeholder_description: String,
+    pub placeholder_image: String,
+    pub placeholder_animation_url: String,
+    /// keccak256 of the concatenated keccak256 hashes of the metadata JSON of
+    /// every NFT, sorted by `nftId`, as served by `/metadata`
+    pub provenance_hash: Option<String>,
+    pub committed_at: Option<NaiveDateTime>,
+
+    #[graphql(skip)]
+    pub created_at: NaiveDateTime,
+    #[graphql(skip)]
+    pub updated_at: NaiveDateTime,
+}
+
+impl CollectionReveal {
+    /// Whether the NFT minted at `minted_at` shows its metadata
+    pub fn is_revealed(&self, minted_at: Option<NaiveDateTime>) -> bool {
+        if self.committed_at.is_none() {
+            return false;
+        }
+        match self.mode {
+            RevealMode::AtTime => self
+                .reveal_at
+                .is_some_and(|reveal_at| reveal_at <= chrono::Utc::now().naive_utc()),
+            RevealMode::OnMint => minted_at.is_some(),
+        }
+    }
+
+    pub fn placeholder(&self) -> TokenMetadata {
+        let non_empty = |url: &String| Some(url.clone()).filter(|url| !url.is_empty());
+        TokenMetadata {
+            name: self.placeholder_name.clone(),
+            description: self.placeholder_description.clone(),
+            image: self.placeholder_image.clone(),
+            external_url: None,
+            animation_url: non_empty(&self.placeholder_animation_url),
+            attributes: vec![],
+        }
+    }
+}
+
+#[derive(Debug, InputObject)]
+pub struct SetRevealInput {
+    pub collection_id: Uuid,
+    pub mode: RevealMode,
+    /// required by `AT_TIME`
+    pub reveal_at: Option<NaiveDateTime>,
+    pub placeholder_name: String,
+    #[graphql(default)]
+    pub placeholder_description: String,
+    #[graphql(validator(url))]
+    pub placeholder_image: String,
+    #[graphql(default)]
+    pub placeholder_animation_url: String,
+}
+
+pub fn revealed() -> SqlLiteral<Bool> {
+    sql::<Bool>(REVEALED)
+}
+
+/// Creates or replaces the reveal of the collection, until its provenance is
+/// committed
+pub fn set_reveal(
+    conn: &mut PgConnection,
+    input: SetRevealInput,
+) -> Result<CollectionReveal, EthosError> {
+    if input.mode == RevealMode::AtTime && input.reveal_at.is_none() {
+        return Err(EthosError::RevealTimeRequired);
+    }
+    conn.transaction(|conn| {
+        let current = collection_reveals::table
+            .find(input.collection_id)
+            .for_update()
+            .first::<CollectionReveal>(conn)
+            .optional()?;
+        if current.is_some_and(|reveal| reveal.committed_at.is_some()) {
+            return Err(EthosError::ProvenanceCommitted);
+        }
+        let values = (
+            collection_reveals::mode.eq(input.mode),
+            collection_reveals::reveal_at.eq(input.reveal_at),
+            collection_reveals::placeholder_name.eq(input.placeholder_name),
+            collection_reveals::placeholder_description.eq(input.placeholder_description),
+            collection_reveals::placeholder_image.eq(input.placeholder_image),
+            collection_reveals::placeholder_animation_url.eq(input.placeholder_animation_url),
+        );
+        let result = diesel::insert_into(collection_reveals::table)
+            .values((
+                collection_reveals::collection_id.eq(input.collection_id),
+                values.clone(),
+            ))
+            .on_conflict(collection_reveals::collection_id)
+            .do_update()
+            .set(values)
+            .get_result::<CollectionReveal>(conn)?;
+        Ok(result)
+    })
+}
+
+/// Commits the provenance hash of the final metadata, once. The NFTs can be
+/// revealed from then on.
+pub fn commit_provenance(
+    conn: &mut PgConnection,
+    collection_id: Uuid,
+    metadata: &[TokenMetadata],
+) -> Result<CollectionReveal, EthosError> {
+    let hash = provenance_hash(metadata);
+    let result = diesel::update(
+        collection_reveals::table
+            .find(collection_id)
+            .filter(collection_reveals::committed_at.is_null()),
+    )
+    .set((
+        collection_reveals::provenance_hash.eq(hash),
+        collection_reveals::committed_at.eq(diesel::dsl::now),
+    ))
+    .get_result::<CollectionReveal>(conn)
+    .optional()?;
+    match result {
+        Some(reveal) => Ok(reveal),
+        None => {
+            // the reveal is missing or was already committed
+            collection_reveals::table
+                .find(collection_id)
+                .first::<CollectionReveal>(conn)?;
+            Err(EthosError::ProvenanceCommitted)
+        }
+    }
+}
+
+/// Locks the reveal of the collection until the end of the transaction. The
+/// metadata can't be edited while it's locked, so it can be hashed.
+pub fn lock_reveal(conn: &mut PgConnection, collection_id: Uuid) -> Result<(), EthosError> {
+    collection_reveals::table
+        .find(collection_id)
+        .select(collection_reveals::collection_id)
+        .for_update()
+        .first::<Uuid>(conn)
+        .optional()?;
+    Ok(())
+}
+
+/// Fails once the provenance of one of the collections is committed, as the
+/// metadata of their NFTs is hashed. The reveals are locked until the end of
+/// the transaction, so the provenance can't be committed meanwhile.
+pub fn ensure_not_committed(
+    conn: &mut PgConnection,
+    collection_ids: &[Uuid],
+) -> Result<(), EthosError> {
+    let reveals = collection_reveals::table
+        .filter(collection_reveals::collection_id.eq_any(collection_ids))
+        .for_share()
+        .load::<CollectionReveal>(conn)?;
+    if reveals.iter().any(|reveal| reveal.committed_at.is_some()) {
+        return Err(EthosError::ProvenanceCommitted);
+    }
+    Ok(())
+}
+
+pub fn get_reveals(
+    conn: &mut PgConnection,
+    collection_ids: &[Uuid],
+) -> Result<Vec<CollectionReveal>, EthosError> {
+    let result = collection_reveals::table
+        .filter(collection_reveals::collection_id.eq_any(collection_ids))
+        .load::<CollectionReveal>(conn)?;
+    Ok(result)
+}
+
+/// Hash committing to the metadata of every NFT, in order
+pub fn provenance_hash(metadata: &[TokenMetadata]) -> String {
+    let mut hashes = Vec::with_capacity(metadata.len() * 32);
+    for token in metadata {
+        let json = serde_json::to_vec(token).expect("metadata is serializable");
+        hashes.extend(keccak256(json));
+    }
+    format!("0x{}", hex::encode(keccak256(hashes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ops::RangeInclusive, sync::Arc};
+
+    use anyhow::Result;
+    use chrono::{Duration, Utc};
+    use diesel::prelude::*;
+    use dotenvy::dotenv;
+    use ethers::types::Address;
+    use uuid::Uuid;
+
+    use crate::{
+        database::create_connection_pool,
+        errors::EthosError,
+        events::EventBus,
+        pagination::PageRequest,
+        schema::nfts,
+        services::{
+            nft::{CollectionContract, NewNft, NftService},
+            project::{Project, ProjectService},
+        },
+    };
+
+    use super::{provenance_hash, RevealMode, SetRevealInput};
+
+    /// Creates a collection of the project on a new chain, returned with the
+    /// chain its metadata is read from
+    fn create_contract(
+        service: &NftService,
+        project: &Project,
+    ) -> Result<(i32, CollectionContract)> {
+        let chain = (Uuid::new_v4().as_u128() % 1_000_000) as i32;
+        let network = service.create_network(chain)?;
+        let collection = service.create_collection(project, "Reveal", None)?;
+        let contract = service.create_collection_contract(
+            &collection,
+            &network,
+            &format!("{:?}", Address::random()),
+            &format!("{:?}", Address::random()),
+        )?;
+        Ok((chain, contract))
+    }
+
+    fn new_nfts(contract: &CollectionContract, nft_ids: RangeInclusive<i32>) -> Vec<NewNft> {
+        nft_ids
+            .map(|nft_id| NewNft {
+                nft_id,
+                name: format!("Reveal #{}", nft_id),
+                image: String::new(),
+                description: String::new(),
+                external_url: String::new(),
+                animation_url: String::new(),
+                collection_id: contract.collection_id,
+                network_contract_id: contract.id,
+            })
+            .collect()
+    }
+
+    fn reveal_input(collection_id: uuid::Uuid, mode: RevealMode) -> SetRevealInput {
+        SetRevealInput {
+            collection_id,
+            mode,
+            reveal_at: None,
+            placeholder_name: "Unrevealed".to_string(),
+            placeholder_description: String::new(),
+            placeholder_image: "https://ethos.xyz/unrevealed.png".to_string(),
+            placeholder_animation_url: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_reveal_at_time() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool).create_project("Reveal", None)?;
+        let (chain, contract) = create_contract(&service, &project)?;
+        let nft = service.create_nfts(new_nfts(&contract, 1..=2))?.remove(0);
+        let tier = service.create_attribute(Some("Tier"), Some("1".to_string()), None, None)?;
+        service.create_attribute_nft_relation(nft.id, tier.id)?;
+        let address = contract.address.clone();
+
+        let mut input = reveal_input(nft.collection_id, RevealMode::AtTime);
+        let Err(EthosError::RevealTimeRequired) = service.set_reveal(&project, input) else {
+            panic!("revealing at a time needs the time");
+        };
+        input = reveal_input(nft.collection_id, RevealMode::AtTime);
+        input.reveal_at = Some(Utc::now().naive_utc() - Duration::minutes(1));
+        let reveal = service.set_reveal(&project, input)?;
+        assert_eq!(reveal.provenance_hash, None);
+
+        // the reveal time passed but the provenance isn't committed
+        let metadata = service.get_token_metadata(chain, &address, 1)?;
+        assert_eq!(metadata, reveal.placeholder());
+
+        let reveal = service.commit_provenance(&project, nft.collection_id)?;
+        let revealed = [
+            service.get_token_metadata(chain, &address, 1)?,
+            service.get_token_metadata(chain, &address, 2)?,
+        ];
+        assert_eq!(revealed[0].name, "Reveal #1");
+        assert_eq!(revealed[0].attributes.len(), 1);
+        assert_eq!(reveal.provenance_hash, Some(provenance_hash(&revealed)));
+
+        let Err(EthosError::ProvenanceCommitted) =
+            service.commit_provenance(&project, nft.collection_id)
+        else {
+            panic!("provenance is committed once");
+        };
+        let Err(EthosError::ProvenanceCommitted) = service.set_reveal(
+            &project,
+            reveal_input(nft.collection_id, RevealMode::OnMint),
+        ) else {
+            panic!("reveal can't change after the commitment");
+        };
+        // nor can the hashed metadata
+        let Err(EthosError::ProvenanceCommitted) =
+            service.create_attribute_nft_relation(nft.id, tier.id)
+        else {
+            panic!("attributes can't change after the commitment");
+        };
+        let Err(EthosError::ProvenanceCommitted) = service.create_nfts(new_nfts(&contract, 3..=3))
+        else {
+            panic!("NFTs can't be added after the commitment");
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reveal_on_mint() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let service = NftService::new(pool.clone(), Arc::new(EventBus::default()));
+        let project = ProjectService::new(pool.clone()).create_project("Reveal", None)?;
+        let (chain, contract) = create_contract(&service, &project)?;
+        let mut nfts = service.create_nfts(new_nfts(&contract, 1..=2))?;
+        let (nft, unminted) = (nfts.remove(0), nfts.remove(0));
+        let address = contract.address;
+
+        let attribute = |value: &str| {
+            service.create_attribute(Some("Tier"), Some(value.to_string()), None, None)
+        };
+        let (tier_1, tier_2) = (attribute("1")?, attribute("2")?);
+        service.create_attribute_nft_relation(nft.id, tier_1.id)?;
+        service.create_attribute_nft_relation(unminted.id, tier_2.id)?;
+
+        let input = reveal_input(nft.collection_id, RevealMode::OnMint);
+        let reveal = service.set_reveal(&project, input)?;
+        service.commit_provenance(&project, nft.collection_id)?;
+        diesel::update(nfts::table.find(nft.id))
+            .set(nfts::minted_at.eq(diesel::dsl::now))
+            .execute(&mut pool.get()?)?;
+
+        // the traits of the unminted NFT are hidden
+        let attributes = service.get_collection_attributes(
+            &project,
+            nft.collection_id,
+            &PageRequest::new(None, None, Some(10), None)?,
+        )?;
+        assert_eq!(attributes.total_count, 1);
+        assert_eq!(attributes.rows[0].id, tier_1.id);
+
+        assert_eq!(
+            service.get_token_metadata(chain, &address, 1)?.name,
+            "Reveal #1"
+        );
+        assert_eq!(
+            service.get_token_metadata(chain, &address, unminted.nft_id)?,
+            reveal.placeholder()
+        );
+
+        Ok(())
+    }
+}
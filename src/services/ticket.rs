@@ -0,0 +1,438 @@
+use std::{env, sync::Arc};
+
+use async_graphql::{ComplexObject, Context, Enum, InputObject, SimpleObject};
+use diesel::prelude::*;
+use diesel::r2d2::ConnectionManager;
+use diesel::{Identifiable, PgConnection, Queryable};
+use diesel_derive_enum::DbEnum;
+use r2d2::Pool;
+use uuid::Uuid;
+
+use crate::database::ConnectionPool;
+use crate::errors::EthosError;
+use crate::schema::tickets;
+
+use super::project::Project;
+use super::random::{RandomRequest, RandomRequestService};
+use super::wallet::Wallet;
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::TicketPurpose"]
+pub enum TicketPurpose {
+    /// Ticket was not assigned to an attendee yet
+    Assign,
+    /// Ticket was assigned to an attendee, who can redeem it
+    Assigned,
+    /// Ticket was redeemed by a wallet
+    Redeem,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::TicketState"]
+pub enum TicketState {
+    /// Ticket is not yet claimed
+    Request,
+    /// Ticket request waiting for random number
+    RandomNumber,
+    /// Ticket after received random number
+    Unpack,
+    /// Ticket is claimed
+    Minted,
+}
+
+impl TicketState {
+    /// Tickets only move forward, one state at a time
+    pub fn next(&self) -> Option<TicketState> {
+        match self {
+            TicketState::Request => Some(TicketState::RandomNumber),
+            TicketState::RandomNumber => Some(TicketState::Unpack),
+            TicketState::Unpack => Some(TicketState::Minted),
+            TicketState::Minted => None,
+        }
+    }
+
+    pub fn can_transition_to(&self, state: TicketState) -> bool {
+        self.next() == Some(state)
+    }
+}
+
+#[derive(Debug, Clone, Queryable, SimpleObject, Associations, Identifiable)]
+#[diesel(belongs_to(Project))]
+#[diesel(belongs_to(Wallet))]
+#[diesel(table_name = tickets)]
+#[graphql(complex)]
+pub struct Ticket {
+    pub id: Uuid,
+    #[graphql(skip)]
+    pub token: String,
+    pub code: String,
+    pub email: String,
+    pub name: String,
+    pub purpose: TicketPurpose,
+    pub tier: i32,
+    pub state: TicketState,
+    #[graphql(skip)]
+    pub event: Option<String>,
+    #[graphql(skip)]
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    #[graphql(skip)]
+    pub project_id: Uuid,
+    #[graphql(skip)]
+    pub wallet_id: Option<Uuid>,
+    created_at: chrono::NaiveDateTime,
+    updated_at: chrono::NaiveDateTime,
+    #[graphql(skip)]
+    pub emailed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[ComplexObject]
+impl Ticket {
+    /// Request drawing the NFT of a redeemed ticket
+    pub async fn random_request(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Option<RandomRequest>, EthosError> {
+        let service = ctx.data::<Arc<RandomRequestService>>().unwrap();
+        service.get_ticket_request(self)
+    }
+}
+
+impl Ticket {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= chrono::Utc::now().naive_utc())
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = tickets)]
+pub struct NewTicket {
+    pub token: String,
+    pub code: String,
+    pub email: String,
+    pub name: String,
+    pub tier: i32,
+    pub event: Option<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub project_id: Uuid,
+}
+
+#[derive(SimpleObject)]
+pub struct TicketsResponse {
+    tickets: Vec<Ticket>,
+    expired: bool,
+}
+
+#[derive(InputObject)]
+pub struct AssignTicketInput {
+    ticket_id: Uuid,
+    name: String,
+    #[graphql(validator(email))]
+    email: String,
+}
+
+#[derive(SimpleObject)]
+pub struct AssignTicketError {
+    ticket_id: Uuid,
+    message: String,
+}
+
+/// If error is null, the mutation was successful outherwise it will return the errors
+#[derive(SimpleObject)]
+pub struct AssignTicketResponse {
+    /// List of tickets that were successfully assigned
+    tickets: Vec<Ticket>,
+    /// List of errors that occurred during the assignment
+    errors: Vec<AssignTicketError>,
+}
+
+pub struct TicketService {
+    pool: ConnectionPool,
+}
+
+impl TicketService {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        Self {
+            pool: ConnectionPool::new(pool),
+        }
+    }
+
+    /// Creates `quantity` tickets of the same order. Only available in development.
+    pub fn create_tickets(
+        &self,
+        project: &Project,
+        email_str: &str,
+        quantity: i32,
+        tier_num: Option<i32>,
+        name_str: Option<String>,
+    ) -> Result<Vec<Ticket>, EthosError> {
+        if env::var("ENVIRONMENT").as_deref() != Ok("development") {
+            return Err(EthosError::DevelopmentOnly);
+        }
+        let order_token = generate_token();
+        let new_tickets = (0..quantity)
+            .map(|_| NewTicket {
+                token: order_token.clone(),
+                code: generate_code(),
+                email: email_str.to_string(),
+                name: name_str.clone().unwrap_or_default(),
+                tier: tier_num.unwrap_or(1),
+                event: None,
+                expires_at: None,
+                project_id: project.id,
+            })
+            .collect::<Vec<NewTicket>>();
+        self.insert_tickets(new_tickets)
+    }
+
+    pub fn insert_tickets(&self, new_tickets: Vec<NewTicket>) -> Result<Vec<Ticket>, EthosError> {
+        use crate::schema::tickets::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let result = diesel::insert_into(tickets)
+            .values(new_tickets)
+            .get_results::<Ticket>(&mut conn)?;
+        Ok(result)
+    }
+
+    /// Inserts the tickets whose code is not known yet, returning them
+    pub fn import_tickets(&self, new_tickets: Vec<NewTicket>) -> Result<Vec<Ticket>, EthosError> {
+        use crate::schema::tickets::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let result = diesel::insert_into(tickets)
+            .values(new_tickets)
+            .on_conflict(code)
+            .do_nothing()
+            .get_results::<Ticket>(&mut conn)?;
+        Ok(result)
+    }
+
+    pub fn get_ticket(&self, ticket_id: Uuid) -> Result<Ticket, EthosError> {
+        use crate::schema::tickets::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let result = tickets.find(ticket_id).first::<Ticket>(&mut conn)?;
+        Ok(result)
+    }
+
+    pub fn get_tickets_by_token(
+        &self,
+        project: &Project,
+        order_token: &str,
+    ) -> Result<Vec<Ticket>, EthosError> {
+        use crate::schema::tickets::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let result = tickets
+            .filter(project_id.eq(project.id))
+            .filter(token.eq(order_token))
+            .order(created_at.asc())
+            .load::<Ticket>(&mut conn)?;
+        Ok(result)
+    }
+
+    pub fn get_tickets(
+        &self,
+        project: &Project,
+        order_token: &str,
+    ) -> Result<TicketsResponse, EthosError> {
+        let tickets = self.get_tickets_by_token(project, order_token)?;
+        let expired = tickets.iter().any(|ticket| ticket.is_expired());
+        Ok(TicketsResponse { tickets, expired })
+    }
+
+    pub fn email_has_ticket(&self, project: &Project, email_str: &str) -> Result<bool, EthosError> {
+        use crate::schema::tickets::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let result = diesel::select(diesel::dsl::exists(
+            tickets
+                .filter(project_id.eq(project.id))
+                .filter(email.eq(email_str)),
+        ))
+        .get_result::<bool>(&mut conn)?;
+        Ok(result)
+    }
+
+    /// Assigns the tickets of an order to attendees. Every ticket is assigned
+    /// independently, failures are reported without aborting the others.
+    pub fn assign_tickets(
+        &self,
+        project: &Project,
+        order_token: &str,
+        inputs: Vec<AssignTicketInput>,
+    ) -> Result<AssignTicketResponse, EthosError> {
+        use crate::schema::tickets::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let mut response = AssignTicketResponse {
+            tickets: vec![],
+            errors: vec![],
+        };
+        for input in inputs {
+            let assigned = diesel::update(tickets)
+                .filter(id.eq(input.ticket_id))
+                .filter(project_id.eq(project.id))
+                .filter(token.eq(order_token))
+                .filter(purpose.eq(TicketPurpose::Assign))
+                .set((
+                    name.eq(input.name),
+                    email.eq(input.email),
+                    purpose.eq(TicketPurpose::Assigned),
+                ))
+                .get_result::<Ticket>(&mut conn)
+                .optional()?;
+
+            match assigned {
+                Some(ticket) => response.tickets.push(ticket),
+                None => {
+                    let exists = tickets
+                        .filter(id.eq(input.ticket_id))
+                        .filter(project_id.eq(project.id))
+                        .filter(token.eq(order_token))
+                        .first::<Ticket>(&mut conn)
+                        .optional()?;
+                    let message = match exists {
+                        Some(_) => "Ticket was already assigned",
+                        None => "Ticket not found",
+                    };
+                    response.errors.push(AssignTicketError {
+                        ticket_id: input.ticket_id,
+                        message: message.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(response)
+    }
+
+    /// Moves the ticket to the next state. Fails if the transition is not legal
+    /// or if the ticket was changed concurrently.
+    pub fn transition(&self, ticket: &Ticket, next: TicketState) -> Result<Ticket, EthosError> {
+        let mut conn = self.pool.get()?;
+        transition(&mut conn, ticket, next)
+    }
+}
+
+/// Same as [`TicketService::transition`], for callers running their own transaction
+pub fn transition(
+    conn: &mut PgConnection,
+    ticket: &Ticket,
+    next: TicketState,
+) -> Result<Ticket, EthosError> {
+    use crate::schema::tickets::dsl::*;
+
+    if !ticket.state.can_transition_to(next) {
+        return Err(EthosError::InvalidTicketTransition(ticket.state, next));
+    }
+    diesel::update(tickets)
+        .filter(id.eq(ticket.id))
+        .filter(state.eq(ticket.state))
+        .set(state.eq(next))
+        .get_result::<Ticket>(conn)
+        .optional()?
+        .ok_or(EthosError::InvalidTicketTransition(ticket.state, next))
+}
+
+fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+fn generate_code() -> String {
+    Uuid::new_v4().simple().to_string()[..10].to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use dotenvy::dotenv;
+    use uuid::Uuid;
+
+    use crate::{
+        database::create_connection_pool, errors::EthosError, services::project::ProjectService,
+    };
+
+    use super::{
+        generate_code, generate_token, AssignTicketInput, NewTicket, TicketPurpose, TicketService,
+        TicketState,
+    };
+
+    #[test]
+    fn test_ticket_assignment() -> Result<()> {
+        dotenv().ok();
+        let pool = create_connection_pool();
+        let ticket_service = TicketService::new(pool.clone());
+        let project = ProjectService::new(pool).create_project("Tickets", None)?;
+        let token = generate_token();
+        let new_ticket = NewTicket {
+            token: token.clone(),
+            code: generate_code(),
+            email: "buyer@taipe.xyz".to_string(),
+            name: "Buyer".to_string(),
+            tier: 2,
+            event: None,
+            expires_at: None,
+            project_id: project.id,
+        };
+        let tickets = ticket_service.insert_tickets(vec![
+            new_ticket.clone(),
+            NewTicket {
+                code: generate_code(),
+                ..new_ticket
+            },
+        ])?;
+        assert!(ticket_service.email_has_ticket(&project, "buyer@taipe.xyz")?);
+
+        let assign = |ticket_id| AssignTicketInput {
+            ticket_id,
+            name: "Attendee".to_string(),
+            email: "attendee@taipe.xyz".to_string(),
+        };
+        let response = ticket_service.assign_tickets(
+            &project,
+            &token,
+            vec![
+                assign(tickets[0].id),
+                assign(tickets[0].id),
+                assign(Uuid::new_v4()),
+            ],
+        )?;
+        assert_eq!(response.tickets.len(), 1);
+        assert_eq!(response.tickets[0].purpose, TicketPurpose::Assigned);
+        assert_eq!(response.errors.len(), 2);
+
+        let ticket = ticket_service.transition(&tickets[0], TicketState::RandomNumber)?;
+        assert_eq!(ticket.state, TicketState::RandomNumber);
+        // the stale copy is still in the `Request` state
+        let stale = ticket_service.transition(&tickets[0], TicketState::RandomNumber);
+        assert!(matches!(
+            stale,
+            Err(EthosError::InvalidTicketTransition(..))
+        ));
+        let skipped = ticket_service.transition(&tickets[1], TicketState::Minted);
+        assert!(matches!(
+            skipped,
+            Err(EthosError::InvalidTicketTransition(..))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ticket_state_transitions() {
+        use TicketState::*;
+
+        assert!(Request.can_transition_to(RandomNumber));
+        assert!(RandomNumber.can_transition_to(Unpack));
+        assert!(Unpack.can_transition_to(Minted));
+
+        assert!(!Request.can_transition_to(Unpack));
+        assert!(!Request.can_transition_to(Minted));
+        assert!(!Unpack.can_transition_to(Request));
+        assert!(!Minted.can_transition_to(Request));
+        assert!(!Minted.can_transition_to(Minted));
+        assert_eq!(Minted.next(), None);
+    }
+}
@@ -1,18 +1,26 @@
-use async_graphql::SimpleObject;
+use async_graphql::{ComplexObject, Context, InputObject, SimpleObject};
 use diesel::prelude::*;
 use ethers::types::Address;
 use ethers::utils::to_checksum;
 
 use diesel::{Insertable, Queryable, RunQueryDsl};
-use ethers::types::Signature;
-use serde::{Deserialize, Serialize};
+use ethers::types::{Bytes, Signature, SignatureError, U256};
+use ethers::utils::{hash_message, hex};
 use std::str::FromStr;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::{database::ConnectionPool, errors::EthosError, schema::wallets};
+use crate::{
+    chain::eip1271::ContractSignatureValidator, database::ConnectionPool, errors::EthosError,
+    guards::is_authenticated::IsAuthenticated, schema::wallets, siwe::SiweMessage,
+};
 
-#[derive(Debug, Queryable, SimpleObject, Serialize, Deserialize, Identifiable, PartialEq)]
+use super::nft::{FilterNftsWalletInput, NftService, PaginatedNFTs};
+use super::profile::{Profile, ProfileService};
+
+#[derive(Debug, Clone, Queryable, SimpleObject, Identifiable, PartialEq)]
 #[diesel(table_name = wallets)]
+#[graphql(complex)]
 pub struct Wallet {
     pub id: Uuid,
     pub address: String,
@@ -21,6 +29,31 @@ pub struct Wallet {
     updated_at: chrono::NaiveDateTime,
 }
 
+#[ComplexObject]
+impl Wallet {
+    /// The user profile information, only visible to the wallet itself
+    #[graphql(guard = "IsAuthenticated")]
+    pub async fn profile(&self, ctx: &Context<'_>) -> Result<Option<Profile>, EthosError> {
+        let wallet = ctx.data_unchecked::<Wallet>();
+        if wallet.id != self.id {
+            return Ok(None);
+        }
+        let service = ctx.data::<ProfileService>().unwrap();
+        service.get_profile(self).map(Some)
+    }
+
+    /// current owned NFTs of the experience, sorted by `nftId`
+    #[graphql(guard = "IsAuthenticated")]
+    pub async fn nft_list(
+        &self,
+        ctx: &Context<'_>,
+        input: Option<FilterNftsWalletInput>,
+    ) -> Result<PaginatedNFTs, EthosError> {
+        let service = ctx.data::<Arc<NftService>>().unwrap();
+        service.get_wallet_nfts(self, input.unwrap_or_default())
+    }
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = wallets)]
 struct NewWallet {
@@ -28,13 +61,50 @@ struct NewWallet {
     nonce: Uuid,
 }
 
+/// An ECDSA signature split in its `r`, `s` and `v` components
+#[derive(Debug, InputObject)]
+#[graphql(name = "Signature")]
+pub struct SignatureInput {
+    pub r: String,
+    pub s: String,
+    pub v: i32,
+}
+
+impl TryFrom<&SignatureInput> for Signature {
+    type Error = EthosError;
+
+    fn try_from(input: &SignatureInput) -> Result<Self, Self::Error> {
+        let component = |value: &str| -> Result<U256, EthosError> {
+            let bytes =
+                hex::decode(value.trim_start_matches("0x")).map_err(SignatureError::from)?;
+            if bytes.len() != 32 {
+                return Err(SignatureError::InvalidLength(bytes.len()).into());
+            }
+            Ok(U256::from_big_endian(&bytes))
+        };
+        let v = u64::try_from(input.v).map_err(|_| SignatureError::RecoveryError)?;
+        Ok(Signature {
+            r: component(&input.r)?,
+            s: component(&input.s)?,
+            v,
+        })
+    }
+}
+
 pub struct WalletService {
     pool: ConnectionPool,
+    signature_validator: Arc<dyn ContractSignatureValidator>,
 }
 
 impl WalletService {
-    pub fn new(pool: ConnectionPool) -> Self {
-        Self { pool }
+    pub fn new(
+        pool: ConnectionPool,
+        signature_validator: Arc<dyn ContractSignatureValidator>,
+    ) -> Self {
+        Self {
+            pool,
+            signature_validator,
+        }
     }
 
     pub fn get_wallet(&self, addr: &Address) -> Result<Wallet, EthosError> {
@@ -42,11 +112,28 @@ impl WalletService {
         let mut conn = self.pool.get()?;
 
         let wallet = wallets
-            .filter(address.eq(to_full_addr(&addr)))
+            .filter(address.eq(to_full_addr(addr)))
             .first::<Wallet>(&mut conn)?;
         Ok(wallet)
     }
 
+    pub fn get_wallet_by_id(&self, wallet_id: Uuid) -> Result<Wallet, EthosError> {
+        use crate::schema::wallets::dsl::*;
+        let mut conn = self.pool.get()?;
+
+        let wallet = wallets.find(wallet_id).first::<Wallet>(&mut conn)?;
+        Ok(wallet)
+    }
+
+    pub fn get_wallets_by_ids(&self, ids: &[Uuid]) -> Result<Vec<Wallet>, EthosError> {
+        let mut conn = self.pool.get()?;
+
+        let result = wallets::table
+            .filter(wallets::id.eq_any(ids))
+            .load::<Wallet>(&mut conn)?;
+        Ok(result)
+    }
+
     pub fn upsert_wallet(&self, addr: Address) -> Result<Wallet, EthosError> {
         use crate::schema::wallets::dsl::*;
 
@@ -83,42 +170,75 @@ impl WalletService {
     pub async fn verify_and_update_nonce(
         &self,
         wallet: &Wallet,
+        message: &str,
         signature: String,
     ) -> Result<Wallet, EthosError> {
         let addr = Address::from_str(&wallet.address)?;
-        verify_signature(wallet, signature).await?;
-        Ok(self.update_nonce(addr)?)
+        let parsed = verify_message(wallet, message)?;
+        self.verify_signature(&parsed, message, signature).await?;
+        self.update_nonce(addr)
+    }
+
+    /// Verifies the signature of an externally owned account, falling back to
+    /// EIP-1271 for smart-contract wallets such as Safe
+    async fn verify_signature(
+        &self,
+        parsed: &SiweMessage,
+        message: &str,
+        signature: String,
+    ) -> Result<(), EthosError> {
+        let bytes = hex::decode(signature.trim_start_matches("0x"))
+            .map(Bytes::from)
+            .map_err(SignatureError::from)?;
+        // the signature must be over the exact text the wallet displayed
+        let ecdsa = Signature::try_from(bytes.as_ref())
+            .and_then(|signature| signature.verify(message, parsed.address))
+            .map_err(EthosError::from);
+        if ecdsa.is_ok() {
+            return ecdsa;
+        }
+
+        let valid = self
+            .signature_validator
+            .is_valid_signature(
+                parsed.chain_id,
+                parsed.address,
+                hash_message(message),
+                bytes,
+            )
+            .await;
+        match valid {
+            Ok(true) => Ok(()),
+            Ok(false) | Err(EthosError::NetworkNotConfigured(_)) => ecdsa,
+            Err(err) => Err(err),
+        }
     }
 }
 
-fn create_message(address: &Address, nonce: &str) -> String {
-    format!(
-        "Welcome\n\n\
-            Click to sign in and accept the Terms of Service\n\
-            This request will not trigger a blockchain transaction or cost any gas fees.\n\
-            Your authentication status will reset after 24 hours.\n\n\
-            Wallet address:\n\
-            {}\n\n\
-            Nonce:\n\
-            {}",
-        to_full_addr(address),
-        nonce
-    )
+impl Wallet {
+    /// The wallet nonce as expected by EIP-4361, which only allows alphanumeric characters
+    pub fn login_nonce(&self) -> String {
+        self.nonce.simple().to_string()
+    }
 }
 
-pub async fn verify_signature(wallet: &Wallet, signature: String) -> Result<(), EthosError> {
-    // verify signature
-    let signature = Signature::from_str(&signature)?;
-    // retrive the nonce from the dattabase
+/// Checks that the login message was issued for the wallet and its current nonce
+fn verify_message(wallet: &Wallet, message: &str) -> Result<SiweMessage, EthosError> {
     let addr = Address::from_str(&wallet.address)?;
-    let nonce = wallet.nonce.to_string();
-
-    let message = create_message(&addr, &nonce);
-    println!("{}", message);
-    // check if the nonce of the signature is the same of the database
-    signature.verify(message, addr)?;
-    // update the nonce
-    Ok(())
+    let parsed = SiweMessage::from_str(message)?;
+
+    if parsed.address != addr {
+        return Err(EthosError::InvalidLoginMessage(
+            "message was not issued for this wallet".to_string(),
+        ));
+    }
+    // check if the nonce of the message is the same of the database
+    if parsed.nonce != wallet.login_nonce() {
+        return Err(EthosError::InvalidLoginMessage(
+            "nonce does not match".to_string(),
+        ));
+    }
+    Ok(parsed)
 }
 fn to_full_addr(addr: &Address) -> String {
     to_checksum(addr, None)
@@ -126,25 +246,55 @@ fn to_full_addr(addr: &Address) -> String {
 
 #[cfg(test)]
 mod tests {
-    use std::fmt::Debug;
+    use std::{fmt::Debug, sync::Arc};
 
     use anyhow::Result;
+    use async_graphql::async_trait;
+    use chrono::{Duration, Utc};
     use dotenvy::dotenv;
     use ethers::{
         signers::{LocalWallet, Signer},
-        types::Address,
+        types::{Address, Bytes, H256},
         utils::to_checksum,
     };
     use fixed_hash::rand::thread_rng;
 
     use crate::{
+        chain::eip1271::ContractSignatureValidator,
         database::{create_connection_pool, ConnectionPool},
         errors::EthosError,
-        services::wallet::create_message,
+        siwe::{SiweMessage, VERSION},
     };
 
     use super::WalletService;
 
+    /// Contract wallet validator that accepts a single signature
+    struct MockValidator {
+        wallet: Address,
+        signature: Bytes,
+    }
+
+    #[async_trait::async_trait]
+    impl ContractSignatureValidator for MockValidator {
+        async fn is_valid_signature(
+            &self,
+            _chain_id: u64,
+            wallet: Address,
+            _hash: H256,
+            signature: Bytes,
+        ) -> Result<bool, EthosError> {
+            Ok(wallet == self.wallet && signature == self.signature)
+        }
+    }
+
+    fn wallet_service(pool: ConnectionPool) -> WalletService {
+        let validator = MockValidator {
+            wallet: Address::zero(),
+            signature: Bytes::default(),
+        };
+        WalletService::new(pool, Arc::new(validator))
+    }
+
     fn get_pool() -> ConnectionPool {
         dotenv().ok();
         let database_connection = create_connection_pool();
@@ -160,10 +310,29 @@ mod tests {
         function(pool).unwrap();
     }
 
+    fn login_message(address: Address, nonce: &str) -> String {
+        let issued_at = Utc::now();
+        SiweMessage {
+            domain: "localhost:3001".to_string(),
+            address,
+            statement: None,
+            uri: "http://localhost:3001".to_string(),
+            version: VERSION.to_string(),
+            chain_id: 5,
+            nonce: nonce.to_string(),
+            issued_at,
+            expiration_time: Some(issued_at + Duration::minutes(10)),
+            not_before: None,
+            request_id: None,
+            resources: vec![],
+        }
+        .to_string()
+    }
+
     #[test]
     fn test_wallet_creation() {
         execute_transaction::<_, _, EthosError>(|pool| {
-            let wallet_service = WalletService::new(pool);
+            let wallet_service = wallet_service(pool);
             let addr = Address::random();
             let wallet = wallet_service.upsert_wallet(addr)?;
             assert_eq!(to_checksum(&addr, None), wallet.address);
@@ -177,19 +346,50 @@ mod tests {
 
     #[tokio::test]
     async fn test_wallet_signature() -> Result<()> {
-        let wallet_service = WalletService::new(get_pool());
+        let wallet_service = wallet_service(get_pool());
         let signer = LocalWallet::new(&mut thread_rng());
         let wallet = wallet_service.upsert_wallet(signer.address())?;
-        let message = create_message(&signer.address(), &wallet.nonce.to_string());
+        let message = login_message(signer.address(), &wallet.login_nonce());
 
-        let signature = signer.sign_message(message).await.unwrap();
-        // verify_signature(wallet, signature.to_string()).await?;
+        let signature = signer.sign_message(&message).await.unwrap();
         let wallet_2 = wallet_service
-            .verify_and_update_nonce(&wallet, signature.to_string())
+            .verify_and_update_nonce(&wallet, &message, signature.to_string())
             .await?;
 
         assert_ne!(&wallet.nonce, &wallet_2.nonce);
 
+        // the nonce was consumed, so the same message can't be used twice
+        let replay = wallet_service
+            .verify_and_update_nonce(&wallet_2, &message, signature.to_string())
+            .await;
+        assert!(replay.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_contract_wallet_signature() -> Result<()> {
+        let safe = Address::random();
+        let signature = Bytes::from(vec![7; 130]);
+        let validator = MockValidator {
+            wallet: safe,
+            signature: signature.clone(),
+        };
+        let wallet_service = WalletService::new(get_pool(), Arc::new(validator));
+        let wallet = wallet_service.upsert_wallet(safe)?;
+        let message = login_message(safe, &wallet.login_nonce());
+
+        let wallet_2 = wallet_service
+            .verify_and_update_nonce(&wallet, &message, signature.to_string())
+            .await?;
+        assert_ne!(&wallet.nonce, &wallet_2.nonce);
+
+        let message = login_message(safe, &wallet_2.login_nonce());
+        let rejected = wallet_service
+            .verify_and_update_nonce(&wallet_2, &message, Bytes::from(vec![8; 130]).to_string())
+            .await;
+        assert!(rejected.is_err());
+
         Ok(())
     }
 }
@@ -0,0 +1,309 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use ethers::{types::Address, utils::to_checksum};
+
+use crate::errors::EthosError;
+
+const PREAMBLE: &str = " wants you to sign in with your Ethereum account:";
+pub const VERSION: &str = "1";
+// tolerated clock difference between the wallet and the server
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// A Sign-In with Ethereum message as described by EIP-4361.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+impl SiweMessage {
+    /// Checks the fields the server is responsible for: the domain it was
+    /// issued for and the time window in which it can be used.
+    pub fn validate(&self, domain: &str, now: DateTime<Utc>) -> Result<(), EthosError> {
+        if self.domain != domain {
+            return Err(invalid(format!(
+                "domain `{}` does not match `{}`",
+                self.domain, domain
+            )));
+        }
+        if self.version != VERSION {
+            return Err(invalid(format!("unsupported version `{}`", self.version)));
+        }
+        let skew = Duration::seconds(CLOCK_SKEW_SECONDS);
+        if self.issued_at > now + skew {
+            return Err(invalid("message was issued in the future"));
+        }
+        match self.expiration_time {
+            Some(expiration) if expiration <= now => return Err(invalid("message has expired")),
+            Some(_) => {}
+            None => return Err(invalid("message has no expiration time")),
+        }
+        if let Some(not_before) = self.not_before {
+            if not_before > now + skew {
+                return Err(invalid("message is not valid yet"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the EIP-4361 `domain` (host and optional port) of an url.
+pub fn domain_from_url(url: &str) -> Result<String, EthosError> {
+    let parsed =
+        url::Url::parse(url).map_err(|e| invalid(format!("invalid url `{}`: {}", url, e)))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| invalid(format!("url `{}` has no host", url)))?;
+    Ok(match parsed.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+fn invalid(reason: impl Into<String>) -> EthosError {
+    EthosError::InvalidLoginMessage(reason.into())
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, EthosError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| invalid(format!("invalid timestamp `{}`: {}", value, e)))
+}
+
+impl fmt::Display for SiweMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}{}", self.domain, PREAMBLE)?;
+        writeln!(f, "{}", to_checksum(&self.address, None))?;
+        writeln!(f)?;
+        if let Some(statement) = &self.statement {
+            writeln!(f, "{}", statement)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "URI: {}", self.uri)?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Chain ID: {}", self.chain_id)?;
+        writeln!(f, "Nonce: {}", self.nonce)?;
+        write!(f, "Issued At: {}", format_time(&self.issued_at))?;
+        if let Some(expiration) = &self.expiration_time {
+            write!(f, "\nExpiration Time: {}", format_time(expiration))?;
+        }
+        if let Some(not_before) = &self.not_before {
+            write!(f, "\nNot Before: {}", format_time(not_before))?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, "\nRequest ID: {}", request_id)?;
+        }
+        if !self.resources.is_empty() {
+            write!(f, "\nResources:")?;
+            for resource in &self.resources {
+                write!(f, "\n- {}", resource)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the value of a `Tag: value` line, failing if the tag is missing.
+fn tagged<'a>(line: Option<&'a str>, tag: &str) -> Result<&'a str, EthosError> {
+    line.and_then(|line| line.strip_prefix(tag))
+        .and_then(|line| line.strip_prefix(": "))
+        .ok_or_else(|| invalid(format!("missing `{}`", tag)))
+}
+
+impl FromStr for SiweMessage {
+    type Err = EthosError;
+
+    fn from_str(message: &str) -> Result<Self, Self::Err> {
+        let mut lines = message.split('\n').peekable();
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(PREAMBLE))
+            .filter(|domain| !domain.is_empty())
+            .ok_or_else(|| invalid("missing preamble"))?
+            .to_string();
+
+        let address_line = lines.next().ok_or_else(|| invalid("missing address"))?;
+        let address = Address::from_str(address_line)
+            .map_err(|_| invalid(format!("invalid address `{}`", address_line)))?;
+        if to_checksum(&address, None) != address_line {
+            return Err(invalid("address is not EIP-55 checksummed"));
+        }
+
+        if lines.next() != Some("") {
+            return Err(invalid("expected an empty line after the address"));
+        }
+        let statement = match lines.next() {
+            Some("") => None,
+            Some(statement) => {
+                if lines.next() != Some("") {
+                    return Err(invalid("expected an empty line after the statement"));
+                }
+                Some(statement.to_string())
+            }
+            None => return Err(invalid("message is truncated")),
+        };
+
+        let uri = tagged(lines.next(), "URI")?.to_string();
+        let version = tagged(lines.next(), "Version")?.to_string();
+        let chain_id = tagged(lines.next(), "Chain ID")?;
+        let chain_id = chain_id
+            .parse::<u64>()
+            .map_err(|_| invalid(format!("invalid chain id `{}`", chain_id)))?;
+        let nonce = tagged(lines.next(), "Nonce")?.to_string();
+        if nonce.len() < 8 || !nonce.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(invalid(
+                "nonce must have at least 8 alphanumeric characters",
+            ));
+        }
+        let issued_at = parse_time(tagged(lines.next(), "Issued At")?)?;
+
+        let mut parsed = SiweMessage {
+            domain,
+            address,
+            statement,
+            uri,
+            version,
+            chain_id,
+            nonce,
+            issued_at,
+            expiration_time: None,
+            not_before: None,
+            request_id: None,
+            resources: vec![],
+        };
+
+        if let Some(line) = lines.next_if(|line| line.starts_with("Expiration Time: ")) {
+            parsed.expiration_time = Some(parse_time(tagged(Some(line), "Expiration Time")?)?);
+        }
+        if let Some(line) = lines.next_if(|line| line.starts_with("Not Before: ")) {
+            parsed.not_before = Some(parse_time(tagged(Some(line), "Not Before")?)?);
+        }
+        if let Some(line) = lines.next_if(|line| line.starts_with("Request ID: ")) {
+            parsed.request_id = Some(tagged(Some(line), "Request ID")?.to_string());
+        }
+        if lines.next_if(|line| *line == "Resources:").is_some() {
+            for line in lines.by_ref() {
+                let resource = line
+                    .strip_prefix("- ")
+                    .ok_or_else(|| invalid(format!("invalid resource line `{}`", line)))?;
+                parsed.resources.push(resource.to_string());
+            }
+        }
+        if let Some(line) = lines.next() {
+            return Err(invalid(format!("unexpected line `{}`", line)));
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{Duration, TimeZone, Utc};
+    use ethers::types::Address;
+
+    use super::{domain_from_url, SiweMessage};
+
+    fn message() -> SiweMessage {
+        let issued_at = Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap();
+        SiweMessage {
+            domain: "localhost:3001".to_string(),
+            address: Address::from_str("0xC40e55c684B63Ffc3c9127A1156c9d84c62A69ab").unwrap(),
+            statement: Some("Click to sign in and accept the Terms of Service.".to_string()),
+            uri: "http://localhost:3001".to_string(),
+            version: "1".to_string(),
+            chain_id: 5,
+            nonce: "32891756a4d14c8e8b2f9c1d7e6a5b43".to_string(),
+            issued_at,
+            expiration_time: Some(issued_at + Duration::minutes(10)),
+            not_before: None,
+            request_id: None,
+            resources: vec!["urn:uuid:d49f09d4-ca11-4ac7-a0d7-bc2074d274bd".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let message = message();
+        let text = message.to_string();
+        assert_eq!(
+            text,
+            "localhost:3001 wants you to sign in with your Ethereum account:\n\
+            0xC40e55c684B63Ffc3c9127A1156c9d84c62A69ab\n\n\
+            Click to sign in and accept the Terms of Service.\n\n\
+            URI: http://localhost:3001\n\
+            Version: 1\n\
+            Chain ID: 5\n\
+            Nonce: 32891756a4d14c8e8b2f9c1d7e6a5b43\n\
+            Issued At: 2023-04-01T12:00:00.000Z\n\
+            Expiration Time: 2023-04-01T12:10:00.000Z\n\
+            Resources:\n\
+            - urn:uuid:d49f09d4-ca11-4ac7-a0d7-bc2074d274bd"
+        );
+        assert_eq!(SiweMessage::from_str(&text).unwrap(), message);
+
+        let mut without_statement = message;
+        without_statement.statement = None;
+        without_statement.resources = vec![];
+        let text = without_statement.to_string();
+        assert_eq!(SiweMessage::from_str(&text).unwrap(), without_statement);
+    }
+
+    #[test]
+    fn test_message_validation() {
+        let message = message();
+        let issued_at = message.issued_at;
+
+        assert!(message.validate("localhost:3001", issued_at).is_ok());
+        assert!(message.validate("evil.xyz", issued_at).is_err());
+        assert!(message
+            .validate("localhost:3001", issued_at + Duration::minutes(11))
+            .is_err());
+        assert!(message
+            .validate("localhost:3001", issued_at - Duration::minutes(5))
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        let text = message().to_string();
+        let lowercase = text.replace(
+            "0xC40e55c684B63Ffc3c9127A1156c9d84c62A69ab",
+            "0xc40e55c684b63ffc3c9127a1156c9d84c62a69ab",
+        );
+        assert!(SiweMessage::from_str(&lowercase).is_err());
+        assert!(SiweMessage::from_str(&text.replace("Nonce: ", "Nonce: -")).is_err());
+        assert!(SiweMessage::from_str(&format!("{}\nextra", text)).is_err());
+        assert!(SiweMessage::from_str("Welcome").is_err());
+    }
+
+    #[test]
+    fn test_domain_from_url() {
+        assert_eq!(
+            domain_from_url("http://localhost:3001/login").unwrap(),
+            "localhost:3001"
+        );
+        assert_eq!(
+            domain_from_url("https://festadotaipe.xyz").unwrap(),
+            "festadotaipe.xyz"
+        );
+        assert!(domain_from_url("not an url").is_err());
+    }
+}